@@ -3,12 +3,24 @@
 //! 包含 ModbusTool 主应用结构体和实现，
 //! 整合页面管理、任务管理、串口管理等功能。
 
-use crate::app_ui::{add_font, show_top_menu};
+use crate::app_ui::{
+    FileAction, add_font, show_frame_errors, show_task_status, show_top_menu,
+    show_transport_selector,
+};
 use crate::master::Master;
+use crate::master::client::{MasterError, MasterResponse};
+use crate::master::scan::ScanEnd;
+use crate::mode::{OperatingMode, TaskStatus};
+use crate::net::TcpConnection;
 use crate::page::{Page, PageManager};
+use crate::profile::{Connection, LAST_PROFILE_KEY, ProfileManager};
 use crate::serial::SerialPort;
 use crate::slave::Slave;
-use crate::task::TaskManager;
+use crate::tag::SharedTags;
+use crate::task::{TaskCommand, TaskContext, TaskEvent, TaskManager};
+use crate::traffic::Traffic;
+use crate::transport::{Transport, TransportKind};
+use crate::workspace::Workspace;
 use eframe::{App, egui};
 use log;
 
@@ -16,19 +28,45 @@ use log;
 pub struct ModbusTool {
     page_manager: PageManager,
     task_manager: TaskManager,
+    //主页上选择的传输方式
+    transport_kind: TransportKind,
     serial: SerialPort,
+    tcp: TcpConnection,
     slave: Slave,
     master: Master,
+    traffic: Traffic,
+    profiles: ProfileManager,
+    //主机和从机页面共享的标签
+    tags: SharedTags,
+    //文件菜单中输入的工作区路径和最近一次打开或保存的结果
+    workspace_path: String,
+    workspace_message: Option<Result<String, String>>,
+    //最近一次切换模式被拒绝的原因
+    mode_message: Option<String>,
+    //校验失败的帧数和最近一次的错误
+    frame_errors: usize,
+    last_frame_error: Option<String>,
 }
 
 impl Default for ModbusTool {
     fn default() -> Self {
+        let tags = SharedTags::default();
         Self {
             page_manager: PageManager::new(),
             task_manager: TaskManager::new(),
+            transport_kind: TransportKind::default(),
             serial: SerialPort::default(),
-            slave: Slave::default(),
-            master: Master::default(),
+            tcp: TcpConnection::default(),
+            slave: Slave::new(tags.clone()),
+            master: Master::new(tags.clone()),
+            traffic: Traffic::new(),
+            profiles: ProfileManager::default(),
+            tags,
+            workspace_path: String::new(),
+            workspace_message: None,
+            mode_message: None,
+            frame_errors: 0,
+            last_frame_error: None,
         }
     }
 }
@@ -36,7 +74,165 @@ impl Default for ModbusTool {
 impl ModbusTool {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         add_font(&cc.egui_ctx);
-        Self::default()
+        let mut app = Self {
+            profiles: ProfileManager::load(),
+            ..Self::default()
+        };
+        app.task_manager.set_repaint_context(cc.egui_ctx.clone());
+        if let Some(name) = cc
+            .storage
+            .and_then(|storage| storage.get_string(LAST_PROFILE_KEY))
+            && let Some(connection) = app.profiles.restore(&name)
+        {
+            app.apply_connection(connection);
+        }
+        app
+    }
+
+    /// 加载连接配置中的设置，只在断开时调用
+    fn apply_connection(&mut self, connection: Connection) {
+        match connection {
+            Connection::Serial(settings) => {
+                self.transport_kind = TransportKind::Serial;
+                self.serial.set_settings(settings);
+            }
+            Connection::Tcp(settings) => {
+                self.transport_kind = TransportKind::Tcp;
+                self.tcp.set_settings(settings);
+            }
+        }
+    }
+
+    /// 当前会话的全部内容
+    fn workspace(&self, ctx: &egui::Context) -> Workspace {
+        Workspace {
+            transport_kind: self.transport_kind,
+            serial: self.serial.settings(),
+            tcp: self.tcp.settings(),
+            profile: self.profiles.last_used().map(str::to_string),
+            mode: self.mode(),
+            page: self.page_manager.current_page(),
+            polls: self.master.polls().lock().unwrap().clone(),
+            tags: self.tags.lock().unwrap().clone(),
+            store: self.slave.store().lock().unwrap().clone(),
+            window_size: ctx
+                .input(|input| input.viewport().inner_rect)
+                .map(|rect| [rect.width(), rect.height()]),
+        }
+    }
+
+    /// 用工作区替换当前会话，只在断开时调用
+    fn apply_workspace(&mut self, workspace: Workspace, ctx: &egui::Context) {
+        self.serial.set_settings(workspace.serial);
+        self.tcp.set_settings(workspace.tcp);
+        self.transport_kind = workspace.transport_kind;
+        self.profiles.set_last_used(workspace.profile);
+        self.master.set_polls(workspace.polls);
+        *self.tags.lock().unwrap() = workspace.tags;
+        self.slave.set_store(workspace.store);
+        self.page_manager.set_page(workspace.page);
+        self.handle_page_change();
+        self.set_mode(workspace.mode);
+        if let Some([width, height]) = workspace.window_size {
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(egui::vec2(width, height)));
+        }
+    }
+
+    fn handle_file_action(&mut self, action: FileAction, ctx: &egui::Context) {
+        let path = self.workspace_path.trim().to_string();
+        if path.is_empty() {
+            self.workspace_message = Some(Err("请输入工作区文件路径".to_string()));
+            return;
+        }
+        let result = match action {
+            FileAction::SaveWorkspace => std::fs::write(&path, self.workspace(ctx).to_json())
+                .map(|()| format!("已保存工作区 {}", path))
+                .map_err(|err| format!("保存 {} 失败: {}", path, err)),
+            FileAction::OpenWorkspace => std::fs::read_to_string(&path)
+                .map_err(|err| format!("读取 {} 失败: {}", path, err))
+                .and_then(|text| {
+                    Workspace::from_json(&text).map_err(|err| format!("{} 无效: {}", path, err))
+                })
+                .map(|workspace| {
+                    self.apply_workspace(workspace, ctx);
+                    format!("已打开工作区 {}", path)
+                }),
+        };
+        match &result {
+            Ok(message) => log::info!("{}", message),
+            Err(err) => log::error!("{}", err),
+        }
+        self.workspace_message = Some(result);
+    }
+
+    /// 当前传输方式的设置，保存为连接配置
+    fn current_connection(&self) -> Connection {
+        match self.transport_kind {
+            TransportKind::Serial => Connection::Serial(self.serial.settings()),
+            TransportKind::Tcp => Connection::Tcp(self.tcp.settings()),
+        }
+    }
+
+    /// 把页面产生的请求交给后台任务，并把任务事件分发给对应页面
+    fn handle_task_messages(&mut self) {
+        for request in self.master.take_requests() {
+            if !self.task_manager.send(TaskCommand::Master(request.clone())) {
+                self.master.handle_response(MasterResponse::failed(
+                    request,
+                    MasterError::Transport("未连接".to_string()),
+                ));
+            }
+        }
+        if let Some(command) = self.master.take_scan_command()
+            && !self.task_manager.send(TaskCommand::Scan(command))
+        {
+            self.master
+                .handle_scan_finished(ScanEnd::Failed(MasterError::Transport(
+                    "未连接".to_string(),
+                )));
+        }
+
+        for event in self.task_manager.poll_events() {
+            match event {
+                TaskEvent::Master(response) => self.master.handle_response(response),
+                TaskEvent::Polled { poll, response } => self.master.handle_poll(poll, &response),
+                TaskEvent::Scanned(result) => self.master.handle_scanned(result),
+                TaskEvent::Discovered(result) => self.master.handle_discovered(result),
+                TaskEvent::ScanFinished(end) => self.master.handle_scan_finished(end),
+                TaskEvent::SlaveServed {
+                    client,
+                    unit,
+                    request,
+                    result,
+                } => self.slave.record_access(client, unit, &request, &result),
+                TaskEvent::ClientConnected(address) => self.slave.client_connected(address),
+                TaskEvent::ClientDisconnected(address) => self.slave.client_disconnected(address),
+                TaskEvent::FrameError {
+                    framing,
+                    frame,
+                    error,
+                } => {
+                    self.frame_errors += 1;
+                    self.last_frame_error = Some(format!(
+                        "{} 帧 [{}]: {}",
+                        framing,
+                        frame
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        error
+                    ));
+                }
+                TaskEvent::Traffic(frame) => self.traffic.record(frame),
+            }
+        }
+        if let Some(params) = self.master.take_line_params() {
+            self.serial.set_line_params(params);
+        }
+        // 引擎被停止时不会报告扫描结束
+        self.master
+            .update_engine(self.task_manager.mode(), self.task_manager.status());
     }
 
     fn handle_page_change(&mut self) {
@@ -44,52 +240,66 @@ impl ModbusTool {
         let current_page = self.page_manager.current_page();
 
         if previous_page != current_page {
-            // 只更新任务类型，不删除任务
+            // 只切换工作模式，不删除任务
             match current_page {
                 Page::Slave => {
-                    self.task_manager.set_handle_type(false);
-                    log::info!("页面切换到Slave，设置handle_type为false");
+                    log::info!("页面切换到Slave，切换到从机模式");
+                    self.set_mode(OperatingMode::Slave);
                 }
                 Page::Master => {
-                    self.task_manager.set_handle_type(true);
-                    log::info!("页面切换到Master，设置handle_type为true");
+                    log::info!("页面切换到Master，切换到主机模式");
+                    self.set_mode(OperatingMode::Master);
                 }
                 Page::Home => {
-                    log::info!("页面切换到Home，保持handle_type不变");
+                    log::info!("页面切换到Home，保持工作模式不变");
+                }
+                Page::Traffic => {
+                    log::info!("页面切换到报文，保持工作模式不变");
                 }
             }
         }
     }
 
-    fn handle_serial_connection(&mut self) {
-        let is_connected = self.serial.is_connected();
+    fn is_connected(&self) -> bool {
+        match self.transport_kind {
+            TransportKind::Serial => self.serial.is_connected(),
+            TransportKind::Tcp => self.tcp.is_connected(),
+        }
+    }
+
+    /// 把连接失败的原因交给当前传输方式的设置页面，并视为断开
+    fn set_connection_error(&mut self, message: String) {
+        match self.transport_kind {
+            TransportKind::Serial => self.serial.set_error(message),
+            TransportKind::Tcp => self.tcp.set_error(message),
+        }
+    }
+
+    fn handle_connection(&mut self) {
+        let is_connected = self.is_connected();
 
         // 检查任务管理器中是否有任务
         let has_task = self.task_manager.has_task();
 
+        // 任务运行中出错（例如重新配置失败、TCP 连接被拒绝）时，把错误交给设置页面并视为断开
+        if is_connected
+            && has_task
+            && let TaskStatus::Error(reason) = self.task_manager.status()
+        {
+            self.set_connection_error(reason);
+        }
+
         if is_connected && !has_task {
-            // 串口已连接但没有任务，创建任务
-            log::info!("检测到串口连接，创建任务");
-            // 根据当前页面类型设置任务类型
-            match self.page_manager.current_page() {
-                Page::Slave => {
-                    self.task_manager.set_handle_type(false);
-                    log::info!("当前页面是Slave，创建slave任务");
-                }
-                Page::Master => {
-                    self.task_manager.set_handle_type(true);
-                    log::info!("当前页面是Master，创建master任务");
-                }
-                Page::Home => {
-                    // 默认创建slave任务
-                    self.task_manager.set_handle_type(false);
-                    log::info!("当前页面是Home，默认创建slave任务");
-                }
-            }
+            // 已连接但没有任务，按当前工作模式创建任务
+            log::info!(
+                "检测到{}连接，创建{}模式任务",
+                self.transport_kind.label(),
+                self.task_manager.mode()
+            );
             self.create_task();
         } else if !is_connected && has_task {
-            // 串口断开但有任务，删除任务
-            log::info!("检测到串口断开，删除任务");
+            // 连接断开但有任务，删除任务
+            log::info!("检测到{}断开，删除任务", self.transport_kind.label());
             self.delete_task();
         }
     }
@@ -97,16 +307,56 @@ impl ModbusTool {
     fn show_current_page(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         match self.page_manager.current_page() {
             Page::Home => {
-                self.serial.show(ctx, frame);
+                egui::TopBottomPanel::top("transport_selector").show(ctx, |ui| {
+                    let enabled = !self.is_connected();
+                    show_transport_selector(ui, &mut self.transport_kind, enabled);
+                    let current = self.current_connection();
+                    if let Some(connection) = self.profiles.show(ui, enabled, || current) {
+                        self.apply_connection(connection);
+                    }
+                });
+                match self.transport_kind {
+                    TransportKind::Serial => self.serial.show(ctx, frame),
+                    TransportKind::Tcp => self.tcp.show(ctx, frame),
+                }
             }
             Page::Slave => self.slave.show(ctx, frame),
             Page::Master => self.master.show(ctx, frame),
+            Page::Traffic => self.traffic.show(ctx, frame),
         }
     }
 
     // Task management methods (delegated to TaskManager)
+    fn task_context(&self) -> TaskContext {
+        let transport = match self.transport_kind {
+            TransportKind::Serial => Transport::Serial {
+                settings: self.serial.shared_settings(),
+                need_update: self.serial.need_update_flag(),
+            },
+            TransportKind::Tcp => Transport::Tcp(self.tcp.settings()),
+        };
+        TaskContext {
+            transport,
+            store: self.slave.store(),
+            polls: self.master.polls(),
+        }
+    }
+
     pub fn create_task(&mut self) {
-        self.task_manager.create_task();
+        if self.transport_kind == TransportKind::Tcp {
+            // TCP 设备通常只有一个站号，主机页面默认使用它
+            self.master.set_unit(self.tcp.settings().unit_id);
+        }
+        match self.task_manager.create_task(self.task_context()) {
+            Ok(()) => match self.transport_kind {
+                TransportKind::Serial => self.serial.clear_error(),
+                TransportKind::Tcp => self.tcp.clear_error(),
+            },
+            Err(err) => {
+                log::error!("创建{}任务失败: {}", self.transport_kind.label(), err);
+                self.set_connection_error(err);
+            }
+        }
     }
 
     pub fn delete_task(&mut self) {
@@ -114,15 +364,28 @@ impl ModbusTool {
     }
 
     pub fn recreate_task(&mut self) {
-        self.task_manager.recreate_task();
+        if let Err(err) = self.task_manager.recreate_task(self.task_context()) {
+            log::error!("重新创建{}任务失败: {}", self.transport_kind.label(), err);
+            self.set_connection_error(err);
+        }
     }
 
-    pub fn set_handle_type(&mut self, value: bool) {
-        self.task_manager.set_handle_type(value);
+    pub fn set_mode(&mut self, mode: OperatingMode) {
+        if mode == OperatingMode::Monitor && self.transport_kind == TransportKind::Tcp {
+            self.mode_message = Some("监听模式只能用于串口".to_string());
+            return;
+        }
+        match self.task_manager.set_mode(mode) {
+            Ok(()) => self.mode_message = None,
+            Err(err) => {
+                log::warn!("切换到{}模式被拒绝: {}", mode, err);
+                self.mode_message = Some(err.to_string());
+            }
+        }
     }
 
-    pub fn get_handle_type(&self) -> bool {
-        self.task_manager.get_handle_type()
+    pub fn mode(&self) -> OperatingMode {
+        self.task_manager.mode()
     }
 }
 
@@ -131,7 +394,21 @@ impl App for ModbusTool {
         // 显示顶部菜单并检测页面变化
         egui::TopBottomPanel::top("top_menu").show(ctx, |ui| {
             let mut current_page = self.page_manager.current_page();
-            show_top_menu(ui, &mut current_page);
+            let can_open = !self.is_connected();
+            let action = show_top_menu(ui, &mut current_page, &mut self.workspace_path, can_open);
+            match &self.workspace_message {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(err)) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                }
+                None => {}
+            }
+            if let Some(action) = action {
+                self.handle_file_action(action, ctx);
+                return;
+            }
 
             // 只有当页面真的发生变化时才设置新页面
             if current_page != self.page_manager.current_page() {
@@ -141,10 +418,38 @@ impl App for ModbusTool {
             }
         });
 
-        // 监测串口连接状态
-        self.handle_serial_connection();
+        // 底部状态栏显示工作模式和任务状态
+        egui::TopBottomPanel::bottom("task_status").show(ctx, |ui| {
+            let mut mode = self.task_manager.mode();
+            let status = self.task_manager.status();
+            show_task_status(ui, &mut mode, &status, self.mode_message.as_deref());
+            if mode != self.task_manager.mode() {
+                self.set_mode(mode);
+            }
+            if show_frame_errors(ui, self.frame_errors, self.last_frame_error.as_deref()) {
+                self.frame_errors = 0;
+                self.last_frame_error = None;
+            }
+        });
+
+        // 监测连接状态
+        self.handle_connection();
 
         // 显示当前页面
         self.show_current_page(ctx, frame);
+
+        // 转发请求和事件
+        self.handle_task_messages();
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        if let Some(name) = self.profiles.last_used() {
+            storage.set_string(LAST_PROFILE_KEY, name.to_string());
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        log::info!("程序退出，关闭后台任务");
+        self.task_manager.shutdown();
     }
 }
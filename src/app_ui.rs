@@ -1,4 +1,7 @@
+use crate::modbus::value::{ByteOrder, DataType, ValueFormat};
+use crate::mode::{OperatingMode, TaskStatus};
 use crate::page::Page;
+use crate::transport::TransportKind;
 use eframe::egui;
 use eframe::epaint::text::{FontInsert, InsertFontFamily};
 use eframe::icon_data;
@@ -24,12 +27,143 @@ pub fn add_font(ctx: &egui::Context) {
     ));
 }
 
-pub fn show_top_menu(ui: &mut egui::Ui, current_page: &mut Page) {
+/// 文件菜单中选择的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAction {
+    OpenWorkspace,
+    SaveWorkspace,
+}
+
+/// 顶部菜单，`can_open` 为 `false`（已连接）时不能打开工作区
+pub fn show_top_menu(
+    ui: &mut egui::Ui,
+    current_page: &mut Page,
+    workspace_path: &mut String,
+    can_open: bool,
+) -> Option<FileAction> {
+    let mut action = None;
     ui.horizontal(|ui| {
+        ui.menu_button("文件", |ui| {
+            ui.label("工作区文件:");
+            ui.add(
+                egui::TextEdit::singleline(workspace_path)
+                    .hint_text("workspace.json")
+                    .desired_width(240.0),
+            );
+            if ui
+                .add_enabled(can_open, egui::Button::new("打开工作区"))
+                .on_disabled_hover_text("断开连接后才能打开工作区")
+                .clicked()
+            {
+                action = Some(FileAction::OpenWorkspace);
+                ui.close();
+            }
+            if ui.button("保存工作区").clicked() {
+                action = Some(FileAction::SaveWorkspace);
+                ui.close();
+            }
+        });
+        ui.separator();
         egui::widgets::global_theme_preference_switch(ui);
         ui.separator();
         ui.selectable_value(current_page, Page::Home, "主页");
         ui.selectable_value(current_page, Page::Slave, "从机");
         ui.selectable_value(current_page, Page::Master, "主机");
+        ui.selectable_value(current_page, Page::Traffic, "报文");
+    });
+    action
+}
+
+pub fn show_task_status(
+    ui: &mut egui::Ui,
+    mode: &mut OperatingMode,
+    status: &TaskStatus,
+    message: Option<&str>,
+) {
+    ui.horizontal(|ui| {
+        ui.label("工作模式:");
+        egui::ComboBox::from_id_salt("operating_mode_selector")
+            .selected_text(mode.label())
+            .show_ui(ui, |ui| {
+                for candidate in OperatingMode::ALL {
+                    ui.add_enabled_ui(candidate.is_supported(), |ui| {
+                        ui.selectable_value(mode, candidate, candidate.label());
+                    });
+                }
+            });
+        ui.separator();
+        let color = match status {
+            TaskStatus::Stopped => egui::Color32::from_rgb(150, 150, 150),
+            TaskStatus::Connecting => egui::Color32::from_rgb(220, 180, 50),
+            TaskStatus::Running => egui::Color32::from_rgb(50, 220, 50),
+            TaskStatus::Error(_) => egui::Color32::from_rgb(220, 50, 50),
+        };
+        ui.colored_label(color, "●");
+        ui.label(status.to_string());
+        if let Some(message) = message {
+            ui.separator();
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), message);
+        }
+    });
+}
+
+/// 主页顶部的传输方式选择，连接期间不可切换
+pub fn show_transport_selector(ui: &mut egui::Ui, kind: &mut TransportKind, enabled: bool) {
+    ui.horizontal(|ui| {
+        ui.label("传输方式:");
+        ui.add_enabled_ui(enabled, |ui| {
+            for candidate in [TransportKind::Serial, TransportKind::Tcp] {
+                ui.selectable_value(kind, candidate, candidate.label());
+            }
+        });
     });
 }
+
+/// 显示校验失败的帧数和最近一次的错误，点击清除时返回 `true`
+pub fn show_frame_errors(ui: &mut egui::Ui, count: usize, last: Option<&str>) -> bool {
+    if count == 0 {
+        return false;
+    }
+    let mut clear = false;
+    ui.horizontal(|ui| {
+        ui.colored_label(
+            egui::Color32::from_rgb(220, 50, 50),
+            format!("帧错误: {}", count),
+        );
+        if let Some(last) = last {
+            ui.label(last);
+        }
+        clear = ui.button("清除").clicked();
+    });
+    clear
+}
+
+/// 寄存器的数据类型和字节序选择，主机和从机页面共用
+pub fn show_value_format(ui: &mut egui::Ui, id_salt: &str, format: &mut ValueFormat) {
+    ui.label("类型:");
+    egui::ComboBox::from_id_salt((id_salt, "data_type"))
+        .selected_text(format.data_type.label())
+        .show_ui(ui, |ui| {
+            for candidate in DataType::ALL {
+                let selected = format.data_type.same_kind(candidate);
+                if ui.selectable_label(selected, candidate.label()).clicked() && !selected {
+                    format.data_type = candidate;
+                }
+            }
+        });
+    if let DataType::Ascii(count) | DataType::Utf16(count) = &mut format.data_type {
+        ui.add(
+            egui::DragValue::new(count)
+                .range(1..=125)
+                .suffix(" 个寄存器"),
+        );
+    }
+    ui.label("字节序:");
+    egui::ComboBox::from_id_salt((id_salt, "byte_order"))
+        .selected_text(format.order.label())
+        .show_ui(ui, |ui| {
+            for candidate in ByteOrder::ALL {
+                ui.selectable_value(&mut format.order, candidate, candidate.label());
+            }
+        });
+}
@@ -1,8 +1,16 @@
 pub mod app;
 pub mod app_ui;
 pub mod master;
+pub mod modbus;
+pub mod mode;
+pub mod net;
 pub mod page;
+pub mod profile;
 pub mod serial;
 pub mod slave;
+pub mod tag;
 pub mod task;
+pub mod traffic;
+pub mod transport;
 pub mod ui;
+pub mod workspace;
@@ -0,0 +1,396 @@
+//! 主机请求构造器
+//!
+//! 按所选功能码显示对应的输入项，并把输入转换成请求，输入不合法时给出原因。
+
+use crate::modbus::pdu::{
+    DiagnosticCode, FunctionCode, RESTART_CLEAR_LOG, ReadDeviceIdCode, Request,
+};
+use eframe::*;
+
+/// 一次读取线圈/离散输入的最大数量
+const MAX_READ_BITS: u16 = 2000;
+/// 一次读取寄存器的最大数量
+const MAX_READ_REGISTERS: u16 = 125;
+/// 一次写入多个线圈的最大数量
+const MAX_WRITE_BITS: usize = 1968;
+/// 一次写入多个寄存器的最大数量
+const MAX_WRITE_REGISTERS: usize = 123;
+/// 读写多个寄存器时一次写入的最大数量
+const MAX_READ_WRITE_REGISTERS: usize = 121;
+/// 诊断回显一次最多的数据字数，受 RTU 帧长度限制
+const MAX_QUERY_DATA: usize = 125;
+
+#[derive(Debug)]
+pub struct RequestBuilder {
+    pub unit: u8,
+    pub function: FunctionCode,
+    pub address: u16,
+    pub quantity: u16,
+    //单个线圈写入的值
+    coil: bool,
+    //单个寄存器写入的值
+    value: u16,
+    //多个写入的值，逗号或空格分隔
+    values: String,
+    and_mask: u16,
+    or_mask: u16,
+    //读写多个寄存器时写入的起始地址
+    write_address: u16,
+    //读设备标识的读取类别和起始对象编号
+    device_code: ReadDeviceIdCode,
+    object_id: u8,
+    //诊断子功能，回显的数据使用 `values`
+    diagnostic: DiagnosticCode,
+    //重启通信时同时清除事件记录
+    clear_log: bool,
+}
+
+impl Default for RequestBuilder {
+    fn default() -> Self {
+        Self {
+            unit: 1,
+            function: FunctionCode::ReadHoldingRegisters,
+            address: 0,
+            quantity: 10,
+            coil: false,
+            value: 0,
+            values: String::new(),
+            and_mask: 0xFFFF,
+            or_mask: 0,
+            write_address: 0,
+            device_code: ReadDeviceIdCode::default(),
+            object_id: 0,
+            diagnostic: DiagnosticCode::default(),
+            clear_log: false,
+        }
+    }
+}
+
+impl RequestBuilder {
+    /// 显示输入项，返回是否点击了发送
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        egui::Grid::new("master_request_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("站号:");
+                ui.add(egui::DragValue::new(&mut self.unit));
+                ui.end_row();
+
+                ui.label("功能码:");
+                egui::ComboBox::from_id_salt("function_code_selector")
+                    .selected_text(self.function.to_string())
+                    .width(200.0)
+                    .show_ui(ui, |ui| {
+                        for function in FunctionCode::ALL {
+                            ui.selectable_value(&mut self.function, function, function.to_string());
+                        }
+                    });
+                ui.end_row();
+
+                self.show_function_inputs(ui);
+            });
+        ui.button("发送").clicked()
+    }
+
+    fn show_function_inputs(&mut self, ui: &mut egui::Ui) {
+        match self.function {
+            FunctionCode::ReadDeviceIdentification => {
+                self.show_device_id_inputs(ui);
+                return;
+            }
+            FunctionCode::Diagnostics => {
+                self.show_diagnostic_inputs(ui);
+                return;
+            }
+            // 读通信事件计数和记录没有参数
+            FunctionCode::GetCommEventCounter | FunctionCode::GetCommEventLog => return,
+            _ => {}
+        }
+        let address_label = match self.function {
+            FunctionCode::ReadWriteMultipleRegisters => "读起始地址:",
+            FunctionCode::WriteSingleCoil
+            | FunctionCode::WriteSingleRegister
+            | FunctionCode::MaskWriteRegister => "地址:",
+            _ => "起始地址:",
+        };
+        ui.label(address_label);
+        ui.add(egui::DragValue::new(&mut self.address));
+        ui.end_row();
+
+        match self.function {
+            FunctionCode::ReadCoils | FunctionCode::ReadDiscreteInputs => {
+                self.show_quantity(ui, MAX_READ_BITS);
+            }
+            FunctionCode::ReadHoldingRegisters | FunctionCode::ReadInputRegisters => {
+                self.show_quantity(ui, MAX_READ_REGISTERS);
+            }
+            FunctionCode::WriteSingleCoil => {
+                ui.label("值:");
+                let text = if self.coil { "ON" } else { "OFF" };
+                ui.checkbox(&mut self.coil, text);
+                ui.end_row();
+            }
+            FunctionCode::WriteSingleRegister => {
+                ui.label("值:");
+                ui.add(egui::DragValue::new(&mut self.value));
+                ui.end_row();
+            }
+            FunctionCode::WriteMultipleCoils => {
+                self.show_values(ui, "1 0 1 1");
+            }
+            FunctionCode::WriteMultipleRegisters => {
+                self.show_values(ui, "1, 2, 0x10, -1");
+            }
+            FunctionCode::MaskWriteRegister => {
+                ui.label("AND 掩码:");
+                ui.add(egui::DragValue::new(&mut self.and_mask).hexadecimal(4, false, true));
+                ui.end_row();
+                ui.label("OR 掩码:");
+                ui.add(egui::DragValue::new(&mut self.or_mask).hexadecimal(4, false, true));
+                ui.end_row();
+            }
+            FunctionCode::ReadWriteMultipleRegisters => {
+                self.show_quantity(ui, MAX_READ_REGISTERS);
+                ui.label("写起始地址:");
+                ui.add(egui::DragValue::new(&mut self.write_address));
+                ui.end_row();
+                self.show_values(ui, "1, 2, 3");
+            }
+            FunctionCode::Diagnostics
+            | FunctionCode::GetCommEventCounter
+            | FunctionCode::GetCommEventLog
+            | FunctionCode::ReadDeviceIdentification => {}
+        }
+    }
+
+    fn show_diagnostic_inputs(&mut self, ui: &mut egui::Ui) {
+        ui.label("子功能:");
+        egui::ComboBox::from_id_salt("diagnostic_code_selector")
+            .selected_text(format!(
+                "{:02X} {}",
+                self.diagnostic.code(),
+                self.diagnostic.label()
+            ))
+            .width(200.0)
+            .show_ui(ui, |ui| {
+                for code in DiagnosticCode::ALL {
+                    let text = format!("{:02X} {}", code.code(), code.label());
+                    ui.selectable_value(&mut self.diagnostic, code, text);
+                }
+            });
+        ui.end_row();
+        match self.diagnostic {
+            DiagnosticCode::ReturnQueryData => self.show_values(ui, "0x1234, 0xABCD"),
+            DiagnosticCode::RestartCommunications => {
+                ui.label("");
+                ui.checkbox(&mut self.clear_log, "同时清除事件记录");
+                ui.end_row();
+            }
+            _ => {}
+        }
+    }
+
+    fn show_device_id_inputs(&mut self, ui: &mut egui::Ui) {
+        ui.label("读取类别:");
+        egui::ComboBox::from_id_salt("device_id_code_selector")
+            .selected_text(self.device_code.label())
+            .show_ui(ui, |ui| {
+                for code in ReadDeviceIdCode::ALL {
+                    ui.selectable_value(&mut self.device_code, code, code.label());
+                }
+            });
+        ui.end_row();
+        let object_label = if self.device_code == ReadDeviceIdCode::Individual {
+            "对象编号:"
+        } else {
+            "起始对象:"
+        };
+        ui.label(object_label);
+        ui.add(egui::DragValue::new(&mut self.object_id).hexadecimal(2, false, true));
+        ui.end_row();
+    }
+
+    fn show_quantity(&mut self, ui: &mut egui::Ui, max: u16) {
+        ui.label("数量:");
+        ui.add(egui::DragValue::new(&mut self.quantity).range(1..=max));
+        ui.end_row();
+    }
+
+    fn show_values(&mut self, ui: &mut egui::Ui, hint: &str) {
+        ui.label("写入值:");
+        ui.add(egui::TextEdit::singleline(&mut self.values).hint_text(hint));
+        ui.end_row();
+    }
+
+    /// 把当前输入转换成请求
+    pub fn build(&self) -> Result<Request, String> {
+        let request = match self.function {
+            FunctionCode::ReadCoils => {
+                Request::ReadCoils(self.address, check_quantity(self.quantity, MAX_READ_BITS)?)
+            }
+            FunctionCode::ReadDiscreteInputs => Request::ReadDiscreteInputs(
+                self.address,
+                check_quantity(self.quantity, MAX_READ_BITS)?,
+            ),
+            FunctionCode::ReadHoldingRegisters => Request::ReadHoldingRegisters(
+                self.address,
+                check_quantity(self.quantity, MAX_READ_REGISTERS)?,
+            ),
+            FunctionCode::ReadInputRegisters => Request::ReadInputRegisters(
+                self.address,
+                check_quantity(self.quantity, MAX_READ_REGISTERS)?,
+            ),
+            FunctionCode::WriteSingleCoil => Request::WriteSingleCoil(self.address, self.coil),
+            FunctionCode::WriteSingleRegister => {
+                Request::WriteSingleRegister(self.address, self.value)
+            }
+            FunctionCode::WriteMultipleCoils => {
+                let values = parse_bits(&self.values)?;
+                check_count(values.len(), MAX_WRITE_BITS)?;
+                Request::WriteMultipleCoils(self.address, values)
+            }
+            FunctionCode::WriteMultipleRegisters => {
+                let values = parse_registers(&self.values)?;
+                check_count(values.len(), MAX_WRITE_REGISTERS)?;
+                Request::WriteMultipleRegisters(self.address, values)
+            }
+            FunctionCode::MaskWriteRegister => {
+                Request::MaskWriteRegister(self.address, self.and_mask, self.or_mask)
+            }
+            FunctionCode::ReadWriteMultipleRegisters => {
+                let values = parse_registers(&self.values)?;
+                check_count(values.len(), MAX_READ_WRITE_REGISTERS)?;
+                Request::ReadWriteMultipleRegisters(
+                    self.address,
+                    check_quantity(self.quantity, MAX_READ_REGISTERS)?,
+                    self.write_address,
+                    values,
+                )
+            }
+            FunctionCode::Diagnostics => {
+                let data = match self.diagnostic {
+                    DiagnosticCode::ReturnQueryData => {
+                        let values = parse_registers(&self.values)?;
+                        check_count(values.len(), MAX_QUERY_DATA)?;
+                        values
+                    }
+                    DiagnosticCode::RestartCommunications if self.clear_log => {
+                        vec![RESTART_CLEAR_LOG]
+                    }
+                    _ => vec![0x0000],
+                };
+                Request::Diagnostics(self.diagnostic, data)
+            }
+            FunctionCode::GetCommEventCounter => Request::GetCommEventCounter,
+            FunctionCode::GetCommEventLog => Request::GetCommEventLog,
+            FunctionCode::ReadDeviceIdentification => {
+                Request::ReadDeviceIdentification(self.device_code, self.object_id)
+            }
+        };
+        Ok(request)
+    }
+}
+
+fn check_quantity(quantity: u16, max: u16) -> Result<u16, String> {
+    if quantity == 0 || quantity > max {
+        return Err(format!("数量必须在 1 到 {} 之间", max));
+    }
+    Ok(quantity)
+}
+
+fn check_count(count: usize, max: usize) -> Result<(), String> {
+    if count == 0 {
+        return Err("请输入写入值".to_string());
+    }
+    if count > max {
+        return Err(format!("最多写入 {} 个值，当前 {} 个", max, count));
+    }
+    Ok(())
+}
+
+fn tokens(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+}
+
+/// 解析寄存器值，支持十进制、`0x` 开头的十六进制和 -32768 起的负数
+pub fn parse_registers(text: &str) -> Result<Vec<u16>, String> {
+    tokens(text)
+        .map(|token| {
+            let parsed = if let Some(hex) = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+            {
+                u16::from_str_radix(hex, 16).ok()
+            } else if token.starts_with('-') {
+                token.parse::<i16>().ok().map(|value| value as u16)
+            } else {
+                token.parse::<u16>().ok()
+            };
+            parsed.ok_or_else(|| format!("无法解析寄存器值 \"{}\"", token))
+        })
+        .collect()
+}
+
+/// 解析线圈值，支持 1/0、on/off 和 true/false
+pub fn parse_bits(text: &str) -> Result<Vec<bool>, String> {
+    tokens(text)
+        .map(|token| match token.to_ascii_lowercase().as_str() {
+            "1" | "on" | "true" => Ok(true),
+            "0" | "off" | "false" => Ok(false),
+            _ => Err(format!("无法解析线圈值 \"{}\"", token)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(
+            parse_registers("1, 0x10 -1\t65535"),
+            Ok(vec![1, 0x10, 0xFFFF, 65535])
+        );
+        assert!(parse_registers("65536").is_err());
+        assert_eq!(
+            parse_bits("1 off TRUE,0"),
+            Ok(vec![true, false, true, false])
+        );
+        assert!(parse_bits("2").is_err());
+    }
+
+    #[test]
+    fn test_build_checks_limits() {
+        let mut builder = RequestBuilder {
+            function: FunctionCode::WriteMultipleRegisters,
+            address: 100,
+            values: "1 2 3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            builder.build(),
+            Ok(Request::WriteMultipleRegisters(100, vec![1, 2, 3]))
+        );
+
+        builder.values.clear();
+        assert!(builder.build().is_err());
+
+        builder.function = FunctionCode::ReadHoldingRegisters;
+        builder.quantity = 126;
+        assert!(builder.build().is_err());
+
+        builder.function = FunctionCode::Diagnostics;
+        builder.diagnostic = DiagnosticCode::RestartCommunications;
+        builder.clear_log = true;
+        assert_eq!(
+            builder.build(),
+            Ok(Request::Diagnostics(
+                DiagnosticCode::RestartCommunications,
+                vec![0xFF00]
+            ))
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! 主机引擎
+//!
+//! 在任务建立的 tokio-modbus 客户端上下文（RTU 或 TCP）上逐条执行界面发来的主机请求
+//! 和到期的轮询，并把结果作为事件送回界面。
+
+use super::discovery::{DiscoveryResult, DiscoverySettings};
+use super::poll::{PollScheduler, SharedPolls};
+use super::scan::{ScanCommand, ScanEnd, ScanResult, ScanSettings};
+use crate::modbus::pdu::{
+    CommEventLog, DIAGNOSTICS, DeviceIdentification, DiagnosticCode, ExceptionCode,
+    GET_COMM_EVENT_COUNTER, GET_COMM_EVENT_LOG, MEI_FUNCTION, MEI_READ_DEVICE_ID, Request,
+    Response,
+};
+use crate::serial::LineParams;
+use crate::task::{EventSender, TaskCommand, TaskEvent};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, error::TryRecvError};
+use tokio_modbus::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+/// 没有到期的轮询时，重新读取轮询列表的间隔
+const POLL_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 每条请求执行前读取的链路时序参数
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// 等待应答的超时时间
+    pub timeout: Duration,
+    /// 上一帧结束到下一帧开始的最小间隔，RTU 总线上的慢速设备需要
+    pub gap: Duration,
+}
+
+/// 界面发给主机引擎的一条请求
+#[derive(Debug, Clone)]
+pub struct MasterRequest {
+    /// 由界面分配，用于把应答和请求对应起来
+    pub id: u64,
+    pub unit: u8,
+    pub request: Request,
+}
+
+/// 主机请求的执行结果
+#[derive(Debug, Clone)]
+pub struct MasterResponse {
+    pub id: u64,
+    pub unit: u8,
+    pub request: Request,
+    pub result: Result<Response, MasterError>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MasterError {
+    /// 从机返回了异常应答
+    Exception(ExceptionCode),
+    /// 在超时时间内没有收到应答
+    Timeout,
+    /// 传输层或协议错误
+    Transport(String),
+    /// 当前没有运行主机引擎
+    NotMaster,
+    /// 主机引擎正在扫描总线
+    Scanning,
+}
+
+impl fmt::Display for MasterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MasterError::Exception(code) => write!(f, "异常应答: {}", code),
+            MasterError::Timeout => write!(f, "应答超时"),
+            MasterError::Transport(message) => write!(f, "通信错误: {}", message),
+            MasterError::NotMaster => write!(f, "当前不是主机模式"),
+            MasterError::Scanning => write!(f, "正在扫描总线"),
+        }
+    }
+}
+
+impl MasterResponse {
+    /// 请求没有被执行时的应答
+    pub fn failed(request: MasterRequest, error: MasterError) -> Self {
+        Self {
+            id: request.id,
+            unit: request.unit,
+            request: request.request,
+            result: Err(error),
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// 主机下一步要执行的事务
+enum Job {
+    Request(MasterRequest),
+    Poll(u64, MasterRequest),
+    Scan(ScanSettings),
+    Discover(DiscoverySettings),
+}
+
+/// 客户端上下文，负责帧间隔和事务进行中的标记
+struct Bus<'a> {
+    ctx: client::Context,
+    last_frame: Option<Instant>,
+    in_flight: &'a AtomicBool,
+}
+
+impl Bus<'_> {
+    /// 距上一帧满足帧间隔后执行一条请求
+    async fn transact(&mut self, request: MasterRequest, timing: Timing) -> MasterResponse {
+        if let Some(last_frame) = self.last_frame {
+            tokio::time::sleep_until((last_frame + timing.gap).into()).await;
+        }
+        self.in_flight.store(true, Ordering::Relaxed);
+        let response = execute(&mut self.ctx, request, timing.timeout).await;
+        self.in_flight.store(false, Ordering::Relaxed);
+        self.last_frame = Some(Instant::now());
+        response
+    }
+}
+
+/// 临时修改串口的线路参数，`None` 表示恢复为串口设置中的参数
+pub type Reconfigure<'a> = dyn Fn(Option<LineParams>) -> Result<(), String> + Sync + 'a;
+
+/// 在客户端上下文上运行主机，直到任务被取消或命令通道关闭
+///
+/// 界面发来的请求优先执行，空闲时执行到期的轮询。每条请求的超时和帧间隔在执行前通过 `timing` 读取，
+/// 修改设置后立即生效。取消只在两条请求之间生效，正在进行的事务会完整结束，不会在总线上留下半帧。
+/// `reconfigure` 用于参数识别，只有串口链路提供。
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    ctx: client::Context,
+    timing: &(dyn Fn() -> Timing + Sync),
+    reconfigure: Option<&Reconfigure<'_>>,
+    polls: &SharedPolls,
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+    cancel: &CancellationToken,
+    in_flight: &AtomicBool,
+) -> io::Result<()> {
+    log::info!("主机启动");
+    let mut scheduler = PollScheduler::default();
+    let mut bus = Bus {
+        ctx,
+        last_frame: None,
+        in_flight,
+    };
+    loop {
+        let now = Instant::now();
+        scheduler.sync(&polls.lock().unwrap(), now);
+        let recheck = now + POLL_RECHECK_INTERVAL;
+        let wake = scheduler.next_due().map_or(recheck, |due| due.min(recheck));
+        let job = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => break,
+            command = commands.recv() => match command {
+                Some(TaskCommand::Master(request)) => Job::Request(request),
+                Some(TaskCommand::Scan(ScanCommand::Start(settings))) => Job::Scan(settings),
+                Some(TaskCommand::Scan(ScanCommand::Discover(settings))) => Job::Discover(settings),
+                // 扫描已经结束
+                Some(TaskCommand::Scan(ScanCommand::Stop)) => continue,
+                None => break,
+            },
+            _ = tokio::time::sleep_until(wake.into()) => {
+                let Some(id) = scheduler.take_due(Instant::now()) else {
+                    continue;
+                };
+                let polls = polls.lock().unwrap();
+                let Some(poll) = polls.iter().find(|poll| poll.id == id) else {
+                    continue;
+                };
+                let Some(request) = poll.request() else {
+                    continue;
+                };
+                Job::Poll(id, MasterRequest { id, unit: poll.unit, request })
+            }
+        };
+
+        let timing = timing();
+        let event = match job {
+            Job::Request(request) => TaskEvent::Master(bus.transact(request, timing).await),
+            Job::Poll(poll, request) => TaskEvent::Polled {
+                poll,
+                response: bus.transact(request, timing).await,
+            },
+            Job::Scan(settings) => TaskEvent::ScanFinished(
+                scan(&mut bus, &settings, timing.gap, commands, events, cancel).await,
+            ),
+            Job::Discover(settings) => TaskEvent::ScanFinished(
+                discover(
+                    &mut bus,
+                    &settings,
+                    timing.gap,
+                    reconfigure,
+                    commands,
+                    events,
+                    cancel,
+                )
+                .await,
+            ),
+        };
+        events.send(event);
+    }
+    Ok(())
+}
+
+/// 依次探测扫描范围内的站号，每个站号的结果立即发给界面
+///
+/// 每次探测前检查取消和新命令：收到停止命令或任务被取消时结束，期间收到的主机请求回复正在扫描。
+/// 轮询在扫描期间暂停，错过的周期在扫描结束后直接跳过。
+async fn scan(
+    bus: &mut Bus<'_>,
+    settings: &ScanSettings,
+    gap: Duration,
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+    cancel: &CancellationToken,
+) -> ScanEnd {
+    log::info!("开始扫描站号 {:?}", settings.units());
+    let request = settings.probe.request(settings.address);
+    let timing = Timing {
+        timeout: settings.timeout,
+        gap,
+    };
+    for unit in settings.units() {
+        let mut attempts = 0;
+        let response = loop {
+            if stop_requested(commands, events, cancel) {
+                return ScanEnd::Stopped;
+            }
+            attempts += 1;
+            let probe = MasterRequest {
+                id: 0,
+                unit,
+                request: request.clone(),
+            };
+            let response = bus.transact(probe, timing).await;
+            // 异常应答说明设备存在，不需要重试
+            let retry = matches!(
+                response.result,
+                Err(MasterError::Timeout | MasterError::Transport(_))
+            );
+            if !retry || attempts > settings.retries {
+                break response;
+            }
+        };
+        events.send(TaskEvent::Scanned(ScanResult {
+            unit,
+            result: response.result,
+            elapsed: response.elapsed,
+            attempts,
+        }));
+    }
+    ScanEnd::Completed
+}
+
+/// 处理扫描期间界面发来的命令，返回是否应该停止扫描
+///
+/// 收到停止命令、任务被取消或命令通道关闭时停止；主机请求回复正在扫描，新的扫描被忽略。
+fn stop_requested(
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+    cancel: &CancellationToken,
+) -> bool {
+    if cancel.is_cancelled() {
+        return true;
+    }
+    loop {
+        match commands.try_recv() {
+            Ok(TaskCommand::Scan(ScanCommand::Stop)) => return true,
+            Ok(TaskCommand::Scan(_)) => log::warn!("扫描进行中，忽略新的扫描"),
+            Ok(TaskCommand::Master(request)) => events.send(TaskEvent::Master(
+                MasterResponse::failed(request, MasterError::Scanning),
+            )),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => return true,
+        }
+    }
+}
+
+/// 依次用每组线路参数向同一站号发送探测请求，每组的结果立即发给界面
+///
+/// 结束时（包括停止和出错）把串口恢复为串口设置中的参数。
+async fn discover(
+    bus: &mut Bus<'_>,
+    settings: &DiscoverySettings,
+    gap: Duration,
+    reconfigure: Option<&Reconfigure<'_>>,
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+    cancel: &CancellationToken,
+) -> ScanEnd {
+    let Some(reconfigure) = reconfigure else {
+        return ScanEnd::Failed(MasterError::Transport(
+            "只有串口连接可以识别参数".to_string(),
+        ));
+    };
+    log::info!("开始识别站号 {} 的串口参数", settings.unit);
+    let request = settings.probe.request(settings.address);
+    let timing = Timing {
+        timeout: settings.timeout,
+        gap,
+    };
+    let mut restore = RestoreLine(Some(reconfigure));
+    let mut end = ScanEnd::Completed;
+    for params in settings.candidates() {
+        if stop_requested(commands, events, cancel) {
+            end = ScanEnd::Stopped;
+            break;
+        }
+        if let Err(err) = reconfigure(Some(params)) {
+            end = ScanEnd::Failed(MasterError::Transport(err));
+            break;
+        }
+        let probe = MasterRequest {
+            id: 0,
+            unit: settings.unit,
+            request: request.clone(),
+        };
+        let response = bus.transact(probe, timing).await;
+        let result = DiscoveryResult {
+            params,
+            result: response.result,
+            elapsed: response.elapsed,
+        };
+        let found = result.is_valid();
+        if found {
+            log::info!("站号 {} 在 {} 下应答", settings.unit, params);
+        }
+        events.send(TaskEvent::Discovered(result));
+        if found && settings.first_match_only {
+            break;
+        }
+    }
+    if let Err(err) = restore.restore() {
+        end = ScanEnd::Failed(MasterError::Transport(err));
+    }
+    end
+}
+
+/// 参数识别被中途丢弃（例如切换了工作模式）时也要把串口恢复为原来的参数
+struct RestoreLine<'a>(Option<&'a Reconfigure<'a>>);
+
+impl RestoreLine<'_> {
+    fn restore(&mut self) -> Result<(), String> {
+        match self.0.take() {
+            Some(reconfigure) => reconfigure(None).inspect_err(|err| {
+                log::error!("恢复串口参数失败: {}", err);
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RestoreLine<'_> {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+async fn execute(
+    ctx: &mut client::Context,
+    request: MasterRequest,
+    timeout: Duration,
+) -> MasterResponse {
+    ctx.set_slave(Slave(request.unit));
+    let started = Instant::now();
+    let call = ctx.call(to_tokio_request(&request.request));
+    let result = match tokio::time::timeout(timeout, call).await {
+        Err(_) => Err(MasterError::Timeout),
+        Ok(Err(err)) => Err(MasterError::Transport(err.to_string())),
+        Ok(Ok(Err(exception))) => Err(MasterError::Exception(from_tokio_exception(exception))),
+        Ok(Ok(Ok(response))) => from_tokio_response(response)
+            .ok_or_else(|| MasterError::Transport("无法识别的应答".to_string())),
+    };
+    log::debug!("主机请求 {:?} -> {:?}", request.request, result);
+    MasterResponse {
+        id: request.id,
+        unit: request.unit,
+        request: request.request,
+        result,
+        elapsed: started.elapsed(),
+    }
+}
+
+fn to_tokio_request(request: &Request) -> tokio_modbus::Request<'static> {
+    use tokio_modbus::Request as R;
+    match request.clone() {
+        Request::ReadCoils(address, quantity) => R::ReadCoils(address, quantity),
+        Request::ReadDiscreteInputs(address, quantity) => R::ReadDiscreteInputs(address, quantity),
+        Request::ReadHoldingRegisters(address, quantity) => {
+            R::ReadHoldingRegisters(address, quantity)
+        }
+        Request::ReadInputRegisters(address, quantity) => R::ReadInputRegisters(address, quantity),
+        Request::WriteSingleCoil(address, value) => R::WriteSingleCoil(address, value),
+        Request::WriteSingleRegister(address, value) => R::WriteSingleRegister(address, value),
+        Request::WriteMultipleCoils(address, values) => {
+            R::WriteMultipleCoils(address, values.into())
+        }
+        Request::WriteMultipleRegisters(address, values) => {
+            R::WriteMultipleRegisters(address, values.into())
+        }
+        Request::MaskWriteRegister(address, and_mask, or_mask) => {
+            R::MaskWriteRegister(address, and_mask, or_mask)
+        }
+        Request::ReadWriteMultipleRegisters(read_address, quantity, write_address, values) => {
+            R::ReadWriteMultipleRegisters(read_address, quantity, write_address, values.into())
+        }
+        Request::Diagnostics(code, data) => R::Custom(DIAGNOSTICS, code.encode(&data).into()),
+        Request::GetCommEventCounter => R::Custom(GET_COMM_EVENT_COUNTER, Vec::new().into()),
+        Request::GetCommEventLog => R::Custom(GET_COMM_EVENT_LOG, Vec::new().into()),
+        Request::ReadDeviceIdentification(code, object) => R::Custom(
+            MEI_FUNCTION,
+            vec![MEI_READ_DEVICE_ID, code.code(), object].into(),
+        ),
+    }
+}
+
+fn from_tokio_response(response: tokio_modbus::Response) -> Option<Response> {
+    use tokio_modbus::Response as R;
+    let response = match response {
+        R::ReadCoils(values) => Response::ReadCoils(values),
+        R::ReadDiscreteInputs(values) => Response::ReadDiscreteInputs(values),
+        R::ReadHoldingRegisters(values) => Response::ReadHoldingRegisters(values),
+        R::ReadInputRegisters(values) => Response::ReadInputRegisters(values),
+        R::WriteSingleCoil(address, value) => Response::WriteSingleCoil(address, value),
+        R::WriteSingleRegister(address, value) => Response::WriteSingleRegister(address, value),
+        R::WriteMultipleCoils(address, quantity) => Response::WriteMultipleCoils(address, quantity),
+        R::WriteMultipleRegisters(address, quantity) => {
+            Response::WriteMultipleRegisters(address, quantity)
+        }
+        R::MaskWriteRegister(address, and_mask, or_mask) => {
+            Response::MaskWriteRegister(address, and_mask, or_mask)
+        }
+        R::ReadWriteMultipleRegisters(values) => Response::ReadWriteMultipleRegisters(values),
+        R::Custom(MEI_FUNCTION, data) => {
+            Response::ReadDeviceIdentification(DeviceIdentification::parse(&data)?)
+        }
+        R::Custom(DIAGNOSTICS, data) => {
+            let (code, words) = DiagnosticCode::parse(&data)?;
+            Response::Diagnostics(DiagnosticCode::from_code(code)?, words)
+        }
+        R::Custom(GET_COMM_EVENT_COUNTER, data) => match *data {
+            [s0, s1, c0, c1] => Response::GetCommEventCounter(
+                u16::from_be_bytes([s0, s1]),
+                u16::from_be_bytes([c0, c1]),
+            ),
+            _ => return None,
+        },
+        R::Custom(GET_COMM_EVENT_LOG, data) => {
+            Response::GetCommEventLog(CommEventLog::parse(&data)?)
+        }
+        _ => return None,
+    };
+    Some(response)
+}
+
+fn from_tokio_exception(exception: tokio_modbus::ExceptionCode) -> ExceptionCode {
+    use tokio_modbus::ExceptionCode as E;
+    match exception {
+        E::IllegalFunction => ExceptionCode::IllegalFunction,
+        E::IllegalDataAddress => ExceptionCode::IllegalDataAddress,
+        E::IllegalDataValue => ExceptionCode::IllegalDataValue,
+        E::ServerDeviceFailure => ExceptionCode::ServerDeviceFailure,
+        E::Acknowledge => ExceptionCode::Acknowledge,
+        E::ServerDeviceBusy => ExceptionCode::ServerDeviceBusy,
+        E::MemoryParityError => ExceptionCode::MemoryParityError,
+        E::GatewayPathUnavailable => ExceptionCode::GatewayPathUnavailable,
+        E::GatewayTargetDevice => ExceptionCode::GatewayTargetDevice,
+        E::Custom(code) => ExceptionCode::Other(code),
+    }
+}
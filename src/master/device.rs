@@ -0,0 +1,317 @@
+//! 设备信息
+//!
+//! 用 43/14 读设备标识读取一个站号的厂商、产品代码、版本等信息。流式读取时一帧放不下
+//! 全部对象的设备会在应答中给出下一个对象编号，这里自动继续读取，直到读完。
+
+use super::client::MasterResponse;
+use crate::modbus::decode::device_object_name;
+use crate::modbus::pdu::{DeviceIdentification, ReadDeviceIdCode, Request, Response};
+use eframe::*;
+use std::collections::BTreeMap;
+
+/// 常规类别的最后一个对象，这之前的对象没有读到时也列出
+const LAST_REGULAR_OBJECT: u8 = 0x06;
+
+/// 主机页面的设备信息视图
+#[derive(Debug)]
+pub struct DeviceInfo {
+    unit: u8,
+    code: ReadDeviceIdCode,
+    //单个读取的对象编号
+    object: u8,
+    //等待发给后台任务的请求
+    request: Option<Request>,
+    //已发出、等待应答的请求编号
+    waiting: Option<u64>,
+    //本次读取的站号、起始对象和发出的请求数
+    read_unit: u8,
+    start: u8,
+    requests: usize,
+    conformity: Option<u8>,
+    objects: BTreeMap<u8, Vec<u8>>,
+    error: Option<String>,
+}
+
+impl Default for DeviceInfo {
+    fn default() -> Self {
+        Self {
+            unit: 1,
+            code: ReadDeviceIdCode::default(),
+            object: 0,
+            request: None,
+            waiting: None,
+            read_unit: 1,
+            start: 0,
+            requests: 0,
+            conformity: None,
+            objects: BTreeMap::new(),
+            error: None,
+        }
+    }
+}
+
+impl DeviceInfo {
+    /// 显示读取设置和读到的标识对象
+    ///
+    /// `found` 是总线扫描发现的站号。
+    pub fn show(&mut self, ui: &mut egui::Ui, found: &[u8]) {
+        let busy = self.is_reading();
+        ui.add_enabled_ui(!busy, |ui| self.show_settings(ui, found));
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!busy, egui::Button::new("读取")).clicked() {
+                self.start_reading();
+            }
+            if busy {
+                ui.spinner();
+                ui.label(format!("正在读取站号 {}", self.read_unit));
+            } else if self.requests > 0 {
+                ui.label(format!(
+                    "站号 {}，{} 个对象，{} 帧",
+                    self.read_unit,
+                    self.objects.len(),
+                    self.requests
+                ));
+            }
+        });
+        if let Some(err) = &self.error {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+        }
+        ui.separator();
+        self.show_objects(ui);
+    }
+
+    fn show_settings(&mut self, ui: &mut egui::Ui, found: &[u8]) {
+        egui::Grid::new("device_info_settings")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("站号:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.unit).range(1..=247));
+                    if !found.is_empty() {
+                        egui::ComboBox::from_id_salt("device_info_found")
+                            .selected_text("扫描发现的站号")
+                            .show_ui(ui, |ui| {
+                                for &unit in found {
+                                    ui.selectable_value(&mut self.unit, unit, unit.to_string());
+                                }
+                            });
+                    }
+                });
+                ui.end_row();
+
+                ui.label("读取类别:");
+                egui::ComboBox::from_id_salt("device_info_code")
+                    .selected_text(self.code.label())
+                    .show_ui(ui, |ui| {
+                        for code in ReadDeviceIdCode::ALL {
+                            ui.selectable_value(&mut self.code, code, code.label());
+                        }
+                    });
+                ui.end_row();
+
+                if self.code == ReadDeviceIdCode::Individual {
+                    ui.label("对象编号:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.object).hexadecimal(2, false, true));
+                        ui.label(device_object_name(self.object));
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn show_objects(&self, ui: &mut egui::Ui) {
+        if let Some(level) = self.conformity {
+            ui.label(format!(
+                "标识等级: 0x{:02X} {}",
+                level,
+                conformity_text(level)
+            ));
+        }
+        if self.objects.is_empty() {
+            return;
+        }
+        // 单个读取只列出读到的对象，流式读取同时列出没有读到的常规对象
+        let mut ids: Vec<u8> = self.objects.keys().copied().collect();
+        if self.code != ReadDeviceIdCode::Individual {
+            ids.extend(0..=LAST_REGULAR_OBJECT);
+            ids.sort_unstable();
+            ids.dedup();
+        }
+        egui::ScrollArea::vertical()
+            .id_salt("device_info_objects")
+            .show(ui, |ui| {
+                egui::Grid::new("device_info_objects_grid")
+                    .num_columns(3)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for title in ["编号", "名称", "内容"] {
+                            ui.strong(title);
+                        }
+                        ui.end_row();
+                        for id in ids {
+                            ui.monospace(format!("0x{:02X}", id));
+                            ui.label(device_object_name(id));
+                            match self.objects.get(&id) {
+                                Some(value) => {
+                                    ui.label(String::from_utf8_lossy(value));
+                                }
+                                None => {
+                                    ui.weak("-");
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    fn start_reading(&mut self) {
+        let start = match self.code {
+            ReadDeviceIdCode::Individual => self.object,
+            _ => 0,
+        };
+        log::info!(
+            "读取站号 {} 的{}设备标识，起始对象 0x{:02X}",
+            self.unit,
+            self.code.label(),
+            start
+        );
+        self.read_unit = self.unit;
+        self.start = start;
+        self.requests = 0;
+        self.conformity = None;
+        self.objects.clear();
+        self.error = None;
+        self.request = Some(Request::ReadDeviceIdentification(self.code, start));
+    }
+
+    /// 取出等待发送的请求和站号，发出后用 `sent` 记下请求编号
+    pub fn take_request(&mut self) -> Option<(u8, Request)> {
+        self.request.take().map(|request| (self.read_unit, request))
+    }
+
+    pub fn sent(&mut self, id: u64) {
+        self.requests += 1;
+        self.waiting = Some(id);
+    }
+
+    /// 处理本视图发出的请求的应答，不是时返回 `false`
+    ///
+    /// 应答中有下一个对象编号时准备继续读取的请求。
+    pub fn handle_response(&mut self, response: &MasterResponse) -> bool {
+        if self.waiting != Some(response.id) {
+            return false;
+        }
+        self.waiting = None;
+        match &response.result {
+            Ok(Response::ReadDeviceIdentification(identification)) => self.merge(identification),
+            Ok(other) => self.error = Some(format!("应答类型不符: {}", other)),
+            Err(err) => self.error = Some(err.to_string()),
+        }
+        true
+    }
+
+    fn merge(&mut self, identification: &DeviceIdentification) {
+        self.conformity = Some(identification.conformity);
+        self.objects.extend(identification.objects.iter().cloned());
+        let Some(next) = identification.next_object else {
+            return;
+        };
+        if self.code == ReadDeviceIdCode::Individual {
+            return;
+        }
+        // 下一个对象编号没有前进时继续读取会陷入循环
+        if next <= self.start {
+            self.error = Some(format!(
+                "设备给出的下一个对象 0x{:02X} 没有前进，停止读取",
+                next
+            ));
+            return;
+        }
+        self.start = next;
+        self.request = Some(Request::ReadDeviceIdentification(self.code, next));
+    }
+
+    /// 读取在进行中：有请求等待发送或等待应答
+    pub fn is_reading(&self) -> bool {
+        self.request.is_some() || self.waiting.is_some()
+    }
+}
+
+/// 标识等级的含义，最高位表示支持单个读取
+fn conformity_text(level: u8) -> String {
+    let category = ReadDeviceIdCode::from_code(level & 0x7F)
+        .filter(|code| *code != ReadDeviceIdCode::Individual)
+        .map_or("未知", ReadDeviceIdCode::label);
+    if level & 0x80 != 0 {
+        format!("{}类别，支持单个读取", category)
+    } else {
+        format!("{}类别，仅流式读取", category)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn response(id: u64, next_object: Option<u8>, objects: Vec<(u8, Vec<u8>)>) -> MasterResponse {
+        MasterResponse {
+            id,
+            unit: 1,
+            request: Request::ReadDeviceIdentification(ReadDeviceIdCode::Extended, 0),
+            result: Ok(Response::ReadDeviceIdentification(DeviceIdentification {
+                code: 0x03,
+                conformity: 0x83,
+                next_object,
+                objects,
+            })),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_continues_until_complete() {
+        let mut info = DeviceInfo {
+            unit: 3,
+            code: ReadDeviceIdCode::Extended,
+            ..Default::default()
+        };
+        info.start_reading();
+        assert_eq!(
+            info.take_request(),
+            Some((
+                3,
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Extended, 0)
+            ))
+        );
+        info.sent(7);
+        assert!(!info.handle_response(&response(6, None, vec![])));
+
+        assert!(info.handle_response(&response(7, Some(0x80), vec![(0, b"ACME".to_vec())])));
+        assert_eq!(
+            info.take_request(),
+            Some((
+                3,
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Extended, 0x80)
+            ))
+        );
+        info.sent(8);
+        assert!(info.handle_response(&response(8, None, vec![(0x80, b"SN".to_vec())])));
+        assert!(!info.is_reading());
+        assert_eq!(info.objects.len(), 2);
+        assert_eq!(info.error, None);
+
+        // 下一个对象编号回退时停止
+        info.start_reading();
+        info.take_request();
+        info.sent(9);
+        info.start = 0x80;
+        info.handle_response(&response(9, Some(0x10), vec![]));
+        assert!(!info.is_reading());
+        assert!(info.error.is_some());
+    }
+}
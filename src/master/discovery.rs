@@ -0,0 +1,348 @@
+//! 串口参数识别
+//!
+//! 设备的波特率、校验位和停止位未知时，依次用每一组参数向指定站号发送探测请求，
+//! 收到 CRC 正确的应答（包括异常应答）说明这组参数可用。识别由主机引擎执行，
+//! 期间临时修改已打开串口的参数，结束后恢复为串口设置中的参数。
+
+use super::client::MasterError;
+use super::scan::{ScanCommand, ScanEnd, ScanProbe};
+use crate::modbus::pdu::Response;
+use crate::serial::{BAUD_RATES, LineParams};
+use eframe::*;
+use std::time::Duration;
+
+/// 一次参数识别的设置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoverySettings {
+    pub unit: u8,
+    pub probe: ScanProbe,
+    /// 读保持寄存器探测的地址
+    pub address: u16,
+    /// 每组参数等待应答的时间
+    pub timeout: Duration,
+    /// 要尝试的波特率，按 `BAUD_RATES` 的顺序
+    pub baud_rates: Vec<u32>,
+    /// 找到第一组可用参数后停止
+    pub first_match_only: bool,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        Self {
+            unit: 1,
+            probe: ScanProbe::default(),
+            address: 0,
+            timeout: Duration::from_millis(200),
+            baud_rates: BAUD_RATES.to_vec(),
+            first_match_only: true,
+        }
+    }
+}
+
+impl DiscoverySettings {
+    /// 依次尝试的参数组合：每个波特率下先试无校验，再试偶校验和奇校验
+    pub fn candidates(&self) -> Vec<LineParams> {
+        let mut candidates = Vec::new();
+        for &baud_rate in &self.baud_rates {
+            for parity in LineParams::PARITIES {
+                for stop_bits in LineParams::STOP_BITS {
+                    candidates.push(LineParams {
+                        baud_rate,
+                        parity,
+                        stop_bits,
+                    });
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// 一组参数的探测结果
+#[derive(Debug, Clone)]
+pub struct DiscoveryResult {
+    pub params: LineParams,
+    pub result: Result<Response, MasterError>,
+    pub elapsed: Duration,
+}
+
+impl DiscoveryResult {
+    /// 正常应答和异常应答的 CRC 都正确
+    pub fn is_valid(&self) -> bool {
+        matches!(self.result, Ok(_) | Err(MasterError::Exception(_)))
+    }
+}
+
+/// 主机页面的参数识别视图
+#[derive(Debug)]
+pub struct ParamDiscovery {
+    settings: DiscoverySettings,
+    results: Vec<DiscoveryResult>,
+    running: bool,
+    //本次识别要尝试的组合数
+    total: usize,
+    //等待发给后台任务的命令
+    command: Option<ScanCommand>,
+    end: Option<ScanEnd>,
+    //只列出可用的参数
+    valid_only: bool,
+}
+
+impl Default for ParamDiscovery {
+    fn default() -> Self {
+        Self {
+            settings: DiscoverySettings::default(),
+            results: Vec::new(),
+            running: false,
+            total: 0,
+            command: None,
+            end: None,
+            valid_only: true,
+        }
+    }
+}
+
+impl ParamDiscovery {
+    /// 显示识别设置和结果，返回点击“应用”的参数
+    ///
+    /// `found` 是总线扫描发现的站号，`busy` 表示总线扫描正在进行，此时不能开始识别。
+    pub fn show(&mut self, ui: &mut egui::Ui, found: &[u8], busy: bool) -> Option<LineParams> {
+        ui.add_enabled_ui(!self.running, |ui| self.show_settings(ui, found));
+        ui.horizontal(|ui| {
+            if self.running {
+                if ui.button("停止").clicked() {
+                    self.command = Some(ScanCommand::Stop);
+                }
+            } else if ui
+                .add_enabled(
+                    !busy && !self.settings.baud_rates.is_empty(),
+                    egui::Button::new("开始识别"),
+                )
+                .on_hover_text("识别期间临时修改串口参数，结束后恢复")
+                .clicked()
+            {
+                log::info!("开始识别串口参数: {:?}", self.settings);
+                self.results.clear();
+                self.end = None;
+                self.running = true;
+                self.total = self.settings.candidates().len();
+                self.command = Some(ScanCommand::Discover(self.settings.clone()));
+            }
+            ui.checkbox(&mut self.valid_only, "只显示可用的参数");
+        });
+        self.show_progress(ui);
+        ui.separator();
+        self.show_results(ui)
+    }
+
+    fn show_settings(&mut self, ui: &mut egui::Ui, found: &[u8]) {
+        let settings = &mut self.settings;
+        egui::Grid::new("param_discovery_settings")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("站号:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut settings.unit).range(1..=247));
+                    if !found.is_empty() {
+                        egui::ComboBox::from_id_salt("param_discovery_found")
+                            .selected_text("扫描发现的站号")
+                            .show_ui(ui, |ui| {
+                                for &unit in found {
+                                    ui.selectable_value(&mut settings.unit, unit, unit.to_string());
+                                }
+                            });
+                    }
+                });
+                ui.end_row();
+
+                ui.label("探测请求:");
+                egui::ComboBox::from_id_salt("param_discovery_probe")
+                    .selected_text(settings.probe.label())
+                    .show_ui(ui, |ui| {
+                        for probe in ScanProbe::ALL {
+                            ui.selectable_value(&mut settings.probe, probe, probe.label());
+                        }
+                    });
+                ui.end_row();
+
+                if settings.probe == ScanProbe::ReadHoldingRegister {
+                    ui.label("寄存器地址:");
+                    ui.add(egui::DragValue::new(&mut settings.address));
+                    ui.end_row();
+                }
+
+                ui.label("超时:");
+                let mut timeout_ms = settings.timeout.as_millis() as u64;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut timeout_ms)
+                            .range(10..=10_000)
+                            .suffix("ms"),
+                    )
+                    .changed()
+                {
+                    settings.timeout = Duration::from_millis(timeout_ms);
+                }
+                ui.end_row();
+
+                ui.label("波特率:");
+                ui.horizontal_wrapped(|ui| {
+                    for baud_rate in BAUD_RATES {
+                        let mut checked = settings.baud_rates.contains(&baud_rate);
+                        if ui.checkbox(&mut checked, baud_rate.to_string()).changed() {
+                            settings.baud_rates.retain(|&rate| rate != baud_rate);
+                            if checked {
+                                settings.baud_rates.push(baud_rate);
+                                settings.baud_rates.sort_unstable();
+                            }
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label("");
+                ui.checkbox(&mut settings.first_match_only, "找到可用参数后停止");
+                ui.end_row();
+            });
+        ui.label("每个波特率依次尝试无校验、偶校验、奇校验和 1、2 个停止位，数据位沿用串口设置");
+    }
+
+    fn show_progress(&self, ui: &mut egui::Ui) {
+        let valid = self
+            .results
+            .iter()
+            .filter(|result| result.is_valid())
+            .count();
+        if self.running {
+            let current = match self.results.last() {
+                Some(result) => format!("已尝试 {}", result.params),
+                None => "正在开始".to_string(),
+            };
+            ui.add(
+                egui::ProgressBar::new(self.results.len() as f32 / self.total.max(1) as f32)
+                    .text(format!("{}，找到 {} 组", current, valid)),
+            );
+        } else if let Some(end) = &self.end {
+            let text = format!(
+                "{}，尝试 {} 组参数，{} 组可用",
+                end,
+                self.results.len(),
+                valid
+            );
+            match end {
+                ScanEnd::Failed(_) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), text);
+                }
+                _ => {
+                    ui.label(text);
+                }
+            }
+        }
+    }
+
+    fn show_results(&self, ui: &mut egui::Ui) -> Option<LineParams> {
+        let mut chosen = None;
+        egui::ScrollArea::vertical()
+            .id_salt("param_discovery_results")
+            .show(ui, |ui| {
+                egui::Grid::new("param_discovery_results_grid")
+                    .num_columns(4)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for title in ["参数", "结果", "延迟", ""] {
+                            ui.strong(title);
+                        }
+                        ui.end_row();
+                        let results = self
+                            .results
+                            .iter()
+                            .filter(|result| !self.valid_only || result.is_valid());
+                        for result in results {
+                            ui.monospace(result.params.to_string());
+                            match &result.result {
+                                Ok(value) => {
+                                    ui.colored_label(
+                                        egui::Color32::from_rgb(40, 160, 40),
+                                        format!("应答: {}", value),
+                                    );
+                                }
+                                Err(MasterError::Exception(code)) => {
+                                    ui.colored_label(
+                                        egui::Color32::from_rgb(220, 160, 0),
+                                        format!("异常应答: {}", code),
+                                    );
+                                }
+                                Err(err) => {
+                                    ui.weak(err.to_string());
+                                }
+                            }
+                            if result.is_valid() {
+                                ui.label(format!("{} ms", result.elapsed.as_millis()));
+                                if ui
+                                    .button("应用到串口")
+                                    .on_hover_text("修改串口设置中的波特率、校验位和停止位")
+                                    .clicked()
+                                {
+                                    chosen = Some(result.params);
+                                }
+                            } else {
+                                ui.label("-");
+                                ui.label("");
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        chosen
+    }
+
+    /// 取出等待发送的识别命令，由应用转交给后台任务
+    pub fn take_command(&mut self) -> Option<ScanCommand> {
+        self.command.take()
+    }
+
+    pub fn handle_result(&mut self, result: DiscoveryResult) {
+        self.results.push(result);
+    }
+
+    /// 识别结束，没有在进行时忽略
+    pub fn finish(&mut self, end: ScanEnd) {
+        if self.running {
+            log::info!("参数识别: {}", end);
+            self.running = false;
+            self.end = Some(end);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_serial::{Parity, StopBits};
+
+    #[test]
+    fn test_candidates() {
+        let settings = DiscoverySettings {
+            baud_rates: vec![9600, 19200],
+            ..Default::default()
+        };
+        let candidates = settings.candidates();
+        assert_eq!(candidates.len(), 12);
+        assert_eq!(candidates[0].to_string(), "9600 N1");
+        assert_eq!(
+            candidates[3],
+            LineParams {
+                baud_rate: 9600,
+                parity: Parity::Even,
+                stop_bits: StopBits::Two,
+            }
+        );
+        assert_eq!(candidates[6].baud_rate, 19200);
+    }
+}
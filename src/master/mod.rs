@@ -1,12 +1,644 @@
+pub mod builder;
+pub mod client;
+pub mod device;
+pub mod discovery;
+pub mod poll;
+pub mod scan;
+
+use crate::app_ui::show_value_format;
+use crate::modbus::decode::{comm_event_text, device_object_name};
+use crate::modbus::pdu::{FunctionCode, Request, Response};
+use crate::modbus::value::ValueFormat;
+use crate::mode::{OperatingMode, TaskStatus};
+use crate::serial::LineParams;
+use crate::tag::editor::{MapFile, show_tags};
+use crate::tag::{SharedTags, Tag, TagValue};
+use builder::RequestBuilder;
+use client::{MasterError, MasterRequest, MasterResponse};
+use device::DeviceInfo;
+use discovery::{DiscoveryResult, ParamDiscovery};
 use eframe::*;
+use poll::{PollDefinition, PollStatus, SharedPolls};
+use scan::{BusScan, ScanCommand, ScanEnd, ScanResult};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// 结果列表最多保留的条数
+const MAX_RESULTS: usize = 100;
+/// 新建轮询的默认周期
+const DEFAULT_POLL_PERIOD: Duration = Duration::from_millis(1000);
+/// 轮询表中最多显示的值的个数
+const MAX_VALUES_SHOWN: usize = 8;
+
+/// 主机页面中间区域显示的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum View {
+    #[default]
+    Results,
+    Polls,
+    Tags,
+    Scan,
+    Discovery,
+    Device,
+}
 
 #[derive(Debug, Default)]
-pub struct Master {}
+pub struct Master {
+    builder: RequestBuilder,
+    //构造请求失败的原因
+    build_error: Option<String>,
+    next_id: u64,
+    //等待发给后台任务的请求
+    pending: Vec<MasterRequest>,
+    results: Vec<MasterResponse>,
+    //在详情中显示的结果
+    selected: Option<u64>,
+    view: View,
+    //与后台任务共享的轮询列表
+    polls: SharedPolls,
+    poll_status: HashMap<u64, PollStatus>,
+    next_poll_id: u64,
+    //详情中寄存器按此类型解释
+    format: ValueFormat,
+    //与从机页面共享的标签列表
+    tags: SharedTags,
+    map_file: MapFile,
+    scan: BusScan,
+    discovery: ParamDiscovery,
+    device: DeviceInfo,
+    //参数识别中选择应用到串口的参数
+    line_params: Option<LineParams>,
+    //上一帧看到的工作模式和任务状态
+    engine: Option<(OperatingMode, TaskStatus)>,
+}
 
 impl Master {
+    pub fn new(tags: SharedTags) -> Self {
+        Self {
+            tags,
+            ..Default::default()
+        }
+    }
+
+    /// 设置请求使用的站号
+    pub fn set_unit(&mut self, unit: u8) {
+        self.builder.unit = unit;
+    }
+
     pub fn show(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::left("master_request")
+            .resizable(false)
+            .show(_ctx, |ui| {
+                if self.builder.show(ui) {
+                    match self.builder.build() {
+                        Ok(request) => {
+                            self.build_error = None;
+                            self.submit(request);
+                        }
+                        Err(err) => self.build_error = Some(err),
+                    }
+                }
+                if ui.button("添加为轮询").clicked() {
+                    self.add_poll();
+                }
+                if let Some(err) = &self.build_error {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                }
+            });
         egui::CentralPanel::default().show(_ctx, |ui| {
-            ui.label("Modbus 主机界面");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.view, View::Results, "请求结果");
+                ui.selectable_value(&mut self.view, View::Polls, "周期轮询");
+                ui.selectable_value(&mut self.view, View::Tags, "标签");
+                ui.selectable_value(&mut self.view, View::Scan, "总线扫描");
+                ui.selectable_value(&mut self.view, View::Discovery, "参数识别");
+                ui.selectable_value(&mut self.view, View::Device, "设备信息");
+            });
+            ui.separator();
+            match self.view {
+                View::Results => self.show_results_view(ui),
+                View::Polls => self.show_polls(ui),
+                View::Tags => self.show_tags(ui),
+                View::Scan => {
+                    if let Some(unit) = self.scan.show(ui, self.discovery.is_running()) {
+                        self.set_unit(unit);
+                    }
+                }
+                View::Discovery => {
+                    let found = self.scan.found_units();
+                    let busy = self.scan.is_running();
+                    if let Some(params) = self.discovery.show(ui, &found, busy) {
+                        self.line_params = Some(params);
+                    }
+                }
+                View::Device => {
+                    let found = self.scan.found_units();
+                    self.device.show(ui, &found);
+                    self.submit_device_request();
+                }
+            }
+        });
+    }
+
+    fn show_results_view(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("结果: {} 条", self.results.len()));
+            if ui.button("清除").clicked() {
+                self.results.clear();
+                self.selected = None;
+            }
+        });
+        ui.separator();
+        let height = ui.available_height() / 2.0;
+        egui::ScrollArea::vertical()
+            .id_salt("master_results")
+            .max_height(height)
+            .show(ui, |ui| self.show_results(ui));
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .id_salt("master_result_detail")
+            .show(ui, |ui| self.show_detail(ui));
+    }
+
+    /// 轮询表，修改直接写入共享列表，主机引擎在下一次调度时生效
+    fn show_polls(&mut self, ui: &mut egui::Ui) {
+        let mut polls = self.polls.lock().unwrap();
+        let mut removed = None;
+        egui::ScrollArea::both()
+            .id_salt("master_polls")
+            .show(ui, |ui| {
+                egui::Grid::new("master_polls_grid")
+                    .num_columns(10)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for title in [
+                            "启用",
+                            "站号",
+                            "功能码",
+                            "地址",
+                            "数量",
+                            "周期",
+                            "最近值",
+                            "更新",
+                            "错误数",
+                            "",
+                        ] {
+                            ui.strong(title);
+                        }
+                        ui.end_row();
+                        for poll in polls.iter_mut() {
+                            let status = self.poll_status.get(&poll.id);
+                            show_poll_row(ui, poll, status);
+                            if ui.button("删除").clicked() {
+                                removed = Some(poll.id);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                if polls.is_empty() {
+                    ui.label("在左侧选择读功能码后点击“添加为轮询”");
+                }
+            });
+        if let Some(id) = removed {
+            polls.retain(|poll| poll.id != id);
+            self.poll_status.remove(&id);
+        }
+    }
+
+    /// 标签表，值取自覆盖标签地址的轮询的最近结果
+    ///
+    /// 导入寄存器表后立即为新的标签添加轮询。
+    fn show_tags(&mut self, ui: &mut egui::Ui) {
+        if self.map_file.show(ui, &self.tags, |_| None).is_some() {
+            self.add_tag_polls();
+        }
+        if ui
+            .button("为标签添加轮询")
+            .on_hover_text("为没有被任何轮询覆盖的标签各添加一项轮询")
+            .clicked()
+        {
+            self.add_tag_polls();
+        }
+        let polls = self.polls.clone();
+        let tags = self.tags.clone();
+        show_tags(ui, "master_tags", &tags, |ui, tag| {
+            let polls = polls.lock().unwrap();
+            match tag_value(&polls, &self.poll_status, tag) {
+                Some(Ok(value)) => show_tag_value(ui, tag, &value),
+                Some(Err(err)) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                }
+                None => {
+                    ui.label("-");
+                }
+            }
+        });
+    }
+
+    fn add_tag_polls(&mut self) {
+        let tags = self.tags.lock().unwrap().clone();
+        for tag in tags {
+            let covered = self.polls.lock().unwrap().iter().any(|poll| {
+                poll.unit == tag.unit
+                    && poll.function == tag.read_function()
+                    && tag.is_covered_by(poll.address, poll.quantity)
+            });
+            if !covered {
+                log::info!("为标签 {} ({}) 添加轮询", tag.name, tag.location());
+                self.push_poll(tag.unit, tag.read_function(), tag.address, tag.quantity());
+            }
+        }
+    }
+
+    /// 用构造器当前的站号、功能码和地址范围新建一项轮询
+    fn add_poll(&mut self) {
+        let builder = &self.builder;
+        if !FunctionCode::ALL[..4].contains(&builder.function) {
+            self.build_error = Some("轮询只支持 01–04 读功能码".to_string());
+            return;
+        }
+        self.build_error = None;
+        self.push_poll(
+            builder.unit,
+            builder.function,
+            builder.address,
+            builder.quantity,
+        );
+        self.view = View::Polls;
+    }
+
+    fn push_poll(&mut self, unit: u8, function: FunctionCode, address: u16, quantity: u16) {
+        self.next_poll_id += 1;
+        let poll = PollDefinition {
+            id: self.next_poll_id,
+            unit,
+            function,
+            address,
+            quantity,
+            period: DEFAULT_POLL_PERIOD,
+            enabled: true,
+        };
+        log::info!("添加轮询 #{}: {:?}", poll.id, poll.request());
+        self.polls.lock().unwrap().push(poll);
+    }
+
+    fn show_results(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("master_results_grid")
+            .num_columns(5)
+            .spacing([20.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("#");
+                ui.strong("站号");
+                ui.strong("请求");
+                ui.strong("耗时");
+                ui.strong("结果");
+                ui.end_row();
+                for response in self.results.iter().rev() {
+                    let selected = self.selected == Some(response.id);
+                    if ui
+                        .selectable_label(selected, response.id.to_string())
+                        .clicked()
+                    {
+                        self.selected = Some(response.id);
+                    }
+                    ui.label(response.unit.to_string());
+                    ui.label(response.request.to_string());
+                    ui.label(format!("{} ms", response.elapsed.as_millis()));
+                    match &response.result {
+                        Ok(value) => {
+                            ui.label(value.to_string());
+                        }
+                        Err(err) => {
+                            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err.to_string());
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// 显示选中结果的详细内容：读到的每个地址的值，或者异常码
+    ///
+    /// 寄存器另外按选择的类型解释，多寄存器的值显示在第一个地址所在的行。
+    fn show_detail(&mut self, ui: &mut egui::Ui) {
+        let Some(response) = self
+            .selected
+            .and_then(|id| self.results.iter().find(|response| response.id == id))
+        else {
+            ui.label("点击结果的编号查看详情");
+            return;
+        };
+        ui.label(format!(
+            "#{} 站号 {} {}",
+            response.id, response.unit, response.request
+        ));
+        let values = match &response.result {
+            Err(MasterError::Exception(code)) => {
+                ui.colored_label(
+                    egui::Color32::from_rgb(220, 50, 50),
+                    format!("异常码 0x{:02X}: {}", code.code(), code),
+                );
+                return;
+            }
+            Err(err) => {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err.to_string());
+                return;
+            }
+            Ok(value) => value,
+        };
+        let start = response.request.read_address().unwrap_or_default() as usize;
+        if values.registers().is_some() {
+            ui.horizontal(|ui| show_value_format(ui, "master_detail", &mut self.format));
+        }
+        let format = self.format;
+        egui::Grid::new("master_detail_grid")
+            .num_columns(4)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if let Some(bits) = values.bits() {
+                    ui.strong("地址");
+                    ui.strong("值");
+                    ui.end_row();
+                    for (offset, bit) in bits.iter().enumerate() {
+                        ui.label((start + offset).to_string());
+                        ui.label(if *bit { "ON" } else { "OFF" });
+                        ui.end_row();
+                    }
+                } else if let Some(registers) = values.registers() {
+                    ui.strong("地址");
+                    ui.strong("十进制");
+                    ui.strong("十六进制");
+                    ui.strong(format.data_type.label());
+                    ui.end_row();
+                    let count = format.data_type.register_count();
+                    for (offset, value) in registers.iter().enumerate() {
+                        ui.label((start + offset).to_string());
+                        ui.label(value.to_string());
+                        ui.label(format!("0x{:04X}", value));
+                        match registers.get(offset..offset + count) {
+                            Some(span) if offset % count == 0 => match format.decode(span) {
+                                Ok(typed) => ui.label(typed.to_string()),
+                                Err(err) => {
+                                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err)
+                                }
+                            },
+                            _ => ui.label(""),
+                        };
+                        ui.end_row();
+                    }
+                } else if let Response::ReadDeviceIdentification(identification) = values {
+                    ui.strong("对象");
+                    ui.strong("名称");
+                    ui.strong("内容");
+                    ui.end_row();
+                    for (id, value) in &identification.objects {
+                        ui.label(format!("0x{:02X}", id));
+                        ui.label(device_object_name(*id));
+                        ui.label(String::from_utf8_lossy(value));
+                        ui.end_row();
+                    }
+                    if let Some(next) = identification.next_object {
+                        ui.label("");
+                        ui.weak(format!("后续对象从 0x{:02X} 开始", next));
+                        ui.end_row();
+                    }
+                } else if let Response::GetCommEventLog(log) = values {
+                    ui.label(format!("状态 0x{:04X}", log.status));
+                    ui.label(format!("事件计数 {}", log.event_count));
+                    ui.label(format!("报文计数 {}", log.message_count));
+                    ui.end_row();
+                    ui.strong("序号");
+                    ui.strong("事件");
+                    ui.strong("含义");
+                    ui.end_row();
+                    for (index, event) in log.events.iter().enumerate() {
+                        ui.label(index.to_string());
+                        ui.label(format!("0x{:02X}", event));
+                        ui.label(comm_event_text(*event));
+                        ui.end_row();
+                    }
+                } else if let Response::Diagnostics(code, data) = values
+                    && !code.is_counter()
+                {
+                    ui.strong(code.label());
+                    ui.end_row();
+                    for word in data {
+                        ui.label(word.to_string());
+                        ui.label(format!("0x{:04X}", word));
+                        ui.end_row();
+                    }
+                } else {
+                    ui.label(values.to_string());
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn submit(&mut self, request: Request) {
+        self.submit_to(self.builder.unit, request);
+    }
+
+    fn submit_to(&mut self, unit: u8, request: Request) -> u64 {
+        self.next_id += 1;
+        self.pending.push(MasterRequest {
+            id: self.next_id,
+            unit,
+            request,
+        });
+        self.next_id
+    }
+
+    /// 发出设备信息视图准备好的读设备标识请求
+    fn submit_device_request(&mut self) {
+        if let Some((unit, request)) = self.device.take_request() {
+            let id = self.submit_to(unit, request);
+            self.device.sent(id);
+        }
+    }
+
+    /// 取出等待发送的请求，由应用转交给后台任务
+    pub fn take_requests(&mut self) -> Vec<MasterRequest> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// 取出等待发送的扫描命令
+    pub fn take_scan_command(&mut self) -> Option<ScanCommand> {
+        self.scan
+            .take_command()
+            .or_else(|| self.discovery.take_command())
+    }
+
+    /// 取出参数识别中选择应用到串口的参数
+    pub fn take_line_params(&mut self) -> Option<LineParams> {
+        self.line_params.take()
+    }
+
+    pub fn handle_scanned(&mut self, result: ScanResult) {
+        self.scan.handle_result(result);
+    }
+
+    pub fn handle_discovered(&mut self, result: DiscoveryResult) {
+        self.discovery.handle_result(result);
+    }
+
+    /// 扫描和识别同一时间只有一个在进行，结束事件交给正在进行的那个
+    pub fn handle_scan_finished(&mut self, end: ScanEnd) {
+        self.scan.finish(end.clone());
+        self.discovery.finish(end);
+    }
+
+    /// 后台任务停止或离开主机模式，进行中的扫描或识别随之结束
+    pub fn interrupt_scan(&mut self) {
+        self.handle_scan_finished(ScanEnd::Stopped);
+    }
+
+    /// 每帧报告工作模式和任务状态，变为停止、出错或非主机模式时中断扫描
+    ///
+    /// 只在变化时中断：非主机模式下开始的扫描由任务回复失败原因，不能抢先标记为已停止。
+    pub fn update_engine(&mut self, mode: OperatingMode, status: TaskStatus) {
+        let stopped = matches!(status, TaskStatus::Stopped | TaskStatus::Error(_))
+            || mode != OperatingMode::Master;
+        let current = Some((mode, status));
+        if self.engine != current {
+            self.engine = current;
+            if stopped {
+                self.interrupt_scan();
+            }
+        }
+    }
+
+    /// 与后台任务共享的轮询列表
+    pub fn polls(&self) -> SharedPolls {
+        self.polls.clone()
+    }
+
+    /// 替换整个轮询列表，例如打开工作区时
+    pub fn set_polls(&mut self, polls: Vec<PollDefinition>) {
+        self.next_poll_id = polls.iter().map(|poll| poll.id).max().unwrap_or_default();
+        self.poll_status.clear();
+        *self.polls.lock().unwrap() = polls;
+    }
+
+    pub fn handle_poll(&mut self, poll: u64, response: &MasterResponse) {
+        self.poll_status.entry(poll).or_default().update(response);
+    }
+
+    pub fn handle_response(&mut self, response: MasterResponse) {
+        if self.device.handle_response(&response) {
+            // 设备分多帧给出标识对象时立即继续读取
+            self.submit_device_request();
+        }
+        self.selected = Some(response.id);
+        self.results.push(response);
+        if self.results.len() > MAX_RESULTS {
+            self.results.remove(0);
+        }
+    }
+}
+
+fn show_poll_row(ui: &mut egui::Ui, poll: &mut PollDefinition, status: Option<&PollStatus>) {
+    ui.checkbox(&mut poll.enabled, "");
+    ui.add(egui::DragValue::new(&mut poll.unit));
+    egui::ComboBox::from_id_salt(("poll_function", poll.id))
+        .selected_text(poll.function.to_string())
+        .show_ui(ui, |ui| {
+            for function in &FunctionCode::ALL[..4] {
+                ui.selectable_value(&mut poll.function, *function, function.to_string());
+            }
         });
+    ui.add(egui::DragValue::new(&mut poll.address));
+    let max_quantity = match poll.function {
+        FunctionCode::ReadCoils | FunctionCode::ReadDiscreteInputs => 2000,
+        _ => 125,
+    };
+    ui.add(egui::DragValue::new(&mut poll.quantity).range(1..=max_quantity));
+    let mut period_ms = poll.period.as_millis() as u64;
+    if ui
+        .add(
+            egui::DragValue::new(&mut period_ms)
+                .range(poll::MIN_POLL_PERIOD.as_millis() as u64..=3_600_000)
+                .speed(10)
+                .suffix("ms"),
+        )
+        .changed()
+    {
+        poll.period = Duration::from_millis(period_ms);
+    }
+
+    let status = status.cloned().unwrap_or_default();
+    match (&status.last_error, &status.last_value) {
+        (Some(err), _) => {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err.to_string());
+        }
+        (None, Some(value)) => {
+            ui.label(value_summary(value));
+        }
+        (None, None) => {
+            ui.label("-");
+        }
+    }
+    match status.last_update {
+        Some(time) => ui.label(format!("{:.1} s 前", time.elapsed().as_secs_f32())),
+        None => ui.label("-"),
+    };
+    ui.label(format!("{} / {}", status.error_count, status.request_count));
+}
+
+/// 从覆盖标签地址的轮询中取出最近的值
+fn tag_value(
+    polls: &[PollDefinition],
+    status: &HashMap<u64, PollStatus>,
+    tag: &Tag,
+) -> Option<Result<TagValue, String>> {
+    polls
+        .iter()
+        .filter(|poll| poll.unit == tag.unit && poll.function == tag.read_function())
+        .filter_map(|poll| {
+            let status = status.get(&poll.id)?;
+            let value = tag.extract(poll.address, status.last_value.as_ref()?)?;
+            Some((status.last_update, value))
+        })
+        .max_by_key(|(update, _)| *update)
+        .map(|(_, value)| value)
+}
+
+/// 工程值和单位，超出范围时标红
+fn show_tag_value(ui: &mut egui::Ui, tag: &Tag, value: &TagValue) {
+    let text = format!("{} {}", value, tag.unit_text);
+    if value.out_of_range {
+        ui.colored_label(egui::Color32::from_rgb(220, 50, 50), text)
+            .on_hover_text("超出范围");
+    } else {
+        ui.label(text);
+    }
+}
+
+/// 读应答的前几个值
+fn value_summary(value: &Response) -> String {
+    let mut values: Vec<String> = if let Some(bits) = value.bits() {
+        bits.iter()
+            .take(MAX_VALUES_SHOWN)
+            .map(|bit| (*bit as u8).to_string())
+            .collect()
+    } else if let Some(registers) = value.registers() {
+        registers
+            .iter()
+            .take(MAX_VALUES_SHOWN)
+            .map(|register| register.to_string())
+            .collect()
+    } else {
+        return value.to_string();
+    };
+    let total = value
+        .bits()
+        .map(<[bool]>::len)
+        .or(value.registers().map(<[u16]>::len))
+        .unwrap_or_default();
+    if total > MAX_VALUES_SHOWN {
+        values.push("…".to_string());
     }
+    values.join(", ")
 }
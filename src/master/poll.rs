@@ -0,0 +1,184 @@
+//! 周期轮询
+//!
+//! 轮询列表由主机页面编辑，与后台任务共享；主机引擎按各自的周期执行其中启用的项。
+//! 每项的下一次执行时间按周期累加，不受执行耗时影响，不会逐渐漂移。
+
+use super::client::{MasterError, MasterResponse};
+use crate::modbus::pdu::{FunctionCode, Request, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 轮询周期的下限，避免把总线占满
+pub const MIN_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// 主机页面和后台任务共享的轮询列表
+pub type SharedPolls = Arc<Mutex<Vec<PollDefinition>>>;
+
+/// 一项轮询
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollDefinition {
+    pub id: u64,
+    pub unit: u8,
+    /// 只能是读功能码（01–04）
+    pub function: FunctionCode,
+    pub address: u16,
+    pub quantity: u16,
+    pub period: Duration,
+    pub enabled: bool,
+}
+
+impl PollDefinition {
+    /// 轮询发出的请求，不是读功能码时返回 `None`
+    pub fn request(&self) -> Option<Request> {
+        let request = match self.function {
+            FunctionCode::ReadCoils => Request::ReadCoils(self.address, self.quantity),
+            FunctionCode::ReadDiscreteInputs => {
+                Request::ReadDiscreteInputs(self.address, self.quantity)
+            }
+            FunctionCode::ReadHoldingRegisters => {
+                Request::ReadHoldingRegisters(self.address, self.quantity)
+            }
+            FunctionCode::ReadInputRegisters => {
+                Request::ReadInputRegisters(self.address, self.quantity)
+            }
+            _ => return None,
+        };
+        Some(request)
+    }
+}
+
+/// 一项轮询的最近结果，由主机页面根据事件更新
+#[derive(Debug, Clone, Default)]
+pub struct PollStatus {
+    pub last_value: Option<Response>,
+    pub last_update: Option<Instant>,
+    pub last_error: Option<MasterError>,
+    pub request_count: u64,
+    pub error_count: u64,
+}
+
+impl PollStatus {
+    pub fn update(&mut self, response: &MasterResponse) {
+        self.request_count += 1;
+        match &response.result {
+            Ok(value) => {
+                self.last_value = Some(value.clone());
+                self.last_update = Some(Instant::now());
+                self.last_error = None;
+            }
+            Err(err) => {
+                self.error_count += 1;
+                self.last_error = Some(err.clone());
+            }
+        }
+    }
+}
+
+/// 记录每项启用的轮询下一次的执行时间
+#[derive(Debug, Default)]
+pub struct PollScheduler {
+    // id -> (周期, 下一次执行时间)
+    due: HashMap<u64, (Duration, Instant)>,
+}
+
+impl PollScheduler {
+    /// 按最新的轮询列表更新计划：新启用的项立即执行，周期变化的项重新计时，
+    /// 删除或停用的项不再执行
+    pub fn sync(&mut self, polls: &[PollDefinition], now: Instant) {
+        self.due.retain(|id, _| {
+            polls
+                .iter()
+                .any(|poll| poll.id == *id && poll.enabled && poll.request().is_some())
+        });
+        for poll in polls {
+            if !poll.enabled || poll.request().is_none() {
+                continue;
+            }
+            let period = poll.period.max(MIN_POLL_PERIOD);
+            let entry = self.due.entry(poll.id).or_insert((period, now));
+            if entry.0 != period {
+                *entry = (period, now);
+            }
+        }
+    }
+
+    /// 最早的执行时间
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.values().map(|(_, next)| *next).min()
+    }
+
+    /// 取出一项已经到期的轮询，并把它的下一次执行时间推后一个周期
+    ///
+    /// 总线太忙错过的周期直接跳过，不会在之后集中补发。
+    pub fn take_due(&mut self, now: Instant) -> Option<u64> {
+        let (&id, _) = self
+            .due
+            .iter()
+            .filter(|(_, (_, next))| *next <= now)
+            .min_by_key(|(_, (_, next))| *next)?;
+        let (period, next) = self.due.get_mut(&id)?;
+        while *next <= now {
+            *next += *period;
+        }
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(id: u64, period_ms: u64) -> PollDefinition {
+        PollDefinition {
+            id,
+            unit: 1,
+            function: FunctionCode::ReadHoldingRegisters,
+            address: 0,
+            quantity: 1,
+            period: Duration::from_millis(period_ms),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_schedule_without_drift() {
+        let start = Instant::now();
+        let mut scheduler = PollScheduler::default();
+        scheduler.sync(&[poll(1, 100), poll(2, 250)], start);
+
+        // 两项都立即执行一次
+        assert!(scheduler.take_due(start).is_some());
+        assert!(scheduler.take_due(start).is_some());
+        assert_eq!(scheduler.take_due(start), None);
+
+        // 执行晚了 30ms，下一次仍然按原来的节拍
+        let late = start + Duration::from_millis(130);
+        assert_eq!(scheduler.take_due(late), Some(1));
+        assert_eq!(
+            scheduler.next_due(),
+            Some(start + Duration::from_millis(200))
+        );
+
+        // 错过的周期被跳过
+        let later = start + Duration::from_millis(560);
+        assert!(scheduler.take_due(later).is_some());
+        assert!(scheduler.take_due(later).is_some());
+        assert_eq!(scheduler.take_due(later), None);
+        assert_eq!(
+            scheduler.next_due(),
+            Some(start + Duration::from_millis(600))
+        );
+    }
+
+    #[test]
+    fn test_sync_drops_disabled() {
+        let now = Instant::now();
+        let mut scheduler = PollScheduler::default();
+        let mut polls = vec![poll(1, 100)];
+        scheduler.sync(&polls, now);
+        polls[0].enabled = false;
+        scheduler.sync(&polls, now);
+        assert_eq!(scheduler.next_due(), None);
+    }
+}
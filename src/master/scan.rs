@@ -0,0 +1,409 @@
+//! 总线扫描
+//!
+//! 调试未知的 RS-485 网段时，依次向一段站号发送同一个探测请求，记录哪些站号有应答、
+//! 应答延迟以及是否为异常应答。扫描由主机引擎执行，期间暂停轮询，
+//! 界面发来的其他请求直接回复“正在扫描总线”。
+
+use super::client::MasterError;
+use super::discovery::DiscoverySettings;
+use crate::modbus::pdu::{ReadDeviceIdCode, Request, Response};
+use eframe::*;
+use std::fmt;
+use std::time::Duration;
+
+/// 可以扫描的站号范围，0 是广播地址，248–255 保留
+pub const FIRST_UNIT: u8 = 1;
+pub const LAST_UNIT: u8 = 247;
+/// 每个站号最多的重试次数
+pub const MAX_RETRIES: u8 = 5;
+
+/// 探测请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanProbe {
+    /// 03 读一个保持寄存器
+    #[default]
+    ReadHoldingRegister,
+    /// 43/14 读基本设备标识
+    DeviceIdentification,
+}
+
+impl ScanProbe {
+    pub const ALL: [ScanProbe; 2] = [
+        ScanProbe::ReadHoldingRegister,
+        ScanProbe::DeviceIdentification,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ScanProbe::ReadHoldingRegister => "03 读一个保持寄存器",
+            ScanProbe::DeviceIdentification => "43/14 读设备标识",
+        }
+    }
+
+    /// 探测请求，`address` 是读保持寄存器的地址
+    pub fn request(self, address: u16) -> Request {
+        match self {
+            ScanProbe::ReadHoldingRegister => Request::ReadHoldingRegisters(address, 1),
+            ScanProbe::DeviceIdentification => {
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Basic, 0)
+            }
+        }
+    }
+}
+
+/// 一次扫描的设置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanSettings {
+    pub first: u8,
+    pub last: u8,
+    pub probe: ScanProbe,
+    /// 读保持寄存器探测的地址
+    pub address: u16,
+    /// 每次探测等待应答的时间，代替链路设置中的超时
+    pub timeout: Duration,
+    /// 没有应答时的重试次数，异常应答不重试
+    pub retries: u8,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            first: FIRST_UNIT,
+            last: LAST_UNIT,
+            probe: ScanProbe::default(),
+            address: 0,
+            timeout: Duration::from_millis(100),
+            retries: 1,
+        }
+    }
+}
+
+impl ScanSettings {
+    pub fn units(&self) -> std::ops::RangeInclusive<u8> {
+        self.first.max(FIRST_UNIT)..=self.last.min(LAST_UNIT)
+    }
+}
+
+/// 界面发给主机引擎的扫描命令，总线扫描和参数识别同一时间只能进行一个
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanCommand {
+    Start(ScanSettings),
+    Discover(DiscoverySettings),
+    Stop,
+}
+
+/// 一个站号的扫描结果
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub unit: u8,
+    /// 最后一次探测的结果
+    pub result: Result<Response, MasterError>,
+    /// 最后一次探测的耗时，有应答时即应答延迟
+    pub elapsed: Duration,
+    pub attempts: u8,
+}
+
+impl ScanResult {
+    /// 正常应答和异常应答都说明该站号上有设备
+    pub fn responded(&self) -> bool {
+        matches!(self.result, Ok(_) | Err(MasterError::Exception(_)))
+    }
+}
+
+/// 扫描结束的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanEnd {
+    Completed,
+    Stopped,
+    Failed(MasterError),
+}
+
+impl fmt::Display for ScanEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanEnd::Completed => write!(f, "扫描完成"),
+            ScanEnd::Stopped => write!(f, "扫描已停止"),
+            ScanEnd::Failed(err) => write!(f, "扫描失败: {}", err),
+        }
+    }
+}
+
+/// 主机页面的总线扫描视图
+#[derive(Debug)]
+pub struct BusScan {
+    settings: ScanSettings,
+    results: Vec<ScanResult>,
+    running: bool,
+    //等待发给后台任务的命令
+    command: Option<ScanCommand>,
+    end: Option<ScanEnd>,
+    //只列出有应答的站号
+    responding_only: bool,
+}
+
+impl Default for BusScan {
+    fn default() -> Self {
+        Self {
+            settings: ScanSettings::default(),
+            results: Vec::new(),
+            running: false,
+            command: None,
+            end: None,
+            responding_only: true,
+        }
+    }
+}
+
+impl BusScan {
+    /// 显示扫描设置和结果，返回点击“使用”的站号
+    ///
+    /// `busy` 表示参数识别正在进行，此时不能开始扫描。
+    pub fn show(&mut self, ui: &mut egui::Ui, busy: bool) -> Option<u8> {
+        ui.add_enabled_ui(!self.running, |ui| self.show_settings(ui));
+        ui.horizontal(|ui| {
+            if self.running {
+                if ui.button("停止").clicked() {
+                    self.command = Some(ScanCommand::Stop);
+                }
+            } else if ui
+                .add_enabled(!busy, egui::Button::new("开始扫描"))
+                .clicked()
+            {
+                log::info!("开始扫描总线: {:?}", self.settings);
+                self.results.clear();
+                self.end = None;
+                self.running = true;
+                self.command = Some(ScanCommand::Start(self.settings.clone()));
+            }
+            ui.checkbox(&mut self.responding_only, "只显示有应答的站号");
+        });
+        self.show_progress(ui);
+        ui.separator();
+        self.show_results(ui)
+    }
+
+    fn show_settings(&mut self, ui: &mut egui::Ui) {
+        let settings = &mut self.settings;
+        egui::Grid::new("bus_scan_settings")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("站号范围:");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut settings.first).range(FIRST_UNIT..=settings.last),
+                    );
+                    ui.label("到");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.last).range(settings.first..=LAST_UNIT),
+                    );
+                });
+                ui.end_row();
+
+                ui.label("探测请求:");
+                egui::ComboBox::from_id_salt("bus_scan_probe")
+                    .selected_text(settings.probe.label())
+                    .show_ui(ui, |ui| {
+                        for probe in ScanProbe::ALL {
+                            ui.selectable_value(&mut settings.probe, probe, probe.label());
+                        }
+                    });
+                ui.end_row();
+
+                if settings.probe == ScanProbe::ReadHoldingRegister {
+                    ui.label("寄存器地址:");
+                    ui.add(egui::DragValue::new(&mut settings.address));
+                    ui.end_row();
+                }
+
+                ui.label("超时:");
+                let mut timeout_ms = settings.timeout.as_millis() as u64;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut timeout_ms)
+                            .range(10..=10_000)
+                            .suffix("ms"),
+                    )
+                    .changed()
+                {
+                    settings.timeout = Duration::from_millis(timeout_ms);
+                }
+                ui.end_row();
+
+                ui.label("重试次数:");
+                ui.add(egui::DragValue::new(&mut settings.retries).range(0..=MAX_RETRIES));
+                ui.end_row();
+            });
+    }
+
+    fn show_progress(&self, ui: &mut egui::Ui) {
+        let found = self
+            .results
+            .iter()
+            .filter(|result| result.responded())
+            .count();
+        if self.running {
+            let units = self.settings.units();
+            let total = units.end().saturating_sub(*units.start()) as usize + 1;
+            let current = self
+                .results
+                .last()
+                .map_or(*units.start(), |result| result.unit.saturating_add(1));
+            ui.add(
+                egui::ProgressBar::new(self.results.len() as f32 / total as f32)
+                    .text(format!("正在探测站号 {}，已发现 {} 个", current, found)),
+            );
+        } else if let Some(end) = &self.end {
+            let text = format!("{}，发现 {} 个站号", end, found);
+            match end {
+                ScanEnd::Failed(_) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), text);
+                }
+                _ => {
+                    ui.label(text);
+                }
+            }
+        }
+    }
+
+    fn show_results(&self, ui: &mut egui::Ui) -> Option<u8> {
+        let mut chosen = None;
+        egui::ScrollArea::vertical()
+            .id_salt("bus_scan_results")
+            .show(ui, |ui| {
+                egui::Grid::new("bus_scan_results_grid")
+                    .num_columns(5)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for title in ["站号", "结果", "延迟", "次数", ""] {
+                            ui.strong(title);
+                        }
+                        ui.end_row();
+                        let results = self
+                            .results
+                            .iter()
+                            .filter(|result| !self.responding_only || result.responded());
+                        for result in results {
+                            ui.label(result.unit.to_string());
+                            match &result.result {
+                                Ok(value) => {
+                                    ui.colored_label(
+                                        egui::Color32::from_rgb(40, 160, 40),
+                                        format!("应答: {}", value),
+                                    );
+                                }
+                                Err(MasterError::Exception(code)) => {
+                                    ui.colored_label(
+                                        egui::Color32::from_rgb(220, 160, 0),
+                                        format!("异常应答: {}", code),
+                                    );
+                                }
+                                Err(err) => {
+                                    ui.weak(err.to_string());
+                                }
+                            }
+                            if result.responded() {
+                                ui.label(format!("{} ms", result.elapsed.as_millis()));
+                            } else {
+                                ui.label("-");
+                            }
+                            ui.label(result.attempts.to_string());
+                            if result.responded()
+                                && ui
+                                    .button("使用")
+                                    .on_hover_text("在请求构造器中使用这个站号")
+                                    .clicked()
+                            {
+                                chosen = Some(result.unit);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        chosen
+    }
+
+    /// 取出等待发送的扫描命令，由应用转交给后台任务
+    pub fn take_command(&mut self) -> Option<ScanCommand> {
+        self.command.take()
+    }
+
+    pub fn handle_result(&mut self, result: ScanResult) {
+        self.results.push(result);
+    }
+
+    /// 扫描结束，没有在进行时忽略
+    pub fn finish(&mut self, end: ScanEnd) {
+        if self.running {
+            log::info!("{}", end);
+            self.running = false;
+            self.end = Some(end);
+        }
+    }
+
+    /// 已发现的站号
+    pub fn found_units(&self) -> Vec<u8> {
+        self.results
+            .iter()
+            .filter(|result| result.responded())
+            .map(|result| result.unit)
+            .collect()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::pdu::ExceptionCode;
+    use crate::mode::{OperatingMode, TaskStatus};
+
+    #[test]
+    fn test_responded() {
+        let result = |result| ScanResult {
+            unit: 1,
+            result,
+            elapsed: Duration::ZERO,
+            attempts: 1,
+        };
+        assert!(result(Ok(Response::ReadHoldingRegisters(vec![0]))).responded());
+        assert!(
+            result(Err(MasterError::Exception(
+                ExceptionCode::IllegalDataAddress
+            )))
+            .responded()
+        );
+        assert!(!result(Err(MasterError::Timeout)).responded());
+        let settings = ScanSettings {
+            first: 0,
+            last: 255,
+            ..Default::default()
+        };
+        assert_eq!(settings.units(), 1..=247);
+    }
+
+    #[test]
+    fn test_failure_in_idle_mode() {
+        let mut master = crate::master::Master::default();
+        master.update_engine(OperatingMode::Idle, TaskStatus::Running);
+        master.scan.running = true;
+        // 模式和状态没有变化，扫描保持进行，等待任务回复失败原因
+        master.update_engine(OperatingMode::Idle, TaskStatus::Running);
+        assert!(master.scan.is_running());
+        master.handle_scan_finished(ScanEnd::Failed(MasterError::NotMaster));
+        assert!(matches!(
+            master.scan.end,
+            Some(ScanEnd::Failed(MasterError::NotMaster))
+        ));
+
+        // 扫描进行中任务停止时中断
+        master.update_engine(OperatingMode::Master, TaskStatus::Running);
+        master.scan.running = true;
+        master.update_engine(OperatingMode::Master, TaskStatus::Stopped);
+        assert!(matches!(master.scan.end, Some(ScanEnd::Stopped)));
+    }
+}
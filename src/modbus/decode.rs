@@ -0,0 +1,786 @@
+//! 报文解码
+//!
+//! 把线路上的一帧（RTU、ASCII 或 Modbus TCP）解码成树形结构：
+//! 站号、功能码、地址、数量、数据、异常码和校验码，用于报文页面和测试。
+//! 应答的含义取决于对应的请求，解码应答时可以给出配对的请求以显示数据的地址。
+
+use super::frame::{FrameKind, Framing, crc16, lrc};
+use super::pdu::{ExceptionCode, FunctionCode};
+use std::fmt;
+
+/// 解码树的一个节点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub text: String,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn leaf(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn branch(text: impl Into<String>, children: Vec<Node>) -> Self {
+        Self {
+            text: text.into(),
+            children,
+        }
+    }
+}
+
+/// 帧的校验码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Crc {
+        expected: u16,
+        actual: u16,
+    },
+    Lrc {
+        expected: u8,
+        actual: u8,
+    },
+    /// Modbus TCP 没有校验码
+    None,
+}
+
+impl Check {
+    pub fn is_valid(self) -> bool {
+        match self {
+            Check::Crc { expected, actual } => expected == actual,
+            Check::Lrc { expected, actual } => expected == actual,
+            Check::None => true,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = if self.is_valid() { "正确" } else { "错误" };
+        match self {
+            Check::Crc { expected, actual } if expected == actual => {
+                write!(f, "CRC 0x{:04X} {}", actual, result)
+            }
+            Check::Crc { expected, actual } => write!(
+                f,
+                "CRC {}: 应为 0x{:04X}, 实际 0x{:04X}",
+                result, expected, actual
+            ),
+            Check::Lrc { expected, actual } if expected == actual => {
+                write!(f, "LRC 0x{:02X} {}", actual, result)
+            }
+            Check::Lrc { expected, actual } => write!(
+                f,
+                "LRC {}: 应为 0x{:02X}, 实际 0x{:02X}",
+                result, expected, actual
+            ),
+            Check::None => f.write_str("无校验码"),
+        }
+    }
+}
+
+/// 去掉帧格式之后的报文
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adu {
+    /// MBAP 报文头中的事务号，RTU 和 ASCII 没有
+    pub transaction: Option<u16>,
+    pub unit: u8,
+    pub pdu: Vec<u8>,
+    pub check: Check,
+}
+
+impl Adu {
+    /// 从线路上的字节中取出报文，校验码错误时也照常取出
+    pub fn parse(bytes: &[u8], framing: Framing) -> Result<Adu, String> {
+        match framing {
+            Framing::Rtu => {
+                if bytes.len() < 4 {
+                    return Err(format!("帧长度 {} 太短", bytes.len()));
+                }
+                let (adu, tail) = bytes.split_at(bytes.len() - 2);
+                Ok(Adu {
+                    transaction: None,
+                    unit: adu[0],
+                    pdu: adu[1..].to_vec(),
+                    check: Check::Crc {
+                        expected: crc16(adu),
+                        actual: u16::from_le_bytes([tail[0], tail[1]]),
+                    },
+                })
+            }
+            Framing::Ascii => {
+                let text = bytes.strip_prefix(b":").unwrap_or(bytes);
+                let text = text.strip_suffix(b"\r\n").unwrap_or(text);
+                // 多字节的 UTF-8 字符会使下面按两个字节切片时落在字符中间
+                if !text.is_ascii() {
+                    return Err("包含非 ASCII 字符".to_string());
+                }
+                let text =
+                    std::str::from_utf8(text).map_err(|_| "包含非 ASCII 字符".to_string())?;
+                if !text.len().is_multiple_of(2) {
+                    return Err("十六进制字符个数为奇数".to_string());
+                }
+                let mut adu = (0..text.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(&text[index..index + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| "包含非十六进制字符".to_string())?;
+                if adu.len() < 3 {
+                    return Err(format!("帧长度 {} 太短", adu.len()));
+                }
+                let actual = adu.pop().unwrap_or_default();
+                Ok(Adu {
+                    transaction: None,
+                    unit: adu[0],
+                    check: Check::Lrc {
+                        expected: lrc(&adu),
+                        actual,
+                    },
+                    pdu: adu[1..].to_vec(),
+                })
+            }
+            Framing::Tcp => {
+                if bytes.len() < 8 {
+                    return Err(format!("报文长度 {} 太短", bytes.len()));
+                }
+                Ok(Adu {
+                    transaction: Some(u16::from_be_bytes([bytes[0], bytes[1]])),
+                    unit: bytes[6],
+                    pdu: bytes[7..].to_vec(),
+                    check: Check::None,
+                })
+            }
+        }
+    }
+
+    /// 功能码，异常应答带有最高位
+    pub fn function(&self) -> u8 {
+        self.pdu[0]
+    }
+
+    pub fn is_exception(&self) -> bool {
+        self.function() & 0x80 != 0
+    }
+
+    /// 本报文是否是 `request` 的应答：站号和功能码相同，Modbus TCP 还要求事务号相同
+    pub fn is_response_to(&self, request: &Adu) -> bool {
+        self.unit == request.unit
+            && self.function() & 0x7F == request.function()
+            && self.transaction == request.transaction
+    }
+}
+
+/// 一帧的解码结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// 一行摘要，例如 `请求 站号 1 读保持寄存器 地址 0 数量 10`
+    pub summary: String,
+    pub tree: Vec<Node>,
+}
+
+/// 解码一帧，`request` 是应答对应的请求，用于显示读到的数据所在的地址
+pub fn decode(bytes: &[u8], framing: Framing, kind: FrameKind, request: Option<&Adu>) -> Decoded {
+    let adu = match Adu::parse(bytes, framing) {
+        Ok(adu) => adu,
+        Err(err) => {
+            return Decoded {
+                summary: format!("无法解码: {}", err),
+                tree: vec![Node::leaf(format!("帧格式 {}", framing)), Node::leaf(err)],
+            };
+        }
+    };
+
+    let mut tree = vec![Node::leaf(format!("帧格式 {}", framing))];
+    if framing == Framing::Tcp {
+        let field = |index: usize| u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        tree.push(Node::branch(
+            "MBAP 报文头",
+            vec![
+                Node::leaf(format!("事务号 {}", field(0))),
+                Node::leaf(format!("协议号 {}", field(2))),
+                Node::leaf(format!("长度 {}", field(4))),
+            ],
+        ));
+    }
+    let unit = match adu.unit {
+        0 => "站号 0 (广播)".to_string(),
+        unit => format!("站号 {}", unit),
+    };
+    tree.push(Node::leaf(unit.clone()));
+
+    let function = adu.function();
+    let code = function & 0x7F;
+    let name = function_name(code);
+    let (summary, fields) = if adu.is_exception() {
+        tree.push(Node::leaf(format!(
+            "功能码 0x{:02X} {} (异常)",
+            function, name
+        )));
+        let mut reader = Reader::new(&adu.pdu[1..]);
+        let exception = reader.u8().map(ExceptionCode::from_code);
+        let summary = match exception {
+            Some(exception) => format!("异常 {} {}: {}", unit, name, exception),
+            None => format!("异常 {} {}", unit, name),
+        };
+        let mut fields = exception
+            .map(|exception| vec![Node::leaf(format!("异常码 {}", exception))])
+            .unwrap_or_else(|| vec![Node::leaf("帧在此处截断")]);
+        fields.extend(reader.rest_node());
+        (summary, fields)
+    } else {
+        tree.push(Node::leaf(format!("功能码 0x{:02X} {}", function, name)));
+        let mut reader = Reader::new(&adu.pdu[1..]);
+        let mut fields = Vec::new();
+        let detail = match kind {
+            FrameKind::Request => decode_request(code, &mut reader, &mut fields),
+            FrameKind::Response => {
+                let request = request.filter(|request| adu.is_response_to(request));
+                decode_response(code, request, &mut reader, &mut fields)
+            }
+        };
+        let detail = match detail {
+            Some(detail) => detail,
+            None => {
+                fields.push(Node::leaf("帧在此处截断"));
+                "(不完整)".to_string()
+            }
+        };
+        fields.extend(reader.rest_node());
+        let summary = format!("{} {} {} {}", kind.label(), unit, name, detail);
+        (summary.trim_end().to_string(), fields)
+    };
+    tree.extend(fields);
+    if adu.check != Check::None {
+        tree.push(Node::leaf(adu.check.to_string()));
+    }
+    let summary = if adu.check.is_valid() {
+        summary
+    } else {
+        format!("{} [{}]", summary, adu.check)
+    };
+    Decoded { summary, tree }
+}
+
+/// 功能码的名称，包括主机引擎不支持的功能码
+pub fn function_name(code: u8) -> &'static str {
+    if let Some(function) = FunctionCode::ALL
+        .into_iter()
+        .find(|function| function.code() == code)
+    {
+        return function.label();
+    }
+    match code {
+        0x07 => "读异常状态",
+        0x08 => "诊断",
+        0x0B => "读通信事件计数",
+        0x0C => "读通信事件记录",
+        0x11 => "报告从机 ID",
+        0x14 => "读文件记录",
+        0x15 => "写文件记录",
+        0x18 => "读 FIFO 队列",
+        0x2B => "封装接口传输",
+        _ => "未知功能码",
+    }
+}
+
+/// 诊断功能码（0x08）的子功能名称
+pub fn diagnostic_name(sub_function: u16) -> &'static str {
+    match sub_function {
+        0x00 => "返回询问数据",
+        0x01 => "重启通信",
+        0x02 => "返回诊断寄存器",
+        0x03 => "修改 ASCII 结束符",
+        0x04 => "强制只听模式",
+        0x0A => "清除计数器和诊断寄存器",
+        0x0B => "返回总线报文计数",
+        0x0C => "返回总线通信错误计数",
+        0x0D => "返回总线异常计数",
+        0x0E => "返回从机报文计数",
+        0x0F => "返回从机无应答计数",
+        0x10 => "返回从机 NAK 计数",
+        0x11 => "返回从机忙计数",
+        0x12 => "返回总线字符超限计数",
+        0x14 => "清除超限计数",
+        _ => "未知子功能",
+    }
+}
+
+/// 通信事件记录（0x0C）中一个事件字节的含义
+pub fn comm_event_text(event: u8) -> String {
+    let flags = |names: &[(u8, &'static str)]| -> Vec<&'static str> {
+        names
+            .iter()
+            .filter(|(bit, _)| event & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    };
+    let (kind, details) = match event {
+        0x00 => return "通信重启".to_string(),
+        0x04 => return "进入只听模式".to_string(),
+        0x80.. => (
+            "接收",
+            flags(&[
+                (0x02, "通信错误"),
+                (0x10, "字符超限"),
+                (0x20, "只听模式"),
+                (0x40, "广播"),
+            ]),
+        ),
+        0x40..=0x7F => (
+            "发送",
+            flags(&[
+                (0x01, "读异常"),
+                (0x02, "从机故障异常"),
+                (0x04, "从机忙异常"),
+                (0x08, "NAK 异常"),
+                (0x10, "写超时"),
+                (0x20, "只听模式"),
+            ]),
+        ),
+        _ => return "未知事件".to_string(),
+    };
+    if details.is_empty() {
+        kind.to_string()
+    } else {
+        format!("{}: {}", kind, details.join("、"))
+    }
+}
+
+/// 设备标识（0x2B/0x0E）的对象名称
+pub fn device_object_name(id: u8) -> &'static str {
+    match id {
+        0x00 => "厂商名称",
+        0x01 => "产品代码",
+        0x02 => "版本",
+        0x03 => "厂商网址",
+        0x04 => "产品名称",
+        0x05 => "型号",
+        0x06 => "应用名称",
+        0x07..=0x7F => "保留",
+        _ => "厂商自定义",
+    }
+}
+
+/// 按顺序读取 PDU 中的字段，数据不足时返回 `None`
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + count)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    /// 解码完所有字段后剩余的字节
+    fn rest_node(&mut self) -> Option<Node> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        self.position = self.data.len();
+        (!rest.is_empty()).then(|| Node::leaf(format!("多余的字节 {}", hex(rest))))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn coil_text(value: u16) -> String {
+    match value {
+        0xFF00 => "ON".to_string(),
+        0x0000 => "OFF".to_string(),
+        other => format!("非法值 0x{:04X}", other),
+    }
+}
+
+/// 寄存器值的列表，`address` 已知时按地址显示，否则按序号显示
+fn register_nodes(values: &[u8], address: Option<u16>) -> Vec<Node> {
+    values
+        .chunks(2)
+        .enumerate()
+        .map(|(index, pair)| {
+            let name = match address {
+                Some(address) => format!("地址 {}", address as usize + index),
+                None => format!("寄存器 {}", index),
+            };
+            match pair {
+                [high, low] => {
+                    let value = u16::from_be_bytes([*high, *low]);
+                    Node::leaf(format!("{}: {} (0x{:04X})", name, value, value))
+                }
+                _ => Node::leaf(format!("{}: 只有一个字节 0x{:02X}", name, pair[0])),
+            }
+        })
+        .collect()
+}
+
+/// 位值的列表，`quantity` 已知时去掉最后一个字节的填充位
+fn bit_nodes(bytes: &[u8], address: Option<u16>, quantity: Option<u16>) -> Vec<Node> {
+    let count = quantity.map_or(bytes.len() * 8, |quantity| {
+        (quantity as usize).min(bytes.len() * 8)
+    });
+    (0..count)
+        .map(|index| {
+            let on = bytes[index / 8] & (1 << (index % 8)) != 0;
+            let name = match address {
+                Some(address) => format!("地址 {}", address as usize + index),
+                None => format!("位 {}", index),
+            };
+            Node::leaf(format!("{}: {}", name, if on { "ON" } else { "OFF" }))
+        })
+        .collect()
+}
+
+/// 解码请求的字段，返回摘要中的说明
+fn decode_request(code: u8, reader: &mut Reader, fields: &mut Vec<Node>) -> Option<String> {
+    let detail = match code {
+        0x01..=0x04 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("起始地址 {}", address)));
+            let quantity = reader.u16()?;
+            fields.push(Node::leaf(format!("数量 {}", quantity)));
+            format!("地址 {} 数量 {}", address, quantity)
+        }
+        0x05 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("地址 {}", address)));
+            let value = coil_text(reader.u16()?);
+            fields.push(Node::leaf(format!("值 {}", value)));
+            format!("地址 {} = {}", address, value)
+        }
+        0x06 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("地址 {}", address)));
+            let value = reader.u16()?;
+            fields.push(Node::leaf(format!("值 {} (0x{:04X})", value, value)));
+            format!("地址 {} = {}", address, value)
+        }
+        0x0F | 0x10 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("起始地址 {}", address)));
+            let quantity = reader.u16()?;
+            fields.push(Node::leaf(format!("数量 {}", quantity)));
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let values = reader.bytes(count as usize)?;
+            let values = if code == 0x0F {
+                bit_nodes(values, Some(address), Some(quantity))
+            } else {
+                register_nodes(values, Some(address))
+            };
+            fields.push(Node::branch("写入的值", values));
+            format!("地址 {} 数量 {}", address, quantity)
+        }
+        0x16 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("地址 {}", address)));
+            let and_mask = reader.u16()?;
+            fields.push(Node::leaf(format!("AND 掩码 0x{:04X}", and_mask)));
+            let or_mask = reader.u16()?;
+            fields.push(Node::leaf(format!("OR 掩码 0x{:04X}", or_mask)));
+            format!("地址 {}", address)
+        }
+        0x17 => {
+            let read_address = reader.u16()?;
+            fields.push(Node::leaf(format!("读起始地址 {}", read_address)));
+            let read_quantity = reader.u16()?;
+            fields.push(Node::leaf(format!("读数量 {}", read_quantity)));
+            let write_address = reader.u16()?;
+            fields.push(Node::leaf(format!("写起始地址 {}", write_address)));
+            let write_quantity = reader.u16()?;
+            fields.push(Node::leaf(format!("写数量 {}", write_quantity)));
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let values = reader.bytes(count as usize)?;
+            fields.push(Node::branch(
+                "写入的值",
+                register_nodes(values, Some(write_address)),
+            ));
+            format!(
+                "读 {} 数量 {}, 写 {} 数量 {}",
+                read_address, read_quantity, write_address, write_quantity
+            )
+        }
+        0x08 => {
+            let sub_function = reader.u16()?;
+            let name = diagnostic_name(sub_function);
+            fields.push(Node::leaf(format!(
+                "子功能 0x{:04X} {}",
+                sub_function, name
+            )));
+            let data = reader.bytes(reader.data.len() - reader.position)?;
+            fields.push(Node::leaf(format!("数据 {}", hex(data))));
+            name.to_string()
+        }
+        0x18 => {
+            let address = reader.u16()?;
+            fields.push(Node::leaf(format!("FIFO 地址 {}", address)));
+            format!("地址 {}", address)
+        }
+        0x2B => {
+            let mei = reader.u8()?;
+            fields.push(Node::leaf(format!("MEI 类型 0x{:02X}", mei)));
+            if mei != 0x0E {
+                return Some(format!("MEI 0x{:02X}", mei));
+            }
+            let read_code = reader.u8()?;
+            fields.push(Node::leaf(format!(
+                "读取类型 {}",
+                device_read_code_name(read_code)
+            )));
+            let object = reader.u8()?;
+            fields.push(Node::leaf(format!(
+                "对象 0x{:02X} {}",
+                object,
+                device_object_name(object)
+            )));
+            format!("读设备标识 {}", device_read_code_name(read_code))
+        }
+        // 没有字段的请求和不认识的功能码，剩余字节作为多余的字节显示
+        _ => String::new(),
+    };
+    Some(detail)
+}
+
+/// 解码应答的字段，返回摘要中的说明
+fn decode_response(
+    code: u8,
+    request: Option<&Adu>,
+    reader: &mut Reader,
+    fields: &mut Vec<Node>,
+) -> Option<String> {
+    // 读请求的起始地址和数量
+    let requested = request.and_then(|request| {
+        let pdu = &request.pdu;
+        let field = |index: usize| {
+            pdu.get(index..index + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        Some((field(1)?, field(3)?))
+    });
+    let detail = match code {
+        0x01 | 0x02 => {
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let values = reader.bytes(count as usize)?;
+            let (address, quantity) = requested.unzip();
+            let bits = bit_nodes(values, address, quantity);
+            let detail = format!("{} 个位", bits.len());
+            fields.push(Node::branch("读到的值", bits));
+            detail
+        }
+        0x03 | 0x04 | 0x17 => {
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let values = reader.bytes(count as usize)?;
+            let registers = register_nodes(values, requested.map(|(address, _)| address));
+            let detail = format!("{} 个寄存器", registers.len());
+            fields.push(Node::branch("读到的值", registers));
+            detail
+        }
+        0x05 | 0x06 | 0x0F | 0x10 | 0x16 => {
+            // 写应答与请求的前几个字段相同
+            return decode_request(code, reader, fields).map(|detail| format!("已写入 {}", detail));
+        }
+        0x07 => {
+            let status = reader.u8()?;
+            fields.push(Node::leaf(format!("异常状态 {:08b}", status)));
+            format!("{:08b}", status)
+        }
+        0x08 => {
+            let sub_function = reader.u16()?;
+            let name = diagnostic_name(sub_function);
+            fields.push(Node::leaf(format!(
+                "子功能 0x{:04X} {}",
+                sub_function, name
+            )));
+            let data = reader.bytes(reader.data.len() - reader.position)?;
+            if let [high, low] = data {
+                let value = u16::from_be_bytes([*high, *low]);
+                fields.push(Node::leaf(format!("数据 {} (0x{:04X})", value, value)));
+                format!("{} {}", name, value)
+            } else {
+                fields.push(Node::leaf(format!("数据 {}", hex(data))));
+                name.to_string()
+            }
+        }
+        0x0B => {
+            let status = reader.u16()?;
+            fields.push(Node::leaf(format!("状态 0x{:04X}", status)));
+            let count = reader.u16()?;
+            fields.push(Node::leaf(format!("事件计数 {}", count)));
+            format!("事件计数 {}", count)
+        }
+        0x0C => {
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let status = reader.u16()?;
+            fields.push(Node::leaf(format!("状态 0x{:04X}", status)));
+            let events = reader.u16()?;
+            fields.push(Node::leaf(format!("事件计数 {}", events)));
+            let messages = reader.u16()?;
+            fields.push(Node::leaf(format!("报文计数 {}", messages)));
+            let log = reader.bytes((count as usize).saturating_sub(6))?;
+            fields.push(Node::branch(
+                "事件记录",
+                log.iter()
+                    .map(|event| Node::leaf(format!("0x{:02X} {}", event, comm_event_text(*event))))
+                    .collect(),
+            ));
+            format!("事件计数 {} 报文计数 {}", events, messages)
+        }
+        0x11 => {
+            let count = reader.u8()?;
+            fields.push(Node::leaf(format!("字节数 {}", count)));
+            let data = reader.bytes(count as usize)?;
+            fields.push(Node::leaf(format!("数据 {}", hex(data))));
+            String::new()
+        }
+        0x18 => {
+            let _count = reader.u16()?;
+            let fifo_count = reader.u16()?;
+            fields.push(Node::leaf(format!("FIFO 数量 {}", fifo_count)));
+            let values = reader.bytes(fifo_count as usize * 2)?;
+            fields.push(Node::branch("FIFO 值", register_nodes(values, None)));
+            format!("{} 个值", fifo_count)
+        }
+        0x2B => {
+            let mei = reader.u8()?;
+            fields.push(Node::leaf(format!("MEI 类型 0x{:02X}", mei)));
+            if mei != 0x0E {
+                return Some(format!("MEI 0x{:02X}", mei));
+            }
+            let read_code = reader.u8()?;
+            fields.push(Node::leaf(format!(
+                "读取类型 {}",
+                device_read_code_name(read_code)
+            )));
+            let conformity = reader.u8()?;
+            fields.push(Node::leaf(format!("一致性等级 0x{:02X}", conformity)));
+            let more = reader.u8()?;
+            fields.push(Node::leaf(format!(
+                "后续 {}",
+                if more == 0xFF { "还有" } else { "没有" }
+            )));
+            let next = reader.u8()?;
+            fields.push(Node::leaf(format!("下一个对象 0x{:02X}", next)));
+            let count = reader.u8()?;
+            let mut objects = Vec::new();
+            for _ in 0..count {
+                let id = reader.u8()?;
+                let length = reader.u8()?;
+                let value = reader.bytes(length as usize)?;
+                objects.push(Node::leaf(format!(
+                    "0x{:02X} {}: {}",
+                    id,
+                    device_object_name(id),
+                    String::from_utf8_lossy(value)
+                )));
+            }
+            fields.push(Node::branch(format!("对象 {} 个", count), objects));
+            format!("{} 个设备标识对象", count)
+        }
+        _ => String::new(),
+    };
+    Some(detail)
+}
+
+fn device_read_code_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "基本",
+        0x02 => "常规",
+        0x03 => "扩展",
+        0x04 => "单个对象",
+        _ => "未知",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::frame::{encode_ascii, encode_rtu};
+
+    #[test]
+    fn test_decode_rtu_pair() {
+        let request = encode_rtu(&[0x01, 0x03, 0x00, 0x64, 0x00, 0x02]);
+        let decoded = decode(&request, Framing::Rtu, FrameKind::Request, None);
+        assert_eq!(decoded.summary, "请求 站号 1 读保持寄存器 地址 100 数量 2");
+
+        let response = encode_rtu(&[0x01, 0x03, 0x04, 0x00, 0x0A, 0x01, 0x02]);
+        let request = Adu::parse(&request, Framing::Rtu).unwrap();
+        let adu = Adu::parse(&response, Framing::Rtu).unwrap();
+        assert!(adu.is_response_to(&request));
+        let decoded = decode(&response, Framing::Rtu, FrameKind::Response, Some(&request));
+        assert_eq!(decoded.summary, "应答 站号 1 读保持寄存器 2 个寄存器");
+        let values = decoded
+            .tree
+            .iter()
+            .find(|node| node.text == "读到的值")
+            .unwrap();
+        assert_eq!(values.children[1].text, "地址 101: 258 (0x0102)");
+
+        let mut corrupted = response.clone();
+        corrupted[7] ^= 0xFF;
+        let decoded = decode(&corrupted, Framing::Rtu, FrameKind::Response, None);
+        let crc = crc16(&response[..7]);
+        let actual = u16::from_le_bytes([corrupted[7], corrupted[8]]);
+        assert_eq!(
+            decoded.tree.last().unwrap().text,
+            format!("CRC 错误: 应为 0x{:04X}, 实际 0x{:04X}", crc, actual)
+        );
+    }
+
+    #[test]
+    fn test_decode_exception_and_tcp() {
+        let exception = encode_ascii(&[0x11, 0x83, 0x02]);
+        let decoded = decode(&exception, Framing::Ascii, FrameKind::Response, None);
+        assert_eq!(
+            decoded.summary,
+            "异常 站号 17 读保持寄存器: 非法数据地址 (0x02)"
+        );
+
+        let request = [
+            0x00, 0x07, 0x00, 0x00, 0x00, 0x06, 0x01, 0x05, 0x00, 0x10, 0xFF, 0x00,
+        ];
+        let decoded = decode(&request, Framing::Tcp, FrameKind::Request, None);
+        assert_eq!(decoded.summary, "请求 站号 1 写单个线圈 地址 16 = ON");
+        let response = Adu::parse(&request, Framing::Tcp).unwrap();
+        let mut other = request;
+        other[1] = 0x08;
+        assert!(!response.is_response_to(&Adu::parse(&other, Framing::Tcp).unwrap()));
+    }
+
+    #[test]
+    fn test_ascii_rejects_non_ascii() {
+        assert!(Adu::parse(b":0\xC3\xA90\r\n", Framing::Ascii).is_err());
+        let decoded = decode(
+            b":01\xE6\x96\x8703\r\n",
+            Framing::Ascii,
+            FrameKind::Request,
+            None,
+        );
+        assert!(decoded.summary.contains("非 ASCII"), "{}", decoded.summary);
+    }
+}
@@ -0,0 +1,357 @@
+//! 串行链路帧格式
+//!
+//! RTU 帧是二进制的 `站号 + PDU + CRC16`，ASCII 帧是以 `:` 开头、`\r\n` 结尾的
+//! 十六进制文本，末尾带 LRC。这里只负责校验和编解码，不涉及具体的传输层。
+
+use std::fmt;
+
+/// 报文的帧格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// 二进制帧，带 CRC16
+    #[default]
+    Rtu,
+    /// 十六进制文本帧，带 LRC
+    Ascii,
+    /// Modbus TCP 的 MBAP 报文头，只能用于 TCP
+    Tcp,
+}
+
+impl Framing {
+    /// 串口可用的帧格式
+    pub const SERIAL: [Framing; 2] = [Framing::Rtu, Framing::Ascii];
+    /// TCP 可用的帧格式，RTU/ASCII 用于透传串口报文的转换器
+    pub const TCP: [Framing; 3] = [Framing::Tcp, Framing::Rtu, Framing::Ascii];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Framing::Rtu => "RTU",
+            Framing::Ascii => "ASCII",
+            Framing::Tcp => "TCP (MBAP)",
+        }
+    }
+
+    /// 配置文件中使用的名称
+    pub fn key(self) -> &'static str {
+        match self {
+            Framing::Rtu => "rtu",
+            Framing::Ascii => "ascii",
+            Framing::Tcp => "tcp",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Framing> {
+        Framing::TCP
+            .into_iter()
+            .find(|framing| framing.key().eq_ignore_ascii_case(key))
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// 帧是主机发出的请求还是从机返回的应答，RTU 帧的长度取决于它
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Request,
+    Response,
+}
+
+impl FrameKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            FrameKind::Request => "请求",
+            FrameKind::Response => "应答",
+        }
+    }
+
+    /// 请求的另一方是应答，应答的另一方是请求
+    pub fn opposite(self) -> FrameKind {
+        match self {
+            FrameKind::Request => FrameKind::Response,
+            FrameKind::Response => FrameKind::Request,
+        }
+    }
+}
+
+/// 单个帧的校验或格式错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// RTU 帧的 CRC 与内容不符
+    Crc { expected: u16, actual: u16 },
+    /// ASCII 帧的 LRC 与内容不符
+    Lrc { expected: u8, actual: u8 },
+    /// 无法确定帧长度的功能码
+    UnknownFunction(u8),
+    /// 帧太短或包含非法字符
+    Malformed(String),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Crc { expected, actual } => {
+                write!(
+                    f,
+                    "CRC 错误: 应为 0x{:04X}, 实际 0x{:04X}",
+                    expected, actual
+                )
+            }
+            FrameError::Lrc { expected, actual } => {
+                write!(
+                    f,
+                    "LRC 错误: 应为 0x{:02X}, 实际 0x{:02X}",
+                    expected, actual
+                )
+            }
+            FrameError::UnknownFunction(code) => write!(f, "无法识别的功能码 0x{:02X}", code),
+            FrameError::Malformed(reason) => write!(f, "帧格式错误: {}", reason),
+        }
+    }
+}
+
+/// Modbus CRC16（多项式 0xA001，初值 0xFFFF）
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Modbus LRC：所有字节求和后取补码
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg()
+}
+
+/// 给 `站号 + PDU` 加上 CRC，得到完整的 RTU 帧
+pub fn encode_rtu(adu: &[u8]) -> Vec<u8> {
+    let mut frame = adu.to_vec();
+    frame.extend_from_slice(&crc16(adu).to_le_bytes());
+    frame
+}
+
+/// 校验完整的 RTU 帧，返回去掉 CRC 的 `站号 + PDU`
+pub fn decode_rtu(frame: &[u8]) -> Result<&[u8], FrameError> {
+    if frame.len() < 4 {
+        return Err(FrameError::Malformed(format!(
+            "帧长度 {} 太短",
+            frame.len()
+        )));
+    }
+    let (adu, tail) = frame.split_at(frame.len() - 2);
+    let expected = crc16(adu);
+    let actual = u16::from_le_bytes([tail[0], tail[1]]);
+    if expected != actual {
+        return Err(FrameError::Crc { expected, actual });
+    }
+    Ok(adu)
+}
+
+/// 把 `站号 + PDU` 编码成 ASCII 帧，包括起始的 `:` 和结尾的 `\r\n`
+pub fn encode_ascii(adu: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(adu.len() * 2 + 5);
+    frame.push(b':');
+    for byte in adu.iter().chain(std::iter::once(&lrc(adu))) {
+        frame.extend_from_slice(format!("{:02X}", byte).as_bytes());
+    }
+    frame.extend_from_slice(b"\r\n");
+    frame
+}
+
+/// 解码一行 ASCII 帧（可以带或不带 `:` 和 `\r\n`），返回去掉 LRC 的 `站号 + PDU`
+pub fn decode_ascii(line: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let text = line.strip_prefix(b":").unwrap_or(line);
+    let text = text.strip_suffix(b"\r\n").unwrap_or(text);
+    if !text.len().is_multiple_of(2) {
+        return Err(FrameError::Malformed("十六进制字符个数为奇数".to_string()));
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for pair in text.chunks(2) {
+        let byte = std::str::from_utf8(pair)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| {
+                FrameError::Malformed(format!("非法字符 {:?}", String::from_utf8_lossy(pair)))
+            })?;
+        bytes.push(byte);
+    }
+    if bytes.len() < 3 {
+        return Err(FrameError::Malformed(format!(
+            "帧长度 {} 太短",
+            bytes.len()
+        )));
+    }
+    let actual = bytes.pop().unwrap_or_default();
+    let expected = lrc(&bytes);
+    if expected != actual {
+        return Err(FrameError::Lrc { expected, actual });
+    }
+    Ok(bytes)
+}
+
+/// RTU 帧的最大长度：站号、253 字节的 PDU 和 CRC
+pub const MAX_RTU_FRAME: usize = 256;
+
+/// 根据已收到的前几个字节计算 RTU 帧的总长度（含 CRC）
+///
+/// 字节不足以确定长度时返回 `Ok(None)`。
+pub fn rtu_frame_length(buf: &[u8], kind: FrameKind) -> Result<Option<usize>, FrameError> {
+    let Some(&function) = buf.get(1) else {
+        return Ok(None);
+    };
+    // 带字节计数的帧：固定头部长度 + 计数所在位置
+    let counted = |header: usize| {
+        buf.get(header - 1)
+            .map(|&count| header + count as usize + 2)
+    };
+    let length = match kind {
+        FrameKind::Request => match function {
+            0x08 => return diagnostics_length(buf),
+            0x01..=0x06 => Some(8),
+            0x07 | 0x0B | 0x0C | 0x11 => Some(4),
+            0x0F | 0x10 => counted(7),
+            0x16 => Some(10),
+            0x17 => counted(11),
+            0x2B => Some(7),
+            _ => return Err(FrameError::UnknownFunction(function)),
+        },
+        FrameKind::Response => match function {
+            code if code & 0x80 != 0 => Some(5),
+            0x01..=0x04 | 0x0C | 0x11 | 0x17 => counted(3),
+            0x08 => return diagnostics_length(buf),
+            0x05 | 0x06 | 0x0B | 0x0F | 0x10 => Some(8),
+            0x07 => Some(5),
+            0x16 => Some(10),
+            0x2B => return device_id_response_length(buf),
+            _ => return Err(FrameError::UnknownFunction(function)),
+        },
+    };
+    Ok(length)
+}
+
+/// 诊断（0x08）的请求和应答的长度
+///
+/// 子功能 0x0000 返回询问数据可以带任意个数据字，帧中没有字节计数，只能在已收到的
+/// 字节中找 CRC 正确的最短长度；收满最大帧长仍找不到时按一个数据字处理，报告 CRC 错误。
+/// 其他子功能固定带一个数据字。
+fn diagnostics_length(buf: &[u8]) -> Result<Option<usize>, FrameError> {
+    let Some(sub_function) = buf.get(2..4) else {
+        return Ok(None);
+    };
+    if sub_function != [0x00, 0x00] {
+        return Ok(Some(8));
+    }
+    let found = (8..=buf.len().min(MAX_RTU_FRAME))
+        .step_by(2)
+        .find(|&length| decode_rtu(&buf[..length]).is_ok());
+    match found {
+        Some(length) => Ok(Some(length)),
+        None if buf.len() >= MAX_RTU_FRAME => Ok(Some(8)),
+        None => Ok(None),
+    }
+}
+
+/// 读设备标识（0x2B/0x0E）应答的长度：固定头部之后依次是每个对象的编号、长度和内容
+fn device_id_response_length(buf: &[u8]) -> Result<Option<usize>, FrameError> {
+    let Some(&mei) = buf.get(2) else {
+        return Ok(None);
+    };
+    if mei != 0x0E {
+        return Err(FrameError::UnknownFunction(0x2B));
+    }
+    // 站号、功能码、MEI 类型、读取类别、等级、后续标志、下一对象、对象数
+    let Some(&count) = buf.get(7) else {
+        return Ok(None);
+    };
+    let mut position = 8;
+    for _ in 0..count {
+        let Some(&length) = buf.get(position + 1) else {
+            return Ok(None);
+        };
+        position += 2 + length as usize;
+    }
+    Ok(Some(position + 2))
+}
+
+/// 根据 MBAP 报文头计算 Modbus TCP 报文的总长度（含报文头）
+///
+/// 报文头不足 6 个字节时返回 `None`。
+pub fn mbap_frame_length(buf: &[u8]) -> Option<usize> {
+    let length = buf.get(4..6)?;
+    Some(6 + u16::from_be_bytes([length[0], length[1]]) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtu_round_trip() {
+        let frame = encode_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]);
+        assert_eq!(&frame[6..], &[0xC5, 0xCD]);
+        assert_eq!(decode_rtu(&frame).unwrap(), &frame[..6]);
+        assert_eq!(
+            rtu_frame_length(&frame, FrameKind::Request).unwrap(),
+            Some(8)
+        );
+
+        let mut corrupted = frame.clone();
+        corrupted[7] ^= 0xFF;
+        assert!(matches!(
+            decode_rtu(&corrupted),
+            Err(FrameError::Crc { .. })
+        ));
+    }
+
+    #[test]
+    fn test_device_id_response_length() {
+        let frame = encode_rtu(&[
+            0x01, 0x2B, 0x0E, 0x01, 0x81, 0x00, 0x00, 0x03, 0x00, 0x04, b'A', b'C', b'M', b'E',
+            0x01, 0x02, b'P', b'1', 0x02, 0x03, b'1', b'.', b'0',
+        ]);
+        for partial in [3, 8, 9, 15, 19] {
+            assert_eq!(
+                rtu_frame_length(&frame[..partial], FrameKind::Response),
+                Ok(None)
+            );
+        }
+        assert_eq!(
+            rtu_frame_length(&frame[..21], FrameKind::Response),
+            Ok(Some(frame.len()))
+        );
+        assert_eq!(
+            rtu_frame_length(&frame, FrameKind::Response),
+            Ok(Some(frame.len()))
+        );
+    }
+
+    #[test]
+    fn test_ascii_round_trip() {
+        let frame = encode_ascii(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(frame, b":010300000001FB\r\n");
+        assert_eq!(
+            decode_ascii(&frame).unwrap(),
+            vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x01]
+        );
+        assert_eq!(
+            decode_ascii(b":010300000001FC\r\n"),
+            Err(FrameError::Lrc {
+                expected: 0xFB,
+                actual: 0xFC
+            })
+        );
+    }
+}
@@ -0,0 +1,4 @@
+pub mod decode;
+pub mod frame;
+pub mod pdu;
+pub mod value;
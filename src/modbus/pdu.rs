@@ -0,0 +1,614 @@
+//! Modbus 协议数据单元
+//!
+//! 界面、主机引擎和从机存储之间传递的请求、应答和异常码，
+//! 与具体的传输库无关。
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    ReadCoils(u16, u16),
+    ReadDiscreteInputs(u16, u16),
+    ReadHoldingRegisters(u16, u16),
+    ReadInputRegisters(u16, u16),
+    WriteSingleCoil(u16, bool),
+    WriteSingleRegister(u16, u16),
+    /// 08 诊断：子功能和数据
+    Diagnostics(DiagnosticCode, Vec<u16>),
+    /// 11 读通信事件计数
+    GetCommEventCounter,
+    /// 12 读通信事件记录
+    GetCommEventLog,
+    WriteMultipleCoils(u16, Vec<bool>),
+    WriteMultipleRegisters(u16, Vec<u16>),
+    MaskWriteRegister(u16, u16, u16),
+    ReadWriteMultipleRegisters(u16, u16, u16, Vec<u16>),
+    /// 43/14 读设备标识：读取类别和起始对象编号
+    ReadDeviceIdentification(ReadDeviceIdCode, u8),
+}
+
+/// 主机可以发出的标准功能码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionCode {
+    ReadCoils,
+    ReadDiscreteInputs,
+    ReadHoldingRegisters,
+    ReadInputRegisters,
+    WriteSingleCoil,
+    WriteSingleRegister,
+    Diagnostics,
+    GetCommEventCounter,
+    GetCommEventLog,
+    WriteMultipleCoils,
+    WriteMultipleRegisters,
+    MaskWriteRegister,
+    ReadWriteMultipleRegisters,
+    ReadDeviceIdentification,
+}
+
+impl FunctionCode {
+    pub const ALL: [FunctionCode; 14] = [
+        FunctionCode::ReadCoils,
+        FunctionCode::ReadDiscreteInputs,
+        FunctionCode::ReadHoldingRegisters,
+        FunctionCode::ReadInputRegisters,
+        FunctionCode::WriteSingleCoil,
+        FunctionCode::WriteSingleRegister,
+        FunctionCode::Diagnostics,
+        FunctionCode::GetCommEventCounter,
+        FunctionCode::GetCommEventLog,
+        FunctionCode::WriteMultipleCoils,
+        FunctionCode::WriteMultipleRegisters,
+        FunctionCode::MaskWriteRegister,
+        FunctionCode::ReadWriteMultipleRegisters,
+        FunctionCode::ReadDeviceIdentification,
+    ];
+
+    pub fn code(self) -> u8 {
+        match self {
+            FunctionCode::ReadCoils => 0x01,
+            FunctionCode::ReadDiscreteInputs => 0x02,
+            FunctionCode::ReadHoldingRegisters => 0x03,
+            FunctionCode::ReadInputRegisters => 0x04,
+            FunctionCode::WriteSingleCoil => 0x05,
+            FunctionCode::WriteSingleRegister => 0x06,
+            FunctionCode::Diagnostics => DIAGNOSTICS,
+            FunctionCode::GetCommEventCounter => GET_COMM_EVENT_COUNTER,
+            FunctionCode::GetCommEventLog => GET_COMM_EVENT_LOG,
+            FunctionCode::WriteMultipleCoils => 0x0F,
+            FunctionCode::WriteMultipleRegisters => 0x10,
+            FunctionCode::MaskWriteRegister => 0x16,
+            FunctionCode::ReadWriteMultipleRegisters => 0x17,
+            FunctionCode::ReadDeviceIdentification => MEI_FUNCTION,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FunctionCode::ReadCoils => "读线圈",
+            FunctionCode::ReadDiscreteInputs => "读离散输入",
+            FunctionCode::ReadHoldingRegisters => "读保持寄存器",
+            FunctionCode::ReadInputRegisters => "读输入寄存器",
+            FunctionCode::WriteSingleCoil => "写单个线圈",
+            FunctionCode::WriteSingleRegister => "写单个寄存器",
+            FunctionCode::Diagnostics => "诊断",
+            FunctionCode::GetCommEventCounter => "读通信事件计数",
+            FunctionCode::GetCommEventLog => "读通信事件记录",
+            FunctionCode::WriteMultipleCoils => "写多个线圈",
+            FunctionCode::WriteMultipleRegisters => "写多个寄存器",
+            FunctionCode::MaskWriteRegister => "屏蔽写寄存器",
+            FunctionCode::ReadWriteMultipleRegisters => "读写多个寄存器",
+            FunctionCode::ReadDeviceIdentification => "读设备标识",
+        }
+    }
+}
+
+impl fmt::Display for FunctionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02} {}", self.code(), self.label())
+    }
+}
+
+impl Request {
+    pub fn function(&self) -> FunctionCode {
+        match self {
+            Request::ReadCoils(..) => FunctionCode::ReadCoils,
+            Request::ReadDiscreteInputs(..) => FunctionCode::ReadDiscreteInputs,
+            Request::ReadHoldingRegisters(..) => FunctionCode::ReadHoldingRegisters,
+            Request::ReadInputRegisters(..) => FunctionCode::ReadInputRegisters,
+            Request::WriteSingleCoil(..) => FunctionCode::WriteSingleCoil,
+            Request::WriteSingleRegister(..) => FunctionCode::WriteSingleRegister,
+            Request::Diagnostics(..) => FunctionCode::Diagnostics,
+            Request::GetCommEventCounter => FunctionCode::GetCommEventCounter,
+            Request::GetCommEventLog => FunctionCode::GetCommEventLog,
+            Request::WriteMultipleCoils(..) => FunctionCode::WriteMultipleCoils,
+            Request::WriteMultipleRegisters(..) => FunctionCode::WriteMultipleRegisters,
+            Request::MaskWriteRegister(..) => FunctionCode::MaskWriteRegister,
+            Request::ReadWriteMultipleRegisters(..) => FunctionCode::ReadWriteMultipleRegisters,
+            Request::ReadDeviceIdentification(..) => FunctionCode::ReadDeviceIdentification,
+        }
+    }
+
+    /// 读取数据的起始地址，写请求返回 `None`
+    pub fn read_address(&self) -> Option<u16> {
+        match self {
+            Request::ReadCoils(address, _)
+            | Request::ReadDiscreteInputs(address, _)
+            | Request::ReadHoldingRegisters(address, _)
+            | Request::ReadInputRegisters(address, _)
+            | Request::ReadWriteMultipleRegisters(address, ..) => Some(*address),
+            _ => None,
+        }
+    }
+
+    pub fn function_code(&self) -> u8 {
+        self.function().code()
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = self.function().label();
+        match self {
+            Request::ReadCoils(address, quantity)
+            | Request::ReadDiscreteInputs(address, quantity)
+            | Request::ReadHoldingRegisters(address, quantity)
+            | Request::ReadInputRegisters(address, quantity) => {
+                write!(f, "{} 地址 {} 数量 {}", label, address, quantity)
+            }
+            Request::WriteSingleCoil(address, value) => {
+                write!(f, "{} 地址 {} = {}", label, address, bit_text(*value))
+            }
+            Request::WriteSingleRegister(address, value) => {
+                write!(f, "{} 地址 {} = {}", label, address, value)
+            }
+            Request::Diagnostics(code, data) => {
+                write!(f, "{} {}", label, code.label())?;
+                if *code == DiagnosticCode::ReturnQueryData {
+                    write!(f, " {} 个字", data.len())?;
+                }
+                Ok(())
+            }
+            Request::GetCommEventCounter | Request::GetCommEventLog => write!(f, "{}", label),
+            Request::WriteMultipleCoils(address, values) => {
+                write!(f, "{} 地址 {} 数量 {}", label, address, values.len())
+            }
+            Request::WriteMultipleRegisters(address, values) => {
+                write!(f, "{} 地址 {} 数量 {}", label, address, values.len())
+            }
+            Request::MaskWriteRegister(address, and_mask, or_mask) => write!(
+                f,
+                "{} 地址 {} AND 0x{:04X} OR 0x{:04X}",
+                label, address, and_mask, or_mask
+            ),
+            Request::ReadWriteMultipleRegisters(read_address, quantity, write_address, values) => {
+                write!(
+                    f,
+                    "{} 读 {} 数量 {}, 写 {} 数量 {}",
+                    label,
+                    read_address,
+                    quantity,
+                    write_address,
+                    values.len()
+                )
+            }
+            Request::ReadDeviceIdentification(code, object) => {
+                write!(f, "{} {} 对象 0x{:02X}", label, code.label(), object)
+            }
+        }
+    }
+}
+
+fn bit_text(value: bool) -> &'static str {
+    if value { "ON" } else { "OFF" }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    ReadCoils(Vec<bool>),
+    ReadDiscreteInputs(Vec<bool>),
+    ReadHoldingRegisters(Vec<u16>),
+    ReadInputRegisters(Vec<u16>),
+    WriteSingleCoil(u16, bool),
+    WriteSingleRegister(u16, u16),
+    /// 08 诊断：回显的子功能和数据，计数类子功能的数据是计数值
+    Diagnostics(DiagnosticCode, Vec<u16>),
+    /// 11 读通信事件计数：状态字和事件计数
+    GetCommEventCounter(u16, u16),
+    GetCommEventLog(CommEventLog),
+    WriteMultipleCoils(u16, u16),
+    WriteMultipleRegisters(u16, u16),
+    MaskWriteRegister(u16, u16, u16),
+    ReadWriteMultipleRegisters(Vec<u16>),
+    ReadDeviceIdentification(DeviceIdentification),
+}
+
+impl Response {
+    /// 读应答中的位数据
+    pub fn bits(&self) -> Option<&[bool]> {
+        match self {
+            Response::ReadCoils(values) | Response::ReadDiscreteInputs(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// 读应答中的寄存器数据
+    pub fn registers(&self) -> Option<&[u16]> {
+        match self {
+            Response::ReadHoldingRegisters(values)
+            | Response::ReadInputRegisters(values)
+            | Response::ReadWriteMultipleRegisters(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::ReadCoils(values) | Response::ReadDiscreteInputs(values) => {
+                write!(f, "{} 个位", values.len())
+            }
+            Response::ReadHoldingRegisters(values)
+            | Response::ReadInputRegisters(values)
+            | Response::ReadWriteMultipleRegisters(values) => {
+                write!(f, "{} 个寄存器", values.len())
+            }
+            Response::WriteSingleCoil(address, value) => {
+                write!(f, "已写入 地址 {} = {}", address, bit_text(*value))
+            }
+            Response::WriteSingleRegister(address, value) => {
+                write!(f, "已写入 地址 {} = {}", address, value)
+            }
+            Response::Diagnostics(code, data) => match (code.is_counter(), data.as_slice()) {
+                (true, [count]) => write!(f, "{} {}", code.label(), count),
+                (false, _) if *code == DiagnosticCode::ReturnQueryData => {
+                    write!(f, "回显 {} 个字", data.len())
+                }
+                _ => write!(f, "{} 完成", code.label()),
+            },
+            Response::GetCommEventCounter(status, count) => {
+                write!(f, "事件计数 {} 状态 0x{:04X}", count, status)
+            }
+            Response::GetCommEventLog(log) => write!(
+                f,
+                "事件计数 {} 报文计数 {} 记录 {} 个事件",
+                log.event_count,
+                log.message_count,
+                log.events.len()
+            ),
+            Response::WriteMultipleCoils(address, quantity)
+            | Response::WriteMultipleRegisters(address, quantity) => {
+                write!(f, "已写入 地址 {} 数量 {}", address, quantity)
+            }
+            Response::MaskWriteRegister(address, and_mask, or_mask) => write!(
+                f,
+                "已写入 地址 {} AND 0x{:04X} OR 0x{:04X}",
+                address, and_mask, or_mask
+            ),
+            Response::ReadDeviceIdentification(identification) => {
+                write!(f, "{} 个标识对象", identification.objects.len())
+            }
+        }
+    }
+}
+
+/// 诊断和通信事件功能码，传输库按自定义功能码收发
+pub const DIAGNOSTICS: u8 = 0x08;
+pub const GET_COMM_EVENT_COUNTER: u8 = 0x0B;
+pub const GET_COMM_EVENT_LOG: u8 = 0x0C;
+
+/// 封装接口传输（MEI）使用的功能码
+pub const MEI_FUNCTION: u8 = 0x2B;
+/// 读设备标识的 MEI 类型
+pub const MEI_READ_DEVICE_ID: u8 = 0x0E;
+
+/// 读设备标识的读取类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadDeviceIdCode {
+    /// 对象 0x00–0x02：厂商、产品代码、版本
+    #[default]
+    Basic,
+    /// 对象 0x00–0x06，增加网址、产品名称、型号和应用名称
+    Regular,
+    /// 对象 0x00–0xFF，0x80 起为厂商自定义对象
+    Extended,
+    /// 只读取指定的一个对象
+    Individual,
+}
+
+impl ReadDeviceIdCode {
+    pub const ALL: [ReadDeviceIdCode; 4] = [
+        ReadDeviceIdCode::Basic,
+        ReadDeviceIdCode::Regular,
+        ReadDeviceIdCode::Extended,
+        ReadDeviceIdCode::Individual,
+    ];
+
+    pub fn code(self) -> u8 {
+        match self {
+            ReadDeviceIdCode::Basic => 0x01,
+            ReadDeviceIdCode::Regular => 0x02,
+            ReadDeviceIdCode::Extended => 0x03,
+            ReadDeviceIdCode::Individual => 0x04,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReadDeviceIdCode::Basic => "基本",
+            ReadDeviceIdCode::Regular => "常规",
+            ReadDeviceIdCode::Extended => "扩展",
+            ReadDeviceIdCode::Individual => "单个",
+        }
+    }
+}
+
+/// 读设备标识的应答
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeviceIdentification {
+    pub code: u8,
+    /// 设备支持的标识等级
+    pub conformity: u8,
+    /// 一帧放不下时为 `Some(下一个对象编号)`，主机从该对象继续读取
+    pub next_object: Option<u8>,
+    pub objects: Vec<(u8, Vec<u8>)>,
+}
+
+impl DeviceIdentification {
+    /// 解析功能码之后的应答数据（从 MEI 类型开始）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let [
+            MEI_READ_DEVICE_ID,
+            code,
+            conformity,
+            more,
+            next,
+            count,
+            rest @ ..,
+        ] = data
+        else {
+            return None;
+        };
+        let mut objects = Vec::new();
+        let mut rest = rest;
+        for _ in 0..*count {
+            let [id, length, tail @ ..] = rest else {
+                return None;
+            };
+            let value = tail.get(..*length as usize)?;
+            objects.push((*id, value.to_vec()));
+            rest = &tail[*length as usize..];
+        }
+        Some(Self {
+            code: *code,
+            conformity: *conformity,
+            next_object: (*more == 0xFF).then_some(*next),
+            objects,
+        })
+    }
+
+    /// 编码为功能码之后的应答数据，与 `parse` 相反
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![
+            MEI_READ_DEVICE_ID,
+            self.code,
+            self.conformity,
+            if self.next_object.is_some() {
+                0xFF
+            } else {
+                0x00
+            },
+            self.next_object.unwrap_or_default(),
+            self.objects.len() as u8,
+        ];
+        for (id, value) in &self.objects {
+            data.push(*id);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    /// 对象的文本内容，不存在时返回 `None`
+    pub fn text(&self, id: u8) -> Option<String> {
+        self.objects
+            .iter()
+            .find(|(object, _)| *object == id)
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+    }
+}
+
+/// 诊断功能码支持的子功能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiagnosticCode {
+    /// 原样回显请求中的数据
+    #[default]
+    ReturnQueryData,
+    /// 重启通信口并清除计数器，数据为 0xFF00 时同时清除事件记录
+    RestartCommunications,
+    ClearCounters,
+    BusMessageCount,
+    /// 总线上 CRC/LRC 校验错误的帧数
+    BusCommunicationErrorCount,
+    /// 返回的异常应答数
+    BusExceptionErrorCount,
+    ServerMessageCount,
+    /// 发给本站但没有应答的报文数，即广播报文
+    ServerNoResponseCount,
+}
+
+impl DiagnosticCode {
+    pub const ALL: [DiagnosticCode; 8] = [
+        DiagnosticCode::ReturnQueryData,
+        DiagnosticCode::RestartCommunications,
+        DiagnosticCode::ClearCounters,
+        DiagnosticCode::BusMessageCount,
+        DiagnosticCode::BusCommunicationErrorCount,
+        DiagnosticCode::BusExceptionErrorCount,
+        DiagnosticCode::ServerMessageCount,
+        DiagnosticCode::ServerNoResponseCount,
+    ];
+
+    pub fn code(self) -> u16 {
+        match self {
+            DiagnosticCode::ReturnQueryData => 0x00,
+            DiagnosticCode::RestartCommunications => 0x01,
+            DiagnosticCode::ClearCounters => 0x0A,
+            DiagnosticCode::BusMessageCount => 0x0B,
+            DiagnosticCode::BusCommunicationErrorCount => 0x0C,
+            DiagnosticCode::BusExceptionErrorCount => 0x0D,
+            DiagnosticCode::ServerMessageCount => 0x0E,
+            DiagnosticCode::ServerNoResponseCount => 0x0F,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+
+    pub fn label(self) -> &'static str {
+        crate::modbus::decode::diagnostic_name(self.code())
+    }
+
+    /// 应答数据是一个计数值的子功能
+    pub fn is_counter(self) -> bool {
+        self.code() >= DiagnosticCode::BusMessageCount.code()
+    }
+
+    /// 编码为功能码之后的数据：子功能和数据字
+    pub fn encode(self, data: &[u16]) -> Vec<u8> {
+        let mut bytes = self.code().to_be_bytes().to_vec();
+        for word in data {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// 解析功能码之后的数据，返回子功能编号和数据字，子功能是否支持由调用方判断
+    pub fn parse(data: &[u8]) -> Option<(u16, Vec<u16>)> {
+        let [high, low, rest @ ..] = data else {
+            return None;
+        };
+        if rest.len() % 2 != 0 {
+            return None;
+        }
+        let words = rest
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        Some((u16::from_be_bytes([*high, *low]), words))
+    }
+}
+
+/// 重启通信时同时清除事件记录的请求数据
+pub const RESTART_CLEAR_LOG: u16 = 0xFF00;
+
+/// 读通信事件记录的应答
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommEventLog {
+    pub status: u16,
+    pub event_count: u16,
+    /// 总线报文计数，与诊断子功能 0x0B 相同
+    pub message_count: u16,
+    /// 最近的事件，最新的在前
+    pub events: Vec<u8>,
+}
+
+impl CommEventLog {
+    /// 解析功能码之后的应答数据（从字节数开始）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let [count, rest @ ..] = data else {
+            return None;
+        };
+        let rest = rest.get(..*count as usize)?;
+        let [s0, s1, e0, e1, m0, m1, events @ ..] = rest else {
+            return None;
+        };
+        Some(Self {
+            status: u16::from_be_bytes([*s0, *s1]),
+            event_count: u16::from_be_bytes([*e0, *e1]),
+            message_count: u16::from_be_bytes([*m0, *m1]),
+            events: events.to_vec(),
+        })
+    }
+
+    /// 编码为功能码之后的应答数据，与 `parse` 相反
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![(6 + self.events.len()) as u8];
+        data.extend_from_slice(&self.status.to_be_bytes());
+        data.extend_from_slice(&self.event_count.to_be_bytes());
+        data.extend_from_slice(&self.message_count.to_be_bytes());
+        data.extend_from_slice(&self.events);
+        data
+    }
+}
+
+/// Modbus 异常码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCode {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    MemoryParityError,
+    GatewayPathUnavailable,
+    GatewayTargetDevice,
+    Other(u8),
+}
+
+impl ExceptionCode {
+    pub fn code(self) -> u8 {
+        match self {
+            ExceptionCode::IllegalFunction => 0x01,
+            ExceptionCode::IllegalDataAddress => 0x02,
+            ExceptionCode::IllegalDataValue => 0x03,
+            ExceptionCode::ServerDeviceFailure => 0x04,
+            ExceptionCode::Acknowledge => 0x05,
+            ExceptionCode::ServerDeviceBusy => 0x06,
+            ExceptionCode::MemoryParityError => 0x08,
+            ExceptionCode::GatewayPathUnavailable => 0x0A,
+            ExceptionCode::GatewayTargetDevice => 0x0B,
+            ExceptionCode::Other(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => ExceptionCode::IllegalFunction,
+            0x02 => ExceptionCode::IllegalDataAddress,
+            0x03 => ExceptionCode::IllegalDataValue,
+            0x04 => ExceptionCode::ServerDeviceFailure,
+            0x05 => ExceptionCode::Acknowledge,
+            0x06 => ExceptionCode::ServerDeviceBusy,
+            0x08 => ExceptionCode::MemoryParityError,
+            0x0A => ExceptionCode::GatewayPathUnavailable,
+            0x0B => ExceptionCode::GatewayTargetDevice,
+            other => ExceptionCode::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExceptionCode::IllegalFunction => "非法功能码",
+            ExceptionCode::IllegalDataAddress => "非法数据地址",
+            ExceptionCode::IllegalDataValue => "非法数据值",
+            ExceptionCode::ServerDeviceFailure => "从机设备故障",
+            ExceptionCode::Acknowledge => "已确认",
+            ExceptionCode::ServerDeviceBusy => "从机设备忙",
+            ExceptionCode::MemoryParityError => "存储奇偶校验错误",
+            ExceptionCode::GatewayPathUnavailable => "网关路径不可用",
+            ExceptionCode::GatewayTargetDevice => "网关目标设备无响应",
+            ExceptionCode::Other(_) => "未知异常",
+        };
+        write!(f, "{} (0x{:02X})", name, self.code())
+    }
+}
@@ -0,0 +1,503 @@
+//! 寄存器值的类型解码和编码
+//!
+//! 把连续的若干个寄存器按数据类型和字节序解释成数值、字符串或位，
+//! 写入时再按同样的规则编码回寄存器。主机和从机页面共用这一层。
+
+use std::fmt;
+
+/// 多寄存器数值的字节序，字母 A 表示最高字节
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// 大端，Modbus 标准顺序
+    #[default]
+    Abcd,
+    /// 寄存器之间交换顺序
+    Cdab,
+    /// 每个寄存器内交换字节
+    Badc,
+    /// 小端
+    Dcba,
+}
+
+impl ByteOrder {
+    pub const ALL: [ByteOrder; 4] = [
+        ByteOrder::Abcd,
+        ByteOrder::Cdab,
+        ByteOrder::Badc,
+        ByteOrder::Dcba,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ByteOrder::Abcd => "ABCD",
+            ByteOrder::Cdab => "CDAB",
+            ByteOrder::Badc => "BADC",
+            ByteOrder::Dcba => "DCBA",
+        }
+    }
+
+    /// 按名称查找，不区分大小写
+    pub fn from_label(label: &str) -> Option<ByteOrder> {
+        ByteOrder::ALL
+            .into_iter()
+            .find(|order| order.label().eq_ignore_ascii_case(label.trim()))
+    }
+
+    fn swaps_words(self) -> bool {
+        matches!(self, ByteOrder::Cdab | ByteOrder::Dcba)
+    }
+
+    fn swaps_bytes(self) -> bool {
+        matches!(self, ByteOrder::Badc | ByteOrder::Dcba)
+    }
+}
+
+/// 寄存器的数据类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataType {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    /// 每个寄存器两个字符，参数为寄存器个数
+    Ascii(u16),
+    /// 每个寄存器一个 UTF-16 码元，参数为寄存器个数
+    Utf16(u16),
+    /// 每个寄存器 4 位十进制数字
+    Bcd16,
+    /// 两个寄存器 8 位十进制数字
+    Bcd32,
+    /// 寄存器的 16 个位
+    Bits,
+}
+
+impl DataType {
+    /// 界面上可选的类型，字符串使用默认长度
+    pub const ALL: [DataType; 13] = [
+        DataType::U16,
+        DataType::I16,
+        DataType::U32,
+        DataType::I32,
+        DataType::U64,
+        DataType::I64,
+        DataType::F32,
+        DataType::F64,
+        DataType::Ascii(8),
+        DataType::Utf16(8),
+        DataType::Bcd16,
+        DataType::Bcd32,
+        DataType::Bits,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DataType::U16 => "u16",
+            DataType::I16 => "i16",
+            DataType::U32 => "u32",
+            DataType::I32 => "i32",
+            DataType::U64 => "u64",
+            DataType::I64 => "i64",
+            DataType::F32 => "f32",
+            DataType::F64 => "f64",
+            DataType::Ascii(_) => "ASCII 字符串",
+            DataType::Utf16(_) => "UTF-16 字符串",
+            DataType::Bcd16 => "BCD 16 位",
+            DataType::Bcd32 => "BCD 32 位",
+            DataType::Bits => "位",
+        }
+    }
+
+    /// 文件中使用的名称，字符串带寄存器个数，例如 `ascii:8`
+    pub fn key(self) -> String {
+        match self {
+            DataType::Ascii(count) => format!("ascii:{}", count),
+            DataType::Utf16(count) => format!("utf16:{}", count),
+            DataType::Bcd16 => "bcd16".to_string(),
+            DataType::Bcd32 => "bcd32".to_string(),
+            DataType::Bits => "bits".to_string(),
+            other => other.label().to_string(),
+        }
+    }
+
+    /// `key` 的逆过程，不区分大小写
+    pub fn from_key(key: &str) -> Option<DataType> {
+        let key = key.trim().to_ascii_lowercase();
+        if let Some((kind, count)) = key.split_once(':') {
+            let count = count.trim().parse().ok().filter(|count| *count > 0)?;
+            return match kind.trim() {
+                "ascii" => Some(DataType::Ascii(count)),
+                "utf16" => Some(DataType::Utf16(count)),
+                _ => None,
+            };
+        }
+        DataType::ALL
+            .into_iter()
+            .filter(|data_type| !matches!(data_type, DataType::Ascii(_) | DataType::Utf16(_)))
+            .find(|data_type| data_type.key() == key)
+    }
+
+    /// 一个值占用的寄存器个数
+    pub fn register_count(self) -> usize {
+        match self {
+            DataType::U16 | DataType::I16 | DataType::Bcd16 | DataType::Bits => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 | DataType::Bcd32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+            DataType::Ascii(count) | DataType::Utf16(count) => count.max(1) as usize,
+        }
+    }
+
+    /// 是否是可以缩放的数值类型
+    pub fn is_numeric(self) -> bool {
+        !matches!(
+            self,
+            DataType::Ascii(_) | DataType::Utf16(_) | DataType::Bits
+        )
+    }
+
+    /// 除字符串长度外是否是同一种类型
+    pub fn same_kind(self, other: DataType) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+/// 数据类型和字节序的组合，界面上按它解释寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ValueFormat {
+    pub data_type: DataType,
+    pub order: ByteOrder,
+}
+
+impl ValueFormat {
+    pub fn decode(&self, registers: &[u16]) -> Result<Value, String> {
+        decode(registers, self.data_type, self.order)
+    }
+
+    /// 解析界面输入并编码成寄存器
+    pub fn encode_text(&self, text: &str) -> Result<Vec<u16>, String> {
+        encode(&parse(text, self.data_type)?, self.data_type, self.order)
+    }
+}
+
+/// 解码后的值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Text(String),
+    Bits(u16),
+}
+
+impl Value {
+    /// 数值类型的值，字符串和位返回 `None`
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Unsigned(value) => Some(*value as f64),
+            Value::Signed(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            Value::Text(_) | Value::Bits(_) => None,
+        }
+    }
+
+    /// 把浮点数转换成数据类型对应的值，整数类型四舍五入
+    pub fn from_f64(value: f64, data_type: DataType) -> Result<Value, String> {
+        if !value.is_finite() {
+            return Err(format!("{} 不是有效的数值", value));
+        }
+        let value = match data_type {
+            DataType::U16 | DataType::U32 | DataType::U64 | DataType::Bcd16 | DataType::Bcd32 => {
+                if value < 0.0 {
+                    return Err(format!("{} 不能是负数", data_type.label()));
+                }
+                Value::Unsigned(value.round() as u64)
+            }
+            DataType::I16 | DataType::I32 | DataType::I64 => Value::Signed(value.round() as i64),
+            DataType::F32 | DataType::F64 => Value::Float(value),
+            DataType::Ascii(_) | DataType::Utf16(_) | DataType::Bits => {
+                return Err(format!("{} 不是数值类型", data_type.label()));
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Unsigned(value) => write!(f, "{}", value),
+            Value::Signed(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Bits(bits) => write!(
+                f,
+                "{:04b} {:04b} {:04b} {:04b}",
+                bits >> 12,
+                (bits >> 8) & 0xF,
+                (bits >> 4) & 0xF,
+                bits & 0xF
+            ),
+        }
+    }
+}
+
+/// 把寄存器按字节序还原成大端字节
+fn to_bytes(registers: &[u16], order: ByteOrder) -> Vec<u8> {
+    let mut words = registers.to_vec();
+    if order.swaps_words() {
+        words.reverse();
+    }
+    words
+        .into_iter()
+        .flat_map(|word| {
+            let word = if order.swaps_bytes() {
+                word.swap_bytes()
+            } else {
+                word
+            };
+            word.to_be_bytes()
+        })
+        .collect()
+}
+
+/// `to_bytes` 的逆过程
+fn from_bytes(bytes: &[u8], order: ByteOrder) -> Vec<u16> {
+    let mut words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| {
+            let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+            if order.swaps_bytes() {
+                word.swap_bytes()
+            } else {
+                word
+            }
+        })
+        .collect();
+    if order.swaps_words() {
+        words.reverse();
+    }
+    words
+}
+
+fn decode_bcd(digits: u64, count: u32) -> Result<u64, String> {
+    let mut value = 0u64;
+    for index in (0..count).rev() {
+        let digit = (digits >> (index * 4)) & 0xF;
+        if digit > 9 {
+            return Err(format!("0x{:X} 不是有效的 BCD 数", digits));
+        }
+        value = value * 10 + digit;
+    }
+    Ok(value)
+}
+
+fn encode_bcd(value: u64, count: u32) -> Result<u64, String> {
+    if value >= 10u64.pow(count) {
+        return Err(format!("{} 超出 {} 位 BCD 的范围", value, count));
+    }
+    let mut digits = 0u64;
+    let mut rest = value;
+    for index in 0..count {
+        digits |= (rest % 10) << (index * 4);
+        rest /= 10;
+    }
+    Ok(digits)
+}
+
+/// 把寄存器解码成值，寄存器个数必须等于类型占用的个数
+pub fn decode(registers: &[u16], data_type: DataType, order: ByteOrder) -> Result<Value, String> {
+    let count = data_type.register_count();
+    if registers.len() != count {
+        return Err(format!(
+            "{} 需要 {} 个寄存器，实际 {} 个",
+            data_type.label(),
+            count,
+            registers.len()
+        ));
+    }
+    let bytes = to_bytes(registers, order);
+    let integer = || {
+        bytes
+            .iter()
+            .fold(0u64, |value, &byte| value << 8 | byte as u64)
+    };
+    let value = match data_type {
+        DataType::U16 | DataType::U32 | DataType::U64 => Value::Unsigned(integer()),
+        DataType::I16 => Value::Signed(integer() as u16 as i16 as i64),
+        DataType::I32 => Value::Signed(integer() as u32 as i32 as i64),
+        DataType::I64 => Value::Signed(integer() as i64),
+        DataType::F32 => Value::Float(f32::from_bits(integer() as u32) as f64),
+        DataType::F64 => Value::Float(f64::from_bits(integer())),
+        DataType::Ascii(_) => {
+            let end = bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(bytes.len());
+            Value::Text(String::from_utf8_lossy(&bytes[..end]).into_owned())
+        }
+        DataType::Utf16(_) => {
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            Value::Text(String::from_utf16_lossy(&units))
+        }
+        DataType::Bcd16 => Value::Unsigned(decode_bcd(integer(), 4)?),
+        DataType::Bcd32 => Value::Unsigned(decode_bcd(integer(), 8)?),
+        DataType::Bits => Value::Bits(integer() as u16),
+    };
+    Ok(value)
+}
+
+/// 把值编码成寄存器，值超出类型范围时返回错误
+pub fn encode(value: &Value, data_type: DataType, order: ByteOrder) -> Result<Vec<u16>, String> {
+    let count = data_type.register_count();
+    let out_of_range = || format!("{} 超出 {} 的范围", value, data_type.label());
+    let integer: u64 = match (data_type, value) {
+        (DataType::Ascii(_), Value::Text(text)) => {
+            let mut bytes = text.as_bytes().to_vec();
+            if bytes.len() > count * 2 {
+                return Err(format!("字符串超过 {} 个字节", count * 2));
+            }
+            bytes.resize(count * 2, 0);
+            return Ok(from_bytes(&bytes, order));
+        }
+        (DataType::Utf16(_), Value::Text(text)) => {
+            let mut units: Vec<u16> = text.encode_utf16().collect();
+            if units.len() > count {
+                return Err(format!("字符串超过 {} 个 UTF-16 码元", count));
+            }
+            units.resize(count, 0);
+            let bytes: Vec<u8> = units.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+            return Ok(from_bytes(&bytes, order));
+        }
+        (DataType::F32, Value::Float(float)) => (*float as f32).to_bits() as u64,
+        (DataType::F64, Value::Float(float)) => float.to_bits(),
+        (DataType::Bits, Value::Bits(bits)) => *bits as u64,
+        (DataType::Bcd16, Value::Unsigned(unsigned)) => encode_bcd(*unsigned, 4)?,
+        (DataType::Bcd32, Value::Unsigned(unsigned)) => encode_bcd(*unsigned, 8)?,
+        (DataType::U16, Value::Unsigned(unsigned)) => {
+            u16::try_from(*unsigned).map_err(|_| out_of_range())? as u64
+        }
+        (DataType::U32, Value::Unsigned(unsigned)) => {
+            u32::try_from(*unsigned).map_err(|_| out_of_range())? as u64
+        }
+        (DataType::U64, Value::Unsigned(unsigned)) => *unsigned,
+        (DataType::I16, Value::Signed(signed)) => {
+            i16::try_from(*signed).map_err(|_| out_of_range())? as u16 as u64
+        }
+        (DataType::I32, Value::Signed(signed)) => {
+            i32::try_from(*signed).map_err(|_| out_of_range())? as u32 as u64
+        }
+        (DataType::I64, Value::Signed(signed)) => *signed as u64,
+        _ => return Err(format!("{} 不能编码为 {}", value, data_type.label())),
+    };
+    let bytes = integer.to_be_bytes();
+    Ok(from_bytes(&bytes[8 - count * 2..], order))
+}
+
+/// 按数据类型解析界面输入的文本
+pub fn parse(text: &str, data_type: DataType) -> Result<Value, String> {
+    let text = text.trim();
+    let invalid = || format!("无法把 \"{}\" 解析为 {}", text, data_type.label());
+    let value = match data_type {
+        DataType::U16 | DataType::U32 | DataType::U64 | DataType::Bcd16 | DataType::Bcd32 => {
+            let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => text.parse(),
+            };
+            Value::Unsigned(parsed.map_err(|_| invalid())?)
+        }
+        DataType::I16 | DataType::I32 | DataType::I64 => {
+            Value::Signed(text.parse().map_err(|_| invalid())?)
+        }
+        DataType::F32 | DataType::F64 => Value::Float(text.parse().map_err(|_| invalid())?),
+        DataType::Ascii(_) | DataType::Utf16(_) => Value::Text(text.to_string()),
+        DataType::Bits => {
+            let digits: String = text
+                .trim_start_matches("0b")
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != '_')
+                .collect();
+            Value::Bits(u16::from_str_radix(&digits, 2).map_err(|_| invalid())?)
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_orders() {
+        let value = Value::Unsigned(0x11223344);
+        let expected = [
+            (ByteOrder::Abcd, [0x1122, 0x3344]),
+            (ByteOrder::Cdab, [0x3344, 0x1122]),
+            (ByteOrder::Badc, [0x2211, 0x4433]),
+            (ByteOrder::Dcba, [0x4433, 0x2211]),
+        ];
+        for (order, registers) in expected {
+            assert_eq!(encode(&value, DataType::U32, order).unwrap(), registers);
+            assert_eq!(decode(&registers, DataType::U32, order).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_all_types() {
+        let cases = [
+            (DataType::U16, Value::Unsigned(65535)),
+            (DataType::I16, Value::Signed(-2)),
+            (DataType::I32, Value::Signed(-100_000)),
+            (DataType::U64, Value::Unsigned(u64::MAX - 1)),
+            (DataType::I64, Value::Signed(i64::MIN)),
+            (DataType::F32, Value::Float(230.5)),
+            (DataType::F64, Value::Float(-0.000123)),
+            (DataType::Ascii(4), Value::Text("METER1".to_string())),
+            (DataType::Utf16(3), Value::Text("电能表".to_string())),
+            (DataType::Bcd16, Value::Unsigned(1234)),
+            (DataType::Bcd32, Value::Unsigned(87654321)),
+            (DataType::Bits, Value::Bits(0b1010_0000_0000_0001)),
+        ];
+        for (data_type, value) in cases {
+            for order in ByteOrder::ALL {
+                let registers = encode(&value, data_type, order).unwrap();
+                assert_eq!(registers.len(), data_type.register_count());
+                assert_eq!(
+                    decode(&registers, data_type, order).unwrap(),
+                    value,
+                    "{:?} {:?}",
+                    data_type,
+                    order
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_encodings() {
+        // 230.5 的 IEEE 754 表示为 0x43668000
+        assert_eq!(
+            encode(&Value::Float(230.5), DataType::F32, ByteOrder::Abcd).unwrap(),
+            vec![0x4366, 0x8000]
+        );
+        assert_eq!(
+            decode(&[0x1234], DataType::Bcd16, ByteOrder::Abcd).unwrap(),
+            Value::Unsigned(1234)
+        );
+        assert!(decode(&[0x12A4], DataType::Bcd16, ByteOrder::Abcd).is_err());
+        assert_eq!(
+            decode(&[0x4142, 0x4300], DataType::Ascii(2), ByteOrder::Abcd).unwrap(),
+            Value::Text("ABC".to_string())
+        );
+        assert!(encode(&Value::Signed(40000), DataType::I16, ByteOrder::Abcd).is_err());
+        assert_eq!(parse("-5", DataType::I32), Ok(Value::Signed(-5)));
+        assert_eq!(parse("0x10", DataType::U16), Ok(Value::Unsigned(16)));
+    }
+}
@@ -0,0 +1,139 @@
+//! 工作模式与任务状态
+//!
+//! 后台任务在某一时刻只运行一种工作模式，模式之间的切换遵循固定的规则；
+//! 任务状态由后台任务发布，界面据此显示连接情况。
+
+use std::fmt;
+
+/// 后台任务的工作模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperatingMode {
+    /// 打开串口但不收发数据
+    #[default]
+    Idle,
+    /// 作为主机发出请求
+    Master,
+    /// 作为从机应答请求
+    Slave,
+    /// 只监听总线，从不发送
+    Monitor,
+    /// 把收到的请求转发给串口上的从机
+    Gateway,
+}
+
+impl OperatingMode {
+    pub const ALL: [OperatingMode; 5] = [
+        OperatingMode::Idle,
+        OperatingMode::Master,
+        OperatingMode::Slave,
+        OperatingMode::Monitor,
+        OperatingMode::Gateway,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OperatingMode::Idle => "空闲",
+            OperatingMode::Master => "主机",
+            OperatingMode::Slave => "从机",
+            OperatingMode::Monitor => "监听",
+            OperatingMode::Gateway => "网关",
+        }
+    }
+
+    /// 当前版本的任务是否能运行该模式
+    pub fn is_supported(self) -> bool {
+        matches!(
+            self,
+            OperatingMode::Idle
+                | OperatingMode::Master
+                | OperatingMode::Slave
+                | OperatingMode::Monitor
+        )
+    }
+
+    /// 是否允许直接从当前模式切换到 `next`
+    ///
+    /// 主机和从机可以互相切换；监听和网关对串口的使用方式不同，
+    /// 进出这两种模式都必须先回到空闲。
+    pub fn can_transition_to(self, next: OperatingMode) -> bool {
+        use OperatingMode::*;
+        match (self, next) {
+            (from, to) if from == to => true,
+            (Idle, _) | (_, Idle) => true,
+            (Master, Slave) | (Slave, Master) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for OperatingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// 拒绝切换模式的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeError {
+    /// 两种模式之间不能直接切换
+    InvalidTransition {
+        from: OperatingMode,
+        to: OperatingMode,
+    },
+    /// 该模式尚未实现
+    Unsupported(OperatingMode),
+    /// 有事务正在进行，切换会打断总线上的帧
+    Busy,
+}
+
+impl fmt::Display for ModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModeError::InvalidTransition { from, to } => {
+                write!(f, "不能从{}模式直接切换到{}模式，请先切换到空闲", from, to)
+            }
+            ModeError::Unsupported(mode) => write!(f, "暂不支持{}模式", mode),
+            ModeError::Busy => write!(f, "有事务正在进行，请稍后再切换模式"),
+        }
+    }
+}
+
+/// 后台任务发布的当前状态
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TaskStatus {
+    /// 没有任务
+    #[default]
+    Stopped,
+    /// 正在打开串口或建立连接
+    Connecting,
+    /// 引擎正在运行
+    Running,
+    /// 任务因错误结束
+    Error(String),
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskStatus::Stopped => write!(f, "已停止"),
+            TaskStatus::Connecting => write!(f, "连接中"),
+            TaskStatus::Running => write!(f, "运行中"),
+            TaskStatus::Error(reason) => write!(f, "错误: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        use OperatingMode::*;
+        assert!(Idle.can_transition_to(Monitor));
+        assert!(Master.can_transition_to(Slave));
+        assert!(Gateway.can_transition_to(Idle));
+        assert!(!Master.can_transition_to(Monitor));
+        assert!(!Monitor.can_transition_to(Gateway));
+    }
+}
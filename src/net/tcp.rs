@@ -0,0 +1,277 @@
+use crate::modbus::frame::Framing;
+use eframe::*;
+use log::info;
+use std::io;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Debug)]
+pub struct TcpConnection {
+    settings: TcpSettings,
+    //是否开启、关闭连接的标志
+    is_open: Arc<AtomicBool>,
+    //最近一次连接失败的原因
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSettings {
+    /// 目标设备的主机名或 IP 地址
+    pub host: String,
+    /// 目标设备的端口，Modbus TCP 默认为 502
+    pub port: u16,
+    /// 请求默认使用的站号
+    pub unit_id: u8,
+    /// 报文的帧格式，串口服务器透传时使用 RTU 或 ASCII
+    pub framing: Framing,
+    /// 建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 等待应答的超时时间
+    pub timeout: Duration,
+    /// 从机模式监听的地址
+    pub listen_host: String,
+    /// 从机模式监听的端口
+    pub listen_port: u16,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        TcpSettings {
+            host: "127.0.0.1".to_string(),
+            port: 502,
+            unit_id: 1,
+            framing: Framing::Tcp,
+            connect_timeout: Duration::from_millis(3000),
+            timeout: Duration::from_millis(1000),
+            listen_host: "0.0.0.0".to_string(),
+            listen_port: 502,
+        }
+    }
+}
+
+impl TcpSettings {
+    /// 连接目标设备，超过 `connect_timeout` 视为失败
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        let connect = TcpStream::connect((self.host.as_str(), self.port));
+        let stream = tokio::time::timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "连接超时"))??;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// 在从机监听地址上开始监听
+    pub async fn listen(&self) -> io::Result<TcpListener> {
+        TcpListener::bind((self.listen_host.as_str(), self.listen_port)).await
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", self.listen_host, self.listen_port)
+    }
+}
+
+/// 将连接失败的错误转换为界面上显示的提示
+pub fn describe_connect_error(err: &io::Error) -> String {
+    let reason = match err.kind() {
+        io::ErrorKind::ConnectionRefused => "连接被拒绝",
+        io::ErrorKind::TimedOut => "连接超时",
+        io::ErrorKind::NotFound => "无法解析主机名",
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => "连接被断开",
+        io::ErrorKind::AddrInUse => "端口已被占用",
+        io::ErrorKind::AddrNotAvailable => "监听地址不可用",
+        io::ErrorKind::PermissionDenied => "没有权限监听该端口",
+        _ => "网络错误",
+    };
+    format!("{}: {}", reason, err)
+}
+
+impl Default for TcpConnection {
+    fn default() -> Self {
+        Self {
+            settings: TcpSettings::default(),
+            is_open: Arc::new(AtomicBool::new(false)),
+            last_error: None,
+        }
+    }
+}
+
+impl TcpConnection {
+    pub fn is_connected(&self) -> bool {
+        self.is_open.load(Ordering::Relaxed)
+    }
+
+    pub fn settings(&self) -> TcpSettings {
+        self.settings.clone()
+    }
+
+    /// 替换全部设置，只在断开时调用，下次连接生效
+    pub fn set_settings(&mut self, settings: TcpSettings) {
+        self.settings = settings;
+    }
+
+    /// 记录连接失败的原因，并把连接状态恢复为断开
+    pub fn set_error(&mut self, message: String) {
+        self.is_open.store(false, Ordering::Relaxed);
+        self.last_error = Some(message);
+    }
+
+    pub fn clear_error(&mut self) {
+        self.last_error = None;
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.show_connection_buttons(ui);
+            ui.separator();
+
+            // 连接期间设置不可修改，断开后重新连接生效
+            ui.add_enabled_ui(!self.is_connected(), |ui| {
+                egui::Grid::new("tcp_settings_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        self.show_address_input(ui);
+                        self.show_unit_id_input(ui);
+                        self.show_framing_selector(ui);
+                        self.show_timeout_inputs(ui);
+                        self.show_listen_input(ui);
+                    });
+            });
+        });
+    }
+
+    fn show_address_input(&mut self, ui: &mut egui::Ui) {
+        ui.label("主机地址:");
+        ui.text_edit_singleline(&mut self.settings.host);
+        ui.end_row();
+
+        ui.label("端口:");
+        ui.add(egui::DragValue::new(&mut self.settings.port));
+        ui.end_row();
+    }
+
+    fn show_unit_id_input(&mut self, ui: &mut egui::Ui) {
+        ui.label("站号:");
+        ui.add(egui::DragValue::new(&mut self.settings.unit_id).range(0..=255));
+        ui.end_row();
+    }
+
+    fn show_framing_selector(&mut self, ui: &mut egui::Ui) {
+        ui.label("帧格式:");
+        egui::ComboBox::from_id_salt("tcp_framing_selector")
+            .selected_text(self.settings.framing.label())
+            .show_ui(ui, |ui| {
+                for framing in Framing::TCP {
+                    let label = match framing {
+                        Framing::Tcp => framing.label(),
+                        Framing::Rtu => "RTU over TCP",
+                        Framing::Ascii => "ASCII over TCP",
+                    };
+                    ui.selectable_value(&mut self.settings.framing, framing, label);
+                }
+            });
+        ui.end_row();
+    }
+
+    fn show_timeout_inputs(&mut self, ui: &mut egui::Ui) {
+        ui.label("连接超时:");
+        let mut connect_ms = self.settings.connect_timeout.as_millis() as u64;
+        if ui
+            .add(egui::DragValue::new(&mut connect_ms).speed(10).suffix("ms"))
+            .changed()
+        {
+            self.settings.connect_timeout = Duration::from_millis(connect_ms);
+        }
+        ui.end_row();
+
+        ui.label("应答超时:");
+        let mut timeout_ms = self.settings.timeout.as_millis() as u64;
+        if ui
+            .add(egui::DragValue::new(&mut timeout_ms).speed(10).suffix("ms"))
+            .changed()
+        {
+            self.settings.timeout = Duration::from_millis(timeout_ms);
+        }
+        ui.end_row();
+    }
+
+    /// 从机模式下作为 Modbus TCP 服务端监听的地址
+    fn show_listen_input(&mut self, ui: &mut egui::Ui) {
+        ui.label("从机监听地址:");
+        ui.text_edit_singleline(&mut self.settings.listen_host);
+        ui.end_row();
+
+        ui.label("从机监听端口:");
+        ui.add(egui::DragValue::new(&mut self.settings.listen_port));
+        ui.end_row();
+    }
+
+    fn show_connection_buttons(&mut self, ui: &mut egui::Ui) {
+        // 连接/断开按钮
+        ui.horizontal(|ui| {
+            if self.is_open.load(Ordering::Relaxed) {
+                if ui
+                    .add(egui::Button::new("断开").fill(ui.visuals().selection.bg_fill))
+                    .clicked()
+                {
+                    self.is_open.store(false, Ordering::Relaxed);
+                    info!("断开 TCP 连接: {}", self.settings.address());
+                }
+            } else if ui.add(egui::Button::new("连接")).clicked() {
+                info!(
+                    "连接 TCP: {}, 站号: {}, 连接超时: {:?}ms",
+                    self.settings.address(),
+                    self.settings.unit_id,
+                    self.settings.connect_timeout.as_millis()
+                );
+                // 真正的连接动作由任务完成，失败时通过 set_error 回报
+                self.last_error = None;
+                self.is_open.store(true, Ordering::Relaxed);
+            }
+            if self.is_open.load(Ordering::Relaxed) {
+                ui.colored_label(egui::Color32::from_rgb(50, 220, 50), "●");
+            } else if let Some(error) = &self.last_error {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), "●");
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), error);
+            } else {
+                ui.colored_label(egui::Color32::from_rgb(150, 150, 150), "●");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_local_listener() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let settings = TcpSettings {
+            port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        assert!(settings.connect().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_listen_port_in_use() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let settings = TcpSettings {
+            listen_host: "127.0.0.1".to_string(),
+            listen_port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let err = settings.listen().await.unwrap_err();
+        assert!(describe_connect_error(&err).starts_with("端口已被占用"));
+    }
+}
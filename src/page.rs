@@ -5,6 +5,7 @@ pub enum Page {
     Home,
     Slave,
     Master,
+    Traffic,
 }
 
 impl Default for Page {
@@ -0,0 +1,452 @@
+//! 连接配置
+//!
+//! 把串口或 TCP 的全部设置按名称保存在用户配置目录下的 `profiles.json` 中，
+//! 主页上选择后加载。最近使用的配置名通过 eframe 的持久化保存，启动时恢复。
+
+use crate::modbus::frame::Framing;
+use crate::net::TcpSettings;
+use crate::serial::PortSettings;
+use eframe::*;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_serial::{DataBits, FlowControl, Parity, StopBits};
+
+/// eframe 持久化中保存最近使用的配置名的键
+pub const LAST_PROFILE_KEY: &str = "last_profile";
+/// 当前的配置文件版本
+const VERSION: u64 = 1;
+/// 用户配置目录下本程序的目录
+pub const APP_DIR: &str = "modbus_tool";
+const PROFILE_FILE: &str = "profiles.json";
+
+/// 一个配置保存的连接设置
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connection {
+    Serial(PortSettings),
+    Tcp(TcpSettings),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub connection: Connection,
+}
+
+impl Profile {
+    fn to_json(&self) -> Value {
+        let mut object = match &self.connection {
+            Connection::Serial(settings) => serial_to_json(settings),
+            Connection::Tcp(settings) => tcp_to_json(settings),
+        };
+        object["name"] = json!(self.name);
+        object
+    }
+
+    fn from_json(value: &Value) -> Result<Profile, String> {
+        let name = value
+            .get("name")
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty())
+            .ok_or("缺少配置名")?;
+        let connection = match value.get("kind").and_then(Value::as_str) {
+            Some("serial") => Connection::Serial(serial_from_json(value)?),
+            Some("tcp") => Connection::Tcp(tcp_from_json(value)?),
+            _ => return Err(format!("配置 {} 的 kind 必须是 serial 或 tcp", name)),
+        };
+        Ok(Profile {
+            name: name.to_string(),
+            connection,
+        })
+    }
+}
+
+/// 配置文件的路径，系统没有用户配置目录时返回 `None`
+pub fn profile_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(PROFILE_FILE))
+}
+
+pub fn profiles_to_json(profiles: &[Profile]) -> String {
+    let profiles: Vec<Value> = profiles.iter().map(Profile::to_json).collect();
+    let root = json!({ "version": VERSION, "profiles": profiles });
+    serde_json::to_string_pretty(&root).unwrap_or_default()
+}
+
+pub fn profiles_from_json(text: &str) -> Result<Vec<Profile>, String> {
+    let root: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    if let Some(version) = root.get("version")
+        && version.as_u64().is_none_or(|version| version > VERSION)
+    {
+        return Err(format!("不支持的版本 {}", version));
+    }
+    root.get("profiles")
+        .and_then(Value::as_array)
+        .ok_or("缺少 profiles 数组")?
+        .iter()
+        .map(Profile::from_json)
+        .collect()
+}
+
+pub(crate) fn serial_to_json(settings: &PortSettings) -> Value {
+    json!({
+        "kind": "serial",
+        "path": settings.path,
+        "baud_rate": settings.baud_rate,
+        "data_bits": match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        },
+        "parity": match settings.parity {
+            Parity::None => "none",
+            Parity::Odd => "odd",
+            Parity::Even => "even",
+        },
+        "stop_bits": match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        },
+        "flow_control": match settings.flow_control {
+            FlowControl::None => "none",
+            FlowControl::Software => "software",
+            FlowControl::Hardware => "hardware",
+        },
+        "timeout_ms": settings.timeout.as_millis() as u64,
+        "inter_frame_gap_ms": settings.inter_frame_gap.as_millis() as u64,
+        "dtr_on_open": settings.dtr_on_open,
+        "framing": settings.framing.key(),
+    })
+}
+
+/// 缺少的字段使用默认值，取值不合法时返回错误
+pub(crate) fn serial_from_json(value: &Value) -> Result<PortSettings, String> {
+    let fields = Fields(value);
+    let default = PortSettings::default();
+    Ok(PortSettings {
+        path: fields.string("path")?.unwrap_or(default.path),
+        baud_rate: fields.number("baud_rate")?.unwrap_or(default.baud_rate),
+        data_bits: match fields.number::<u8>("data_bits")? {
+            None => default.data_bits,
+            Some(5) => DataBits::Five,
+            Some(6) => DataBits::Six,
+            Some(7) => DataBits::Seven,
+            Some(8) => DataBits::Eight,
+            Some(other) => return Err(format!("无效的数据位 {}", other)),
+        },
+        parity: match fields.string("parity")?.as_deref() {
+            None => default.parity,
+            Some("none") => Parity::None,
+            Some("odd") => Parity::Odd,
+            Some("even") => Parity::Even,
+            Some(other) => return Err(format!("无效的校验位 {}", other)),
+        },
+        stop_bits: match fields.number::<u8>("stop_bits")? {
+            None => default.stop_bits,
+            Some(1) => StopBits::One,
+            Some(2) => StopBits::Two,
+            Some(other) => return Err(format!("无效的停止位 {}", other)),
+        },
+        flow_control: match fields.string("flow_control")?.as_deref() {
+            None => default.flow_control,
+            Some("none") => FlowControl::None,
+            Some("software") => FlowControl::Software,
+            Some("hardware") => FlowControl::Hardware,
+            Some(other) => return Err(format!("无效的流控制 {}", other)),
+        },
+        timeout: fields.millis("timeout_ms")?.unwrap_or(default.timeout),
+        inter_frame_gap: fields
+            .millis("inter_frame_gap_ms")?
+            .unwrap_or(default.inter_frame_gap),
+        dtr_on_open: value.get("dtr_on_open").and_then(Value::as_bool),
+        framing: match fields.framing()? {
+            Some(Framing::Tcp) => return Err("串口不能使用 TCP 帧格式".to_string()),
+            Some(framing) => framing,
+            None => default.framing,
+        },
+    })
+}
+
+pub(crate) fn tcp_to_json(settings: &TcpSettings) -> Value {
+    json!({
+        "kind": "tcp",
+        "host": settings.host,
+        "port": settings.port,
+        "unit_id": settings.unit_id,
+        "framing": settings.framing.key(),
+        "connect_timeout_ms": settings.connect_timeout.as_millis() as u64,
+        "timeout_ms": settings.timeout.as_millis() as u64,
+        "listen_host": settings.listen_host,
+        "listen_port": settings.listen_port,
+    })
+}
+
+pub(crate) fn tcp_from_json(value: &Value) -> Result<TcpSettings, String> {
+    let fields = Fields(value);
+    let default = TcpSettings::default();
+    Ok(TcpSettings {
+        host: fields.string("host")?.unwrap_or(default.host),
+        port: fields.number("port")?.unwrap_or(default.port),
+        unit_id: fields.number("unit_id")?.unwrap_or(default.unit_id),
+        framing: fields.framing()?.unwrap_or(default.framing),
+        connect_timeout: fields
+            .millis("connect_timeout_ms")?
+            .unwrap_or(default.connect_timeout),
+        timeout: fields.millis("timeout_ms")?.unwrap_or(default.timeout),
+        listen_host: fields.string("listen_host")?.unwrap_or(default.listen_host),
+        listen_port: fields.number("listen_port")?.unwrap_or(default.listen_port),
+    })
+}
+
+/// 读取 JSON 对象的字段，缺少或为 null 时返回 `None`
+struct Fields<'a>(&'a Value);
+
+impl Fields<'_> {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key).filter(|value| !value.is_null())
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, String> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("{} 必须是字符串", key))
+            })
+            .transpose()
+    }
+
+    fn number<T: TryFrom<u64>>(&self, key: &str) -> Result<Option<T>, String> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .as_u64()
+                    .and_then(|number| T::try_from(number).ok())
+                    .ok_or_else(|| format!("{} 的值 {} 无效", key, value))
+            })
+            .transpose()
+    }
+
+    fn millis(&self, key: &str) -> Result<Option<Duration>, String> {
+        Ok(self.number(key)?.map(Duration::from_millis))
+    }
+
+    fn framing(&self) -> Result<Option<Framing>, String> {
+        self.string("framing")?
+            .map(|key| Framing::from_key(&key).ok_or_else(|| format!("无效的帧格式 {}", key)))
+            .transpose()
+    }
+}
+
+/// 主页上的配置选择，配置在保存或删除时立即写入文件
+#[derive(Debug, Default)]
+pub struct ProfileManager {
+    profiles: Vec<Profile>,
+    path: Option<PathBuf>,
+    //下拉框中选中的配置名
+    selected: String,
+    //保存当前设置时使用的配置名
+    new_name: String,
+    //最近一次加载或保存的配置名，由 eframe 持久化
+    last_used: Option<String>,
+    //最近一次读写配置文件的结果
+    message: Option<Result<String, String>>,
+}
+
+impl ProfileManager {
+    /// 读取用户配置目录下的配置文件，文件不存在时为空
+    pub fn load() -> Self {
+        let mut manager = ProfileManager {
+            path: profile_path(),
+            ..Default::default()
+        };
+        let Some(path) = &manager.path else {
+            manager.message = Some(Err("找不到用户配置目录，配置无法保存".to_string()));
+            return manager;
+        };
+        match std::fs::read_to_string(path) {
+            Ok(text) => match profiles_from_json(&text) {
+                Ok(profiles) => manager.profiles = profiles,
+                Err(err) => {
+                    log::error!("读取配置文件 {} 失败: {}", path.display(), err);
+                    manager.message = Some(Err(format!("配置文件无效: {}", err)));
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => manager.message = Some(Err(format!("读取配置文件失败: {}", err))),
+        }
+        if let Some(first) = manager.profiles.first() {
+            manager.selected = first.name.clone();
+        }
+        manager
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn last_used(&self) -> Option<&str> {
+        self.last_used.as_deref()
+    }
+
+    pub fn set_last_used(&mut self, name: Option<String>) {
+        if let Some(name) = &name {
+            self.selected = name.clone();
+            self.new_name = name.clone();
+        }
+        self.last_used = name;
+    }
+
+    /// 启动时恢复最近使用的配置，返回其连接设置
+    pub fn restore(&mut self, name: &str) -> Option<Connection> {
+        let connection = self.get(name)?.connection.clone();
+        log::info!("恢复最近使用的配置: {}", name);
+        self.selected = name.to_string();
+        self.new_name = name.to_string();
+        self.last_used = Some(name.to_string());
+        Some(connection)
+    }
+
+    fn write(&mut self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or("找不到用户配置目录")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(path, profiles_to_json(&self.profiles)).map_err(|err| err.to_string())
+    }
+
+    /// 显示配置选择，点击加载时返回要应用的连接设置
+    ///
+    /// `enabled` 为 `false`（已连接）时只能保存，不能加载。
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        enabled: bool,
+        current: impl FnOnce() -> Connection,
+    ) -> Option<Connection> {
+        let mut loaded = None;
+        ui.horizontal(|ui| {
+            ui.label("连接配置:");
+            egui::ComboBox::from_id_salt("profile_selector")
+                .selected_text(&self.selected)
+                .show_ui(ui, |ui| {
+                    for profile in &self.profiles {
+                        let kind = match profile.connection {
+                            Connection::Serial(_) => "串口",
+                            Connection::Tcp(_) => "TCP",
+                        };
+                        ui.selectable_value(
+                            &mut self.selected,
+                            profile.name.clone(),
+                            format!("{} ({})", profile.name, kind),
+                        );
+                    }
+                });
+            let exists = self.get(&self.selected).is_some();
+            if ui
+                .add_enabled(enabled && exists, egui::Button::new("加载"))
+                .clicked()
+            {
+                loaded = self.restore(&self.selected.clone());
+            }
+            if ui.add_enabled(exists, egui::Button::new("删除")).clicked() {
+                let name = std::mem::take(&mut self.selected);
+                self.profiles.retain(|profile| profile.name != name);
+                if self.last_used.as_deref() == Some(name.as_str()) {
+                    self.last_used = None;
+                }
+                self.message = Some(
+                    self.write()
+                        .map(|()| format!("已删除配置 {}", name))
+                        .map_err(|err| format!("保存配置文件失败: {}", err)),
+                );
+            }
+            ui.separator();
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_name)
+                    .hint_text("配置名")
+                    .desired_width(120.0),
+            );
+            let name = self.new_name.trim().to_string();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("保存当前设置"))
+                .clicked()
+            {
+                let profile = Profile {
+                    name: name.clone(),
+                    connection: current(),
+                };
+                match self
+                    .profiles
+                    .iter_mut()
+                    .find(|profile| profile.name == name)
+                {
+                    Some(existing) => *existing = profile,
+                    None => self.profiles.push(profile),
+                }
+                self.selected = name.clone();
+                self.last_used = Some(name.clone());
+                log::info!("保存连接配置: {}", name);
+                self.message = Some(
+                    self.write()
+                        .map(|()| format!("已保存配置 {}", name))
+                        .map_err(|err| format!("保存配置文件失败: {}", err)),
+                );
+            }
+        });
+        match &self.message {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(err)) => {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+            }
+            None => {}
+        }
+        loaded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_round_trip() {
+        let profiles = vec![
+            Profile {
+                name: "仪表".to_string(),
+                connection: Connection::Serial(PortSettings {
+                    path: "/dev/ttyUSB0".to_string(),
+                    baud_rate: 19200,
+                    data_bits: DataBits::Seven,
+                    parity: Parity::Even,
+                    stop_bits: StopBits::Two,
+                    timeout: Duration::from_millis(250),
+                    dtr_on_open: Some(true),
+                    framing: Framing::Ascii,
+                    ..Default::default()
+                }),
+            },
+            Profile {
+                name: "网关".to_string(),
+                connection: Connection::Tcp(TcpSettings {
+                    host: "192.168.1.10".to_string(),
+                    unit_id: 7,
+                    framing: Framing::Rtu,
+                    ..Default::default()
+                }),
+            },
+        ];
+        let text = profiles_to_json(&profiles);
+        assert_eq!(profiles_from_json(&text), Ok(profiles));
+
+        assert!(
+            profiles_from_json(
+                r#"{"profiles": [{"name": "a", "kind": "serial", "data_bits": 9}]}"#
+            )
+            .is_err()
+        );
+    }
+}
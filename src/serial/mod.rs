@@ -1,4 +1,6 @@
 pub mod cmd;
 pub mod port;
+pub mod shared;
 
 pub use port::*;
+pub use shared::SharedPort;
@@ -1,3 +1,4 @@
+use crate::modbus::frame::Framing;
 use eframe::*;
 use log::info;
 use std::sync::{
@@ -5,8 +6,14 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
 };
 use std::time::Duration;
+use tokio_serial::SerialPort as _;
 use tokio_serial::*;
 
+/// 界面提供的常用波特率，参数识别也按这个列表尝试
+pub const BAUD_RATES: [u32; 13] = [
+    300, 600, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600,
+];
+
 #[derive(Debug)]
 pub struct SerialPort {
     list: Vec<(String, String)>,
@@ -17,9 +24,11 @@ pub struct SerialPort {
     is_open: Arc<AtomicBool>,
     //port打开之后，设置发生变化了需要更新设置
     need_update: Arc<AtomicBool>,
+    //最近一次打开串口失败的原因
+    last_error: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PortSettings {
     /// The port name, usually the device path
     pub path: String,
@@ -35,8 +44,12 @@ pub struct PortSettings {
     pub stop_bits: StopBits,
     /// Amount of time to wait to receive data before timing out
     pub timeout: Duration,
+    /// 主机连续发送两帧之间的最小间隔
+    pub inter_frame_gap: Duration,
     /// The state to set DTR to when opening the device
     pub dtr_on_open: Option<bool>,
+    /// 报文的帧格式，RTU 或 ASCII
+    pub framing: Framing,
 }
 
 impl Default for PortSettings {
@@ -49,11 +62,143 @@ impl Default for PortSettings {
             parity: Parity::None,
             stop_bits: StopBits::One,
             timeout: Duration::from_millis(100),
+            inter_frame_gap: Duration::ZERO,
             dtr_on_open: None,
+            framing: Framing::Rtu,
         }
     }
 }
 
+impl PortSettings {
+    /// 按当前设置打开串口，需要在 Tokio 运行时上下文中调用
+    pub fn open(&self) -> tokio_serial::Result<SerialStream> {
+        let mut builder = tokio_serial::new(&self.path, self.baud_rate)
+            .data_bits(self.data_bits)
+            .flow_control(self.flow_control)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .timeout(self.timeout);
+        if let Some(dtr) = self.dtr_on_open {
+            builder = builder.dtr_on_open(dtr);
+        }
+        builder.open_native_async()
+    }
+
+    /// 以监听方式打开串口，不驱动 DTR/RTS，也不使用硬件流控
+    ///
+    /// 串口设备无法只读打开，操作系统在打开时通常会拉高 DTR/RTS，因此打开后立即释放，
+    /// 避免改变 RS-485 转换器的收发方向或让接在控制线上的设备复位。
+    pub fn open_monitor(&self) -> tokio_serial::Result<SerialStream> {
+        let mut stream = tokio_serial::new(&self.path, self.baud_rate)
+            .data_bits(self.data_bits)
+            .flow_control(FlowControl::None)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .timeout(self.timeout)
+            .dtr_on_open(false)
+            .open_native_async()?;
+        release_control_lines(&mut stream)?;
+        Ok(stream)
+    }
+
+    /// 将设置应用到已打开的串口上，路径变化无法原地生效，需要重新打开
+    pub fn apply(&self, port: &mut SerialStream) -> tokio_serial::Result<()> {
+        port.set_baud_rate(self.baud_rate)?;
+        port.set_data_bits(self.data_bits)?;
+        port.set_flow_control(self.flow_control)?;
+        port.set_parity(self.parity)?;
+        port.set_stop_bits(self.stop_bits)?;
+        port.set_timeout(self.timeout)?;
+        if let Some(dtr) = self.dtr_on_open {
+            port.write_data_terminal_ready(dtr)?;
+        }
+        Ok(())
+    }
+
+    /// 传输一个字符的时间：起始位 + 数据位 + 校验位 + 停止位
+    pub fn char_time(&self) -> Duration {
+        let data = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Odd | Parity::Even => 1,
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let bits = 1 + data + parity + stop;
+        Duration::from_secs_f64(bits as f64 / self.baud_rate.max(1) as f64)
+    }
+
+    /// RTU 帧之间的静默间隔 t3.5，波特率高于 19200 时按规范固定为 1.75 ms
+    pub fn silent_interval(&self) -> Duration {
+        if self.baud_rate > 19200 {
+            Duration::from_micros(1750)
+        } else {
+            self.char_time().mul_f64(3.5)
+        }
+    }
+}
+
+/// 参数识别尝试的一组线路参数，数据位沿用当前设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineParams {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl LineParams {
+    pub const PARITIES: [Parity; 3] = [Parity::None, Parity::Even, Parity::Odd];
+    pub const STOP_BITS: [StopBits; 2] = [StopBits::One, StopBits::Two];
+
+    pub fn apply_to(self, settings: &mut PortSettings) {
+        settings.baud_rate = self.baud_rate;
+        settings.parity = self.parity;
+        settings.stop_bits = self.stop_bits;
+    }
+}
+
+impl std::fmt::Display for LineParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parity = match self.parity {
+            Parity::None => "N",
+            Parity::Odd => "O",
+            Parity::Even => "E",
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(f, "{} {}{}", self.baud_rate, parity, stop)
+    }
+}
+
+/// 关闭硬件流控并释放 DTR/RTS，监听模式使用已打开的串口前调用
+pub fn release_control_lines(port: &mut SerialStream) -> tokio_serial::Result<()> {
+    port.set_flow_control(FlowControl::None)?;
+    port.write_data_terminal_ready(false)?;
+    port.write_request_to_send(false)
+}
+
+/// 将打开或设置串口 `path` 的错误转换为界面上显示的提示
+pub fn describe_open_error(path: &str, err: &tokio_serial::Error) -> String {
+    let reason = match err.kind() {
+        ErrorKind::NoDevice => "设备不存在或已被占用",
+        ErrorKind::InvalidInput => "串口参数无效",
+        ErrorKind::Io(std::io::ErrorKind::PermissionDenied) => "没有访问权限",
+        ErrorKind::Io(std::io::ErrorKind::NotFound) => "设备不存在",
+        ErrorKind::Io(_) => "I/O 错误",
+        ErrorKind::Unknown => "未知错误",
+    };
+    format!("串口 {} {}: {}", path, reason, err.description)
+}
+
 impl Default for SerialPort {
     fn default() -> Self {
         let mut port = SerialPort {
@@ -62,6 +207,7 @@ impl Default for SerialPort {
             settings: Arc::new(Mutex::new(PortSettings::default())),
             is_open: Arc::new(AtomicBool::new(false)),
             need_update: Arc::new(AtomicBool::new(false)),
+            last_error: None,
         };
         port.list_ports();
         port.selected = port
@@ -102,6 +248,52 @@ impl SerialPort {
         self.is_open.load(Ordering::Relaxed)
     }
 
+    /// 当前串口设置的快照
+    pub fn settings(&self) -> PortSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// 与串口任务共享的设置，任务通过 need_update 感知其变化
+    pub fn shared_settings(&self) -> Arc<Mutex<PortSettings>> {
+        self.settings.clone()
+    }
+
+    /// 替换全部设置，例如加载连接配置时
+    pub fn set_settings(&mut self, settings: PortSettings) {
+        self.selected = settings.path.clone();
+        *self.settings.lock().unwrap() = settings;
+        self.need_update.store(true, Ordering::Relaxed);
+    }
+
+    /// 应用参数识别找到的波特率、校验位和停止位，已打开的串口随之更新
+    pub fn set_line_params(&mut self, params: LineParams) {
+        let mut settings = self.settings.lock().unwrap();
+        info!(
+            "应用识别出的参数: {} {:?} {:?}",
+            params.baud_rate, params.parity, params.stop_bits
+        );
+        params.apply_to(&mut settings);
+        self.need_update.store(true, Ordering::Relaxed);
+    }
+
+    pub fn need_update_flag(&self) -> Arc<AtomicBool> {
+        self.need_update.clone()
+    }
+
+    /// 记录打开失败的原因，并把连接状态恢复为断开
+    pub fn set_error(&mut self, message: String) {
+        self.is_open.store(false, Ordering::Relaxed);
+        self.last_error = Some(message);
+    }
+
+    pub fn clear_error(&mut self) {
+        self.last_error = None;
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
     pub fn show(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             self.show_connection_buttons(ui);
@@ -119,7 +311,9 @@ impl SerialPort {
                     self.show_parity_selector(ui);
                     self.show_flow_control_selector(ui);
                     self.show_timeout_input(ui);
+                    self.show_gap_input(ui);
                     self.show_dtr_checkbox(ui);
+                    self.show_framing_selector(ui);
                 });
         });
     }
@@ -146,6 +340,7 @@ impl SerialPort {
         // 检查端口是否被修改
         if old_selected != self.selected {
             info!("端口选择修改: {} -> {}", old_selected, self.selected);
+            self.settings.lock().unwrap().path = self.selected.clone();
             self.need_update.store(true, Ordering::Relaxed);
         }
 
@@ -159,11 +354,7 @@ impl SerialPort {
         egui::ComboBox::from_id_salt("baud_rate_selector")
             .selected_text(format!("{}", settings.baud_rate))
             .show_ui(ui, |ui| {
-                let common_baud_rates = [
-                    300, 600, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800,
-                    921600,
-                ];
-                for &baud_rate in &common_baud_rates {
+                for &baud_rate in &BAUD_RATES {
                     ui.selectable_value(
                         &mut settings.baud_rate,
                         baud_rate,
@@ -180,6 +371,24 @@ impl SerialPort {
         ui.end_row();
     }
 
+    fn show_framing_selector(&mut self, ui: &mut egui::Ui) {
+        ui.label("帧格式:");
+        let mut settings = self.settings.lock().unwrap();
+        let old_framing = settings.framing;
+        egui::ComboBox::from_id_salt("framing_selector")
+            .selected_text(settings.framing.label())
+            .show_ui(ui, |ui| {
+                for framing in Framing::SERIAL {
+                    ui.selectable_value(&mut settings.framing, framing, framing.label());
+                }
+            });
+        if old_framing != settings.framing {
+            info!("帧格式修改: {} -> {}", old_framing, settings.framing);
+            self.need_update.store(true, Ordering::Relaxed);
+        }
+        ui.end_row();
+    }
+
     fn show_data_bits_selector(&mut self, ui: &mut egui::Ui) {
         ui.label("数据位:");
         let mut settings = self.settings.lock().unwrap();
@@ -300,6 +509,25 @@ impl SerialPort {
         ui.end_row();
     }
 
+    fn show_gap_input(&mut self, ui: &mut egui::Ui) {
+        ui.label("帧间隔:");
+        let gap_ms = {
+            let settings = self.settings.lock().unwrap();
+            settings.inter_frame_gap.as_millis() as u32
+        };
+        let mut gap_ms_mut = gap_ms;
+        if ui
+            .add(egui::DragValue::new(&mut gap_ms_mut).speed(1).suffix("ms"))
+            .changed()
+        {
+            // 主机每次发送前读取，无需重新配置串口
+            let mut settings = self.settings.lock().unwrap();
+            settings.inter_frame_gap = Duration::from_millis(gap_ms_mut as u64);
+            info!("帧间隔修改: {}ms -> {}ms", gap_ms, gap_ms_mut);
+        }
+        ui.end_row();
+    }
+
     fn show_dtr_checkbox(&mut self, ui: &mut egui::Ui) {
         ui.label("DTR状态:");
         let dtr_enabled = {
@@ -324,16 +552,16 @@ impl SerialPort {
                     .add(egui::Button::new("断开").fill(ui.visuals().selection.bg_fill))
                     .clicked()
                 {
+                    // 任务检测到断开后会释放串口
                     self.is_open.store(false, Ordering::Relaxed);
                     info!("断开串口连接: {}", self.selected);
-                    // TODO: 实现断开逻辑
                 }
             } else {
                 if ui.add(egui::Button::new("连接")).clicked() {
-                    self.is_open.store(true, Ordering::Relaxed);
-                    let settings = self.settings.lock().unwrap();
+                    let mut settings = self.settings.lock().unwrap();
+                    settings.path = self.selected.clone();
                     info!("连接串口: {}, 波特率: {}, 数据位: {:?}, 停止位: {:?}, 校验位: {:?}, 流控制: {:?}, 超时: {:?}ms, DTR: {:?}",
-                          self.selected,
+                          settings.path,
                           settings.baud_rate,
                           settings.data_bits,
                           settings.stop_bits,
@@ -341,11 +569,16 @@ impl SerialPort {
                           settings.flow_control,
                           settings.timeout.as_millis(),
                           settings.dtr_on_open);
-                    // TODO: 实现连接逻辑
+                    // 真正的打开动作由任务管理器完成，失败时通过 set_error 回报
+                    self.last_error = None;
+                    self.is_open.store(true, Ordering::Relaxed);
                 }
             }
             if self.is_open.load(Ordering::Relaxed) {
                 ui.colored_label(egui::Color32::from_rgb(50, 220, 50), "●");
+            } else if let Some(error) = &self.last_error {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), "●");
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), error);
             } else {
                 ui.colored_label(egui::Color32::from_rgb(150, 150, 150), "●");
             }
@@ -384,4 +617,33 @@ mod tests {
         port.list_ports();
         println!("{:?}", port.list);
     }
+
+    #[tokio::test]
+    async fn test_open_missing_device() {
+        let settings = PortSettings {
+            path: "/dev/modbus_tool_missing".to_string(),
+            ..Default::default()
+        };
+        let err = settings.open().unwrap_err();
+        let message = describe_open_error(&settings.path, &err);
+        assert!(message.contains("/dev/modbus_tool_missing"), "{}", message);
+        assert!(message.contains("设备不存在"), "{}", message);
+    }
+
+    #[test]
+    fn test_describe_open_error() {
+        let denied = tokio_serial::Error::new(
+            ErrorKind::Io(std::io::ErrorKind::PermissionDenied),
+            "Permission denied",
+        );
+        assert_eq!(
+            describe_open_error("/dev/ttyUSB0", &denied),
+            "串口 /dev/ttyUSB0 没有访问权限: Permission denied"
+        );
+        let busy = tokio_serial::Error::new(ErrorKind::NoDevice, "Device or resource busy");
+        assert_eq!(
+            describe_open_error("COM3", &busy),
+            "串口 COM3 设备不存在或已被占用: Device or resource busy"
+        );
+    }
 }
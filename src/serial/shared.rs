@@ -0,0 +1,52 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_serial::SerialStream;
+
+/// 可在多个使用者之间共享的串口
+///
+/// Modbus 引擎需要独占一个 `'static` 的传输层，而任务还要在引擎运行时修改串口参数，
+/// 因此把串口放在锁里，读写时短暂加锁。
+#[derive(Debug, Clone)]
+pub struct SharedPort(Arc<Mutex<SerialStream>>);
+
+impl SharedPort {
+    pub fn new(stream: SerialStream) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+
+    /// 访问底层串口，用于修改参数或替换为新打开的串口
+    pub fn with<R>(&self, f: impl FnOnce(&mut SerialStream) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+impl AsyncRead for SharedPort {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SharedPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_shutdown(cx)
+    }
+}
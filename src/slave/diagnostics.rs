@@ -0,0 +1,265 @@
+//! 从机的诊断计数器和通信事件记录
+//!
+//! 每个站号像真实设备一样统计总线上的报文：所有站号都计入总线报文和校验错误，
+//! 发给本站的报文另外计入本站计数。计数器通过 08 诊断的子功能读取和清除，
+//! 事件计数和事件记录通过 11、12 功能码读取。
+
+use super::store::RegisterStore;
+use crate::modbus::decode::comm_event_text;
+use crate::modbus::pdu::{CommEventLog, DiagnosticCode, ExceptionCode, FunctionCode, Response};
+use eframe::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// 事件记录最多保留的事件数
+pub const MAX_EVENTS: usize = 64;
+
+/// 接收事件：最高位为 1，广播和通信错误各占一位
+const EVENT_RECEIVE: u8 = 0x80;
+const EVENT_RECEIVE_COMM_ERROR: u8 = 0x02;
+const EVENT_RECEIVE_BROADCAST: u8 = 0x40;
+/// 发送事件：次高位为 1，低位按异常码分类
+const EVENT_SEND: u8 = 0x40;
+/// 通信重启事件
+const EVENT_RESTART: u8 = 0x00;
+
+/// 一个站号的诊断计数器，计数到 0xFFFF 后回绕
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub bus_messages: u16,
+    pub bus_communication_errors: u16,
+    pub bus_exception_errors: u16,
+    pub server_messages: u16,
+    pub server_no_responses: u16,
+    /// 成功完成的请求数，不含异常应答和读通信事件计数本身
+    pub event_count: u16,
+    //最近的事件，最新的在前
+    events: VecDeque<u8>,
+}
+
+impl Diagnostics {
+    /// 总线上一条校验正确的报文，不论发给哪个站号
+    pub fn record_bus_message(&mut self) {
+        self.bus_messages = self.bus_messages.wrapping_add(1);
+    }
+
+    /// 总线上一帧校验失败
+    pub fn record_communication_error(&mut self) {
+        self.bus_communication_errors = self.bus_communication_errors.wrapping_add(1);
+        self.push_event(EVENT_RECEIVE | EVENT_RECEIVE_COMM_ERROR);
+    }
+
+    /// 本站收到一条请求，`broadcast` 表示广播请求
+    pub fn record_request(&mut self, broadcast: bool) {
+        self.server_messages = self.server_messages.wrapping_add(1);
+        let mut event = EVENT_RECEIVE;
+        if broadcast {
+            event |= EVENT_RECEIVE_BROADCAST;
+        }
+        self.push_event(event);
+    }
+
+    /// 本站处理完一条请求，广播请求不应答，只计入无应答计数
+    ///
+    /// 无法解析的请求没有 `function`，结果总是异常。
+    pub fn record_result(
+        &mut self,
+        function: Option<FunctionCode>,
+        result: &Result<Response, ExceptionCode>,
+        broadcast: bool,
+    ) {
+        if broadcast {
+            self.server_no_responses = self.server_no_responses.wrapping_add(1);
+        }
+        match result {
+            Ok(_) => {
+                if function != Some(FunctionCode::GetCommEventCounter) {
+                    self.event_count = self.event_count.wrapping_add(1);
+                }
+                if !broadcast {
+                    self.push_event(EVENT_SEND);
+                }
+            }
+            // 广播请求出错时不返回异常应答
+            Err(code) if !broadcast => {
+                self.bus_exception_errors = self.bus_exception_errors.wrapping_add(1);
+                self.push_event(EVENT_SEND | exception_event_bits(*code));
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// 清除所有计数器，事件记录保留
+    pub fn clear_counters(&mut self) {
+        *self = Self {
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+    }
+
+    /// 重启通信：清除计数器，`clear_log` 时同时清除事件记录，然后记下重启事件
+    pub fn restart(&mut self, clear_log: bool) {
+        self.clear_counters();
+        if clear_log {
+            self.events.clear();
+        }
+        self.push_event(EVENT_RESTART);
+    }
+
+    /// 计数类子功能对应的计数值
+    pub fn counter(&self, code: DiagnosticCode) -> Option<u16> {
+        let count = match code {
+            DiagnosticCode::BusMessageCount => self.bus_messages,
+            DiagnosticCode::BusCommunicationErrorCount => self.bus_communication_errors,
+            DiagnosticCode::BusExceptionErrorCount => self.bus_exception_errors,
+            DiagnosticCode::ServerMessageCount => self.server_messages,
+            DiagnosticCode::ServerNoResponseCount => self.server_no_responses,
+            _ => return None,
+        };
+        Some(count)
+    }
+
+    pub fn event_log(&self) -> CommEventLog {
+        CommEventLog {
+            status: 0,
+            event_count: self.event_count,
+            message_count: self.bus_messages,
+            events: self.events.iter().copied().collect(),
+        }
+    }
+
+    fn push_event(&mut self, event: u8) {
+        self.events.push_front(event);
+        self.events.truncate(MAX_EVENTS);
+    }
+}
+
+/// 发送事件中表示异常类别的位
+fn exception_event_bits(code: ExceptionCode) -> u8 {
+    match code.code() {
+        0x01..=0x03 => 0x01,
+        0x04 => 0x02,
+        0x05 | 0x06 => 0x04,
+        0x07 => 0x08,
+        _ => 0x00,
+    }
+}
+
+/// 从机页面的诊断计数视图
+#[derive(Debug)]
+pub struct DiagnosticsView {
+    unit: u8,
+}
+
+impl Default for DiagnosticsView {
+    fn default() -> Self {
+        Self { unit: 1 }
+    }
+}
+
+impl DiagnosticsView {
+    pub fn show(&mut self, ui: &mut egui::Ui, store: &Arc<Mutex<RegisterStore>>) {
+        let mut store = store.lock().unwrap();
+        let units = store.unit_ids();
+        if !units.contains(&self.unit)
+            && let Some(&first) = units.first()
+        {
+            self.unit = first;
+        }
+        let Some(unit) = store.unit_mut(self.unit) else {
+            ui.label("没有配置站号");
+            return;
+        };
+        let diagnostics = &mut unit.diagnostics;
+        ui.horizontal(|ui| {
+            ui.label("站号:");
+            egui::ComboBox::from_id_salt("slave_diagnostics_unit")
+                .selected_text(self.unit.to_string())
+                .show_ui(ui, |ui| {
+                    for id in &units {
+                        ui.selectable_value(&mut self.unit, *id, id.to_string());
+                    }
+                });
+            if ui.button("清除计数").clicked() {
+                log::info!("清除从机站号 {} 的诊断计数", self.unit);
+                diagnostics.clear_counters();
+            }
+            if ui.button("清除事件记录").clicked() {
+                diagnostics.events.clear();
+            }
+        });
+        ui.separator();
+        egui::Grid::new("slave_diagnostics_counters")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for code in DiagnosticCode::ALL {
+                    if let Some(count) = diagnostics.counter(code) {
+                        ui.label(code.label());
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                }
+                ui.label("通信事件计数");
+                ui.label(diagnostics.event_count.to_string());
+                ui.end_row();
+            });
+        ui.separator();
+        ui.strong(format!("事件记录（最新在前，最多 {} 个）", MAX_EVENTS));
+        egui::ScrollArea::vertical()
+            .id_salt("slave_diagnostics_events")
+            .show(ui, |ui| {
+                for event in &diagnostics.events {
+                    ui.horizontal(|ui| {
+                        ui.monospace(format!("0x{:02X}", event));
+                        ui.label(comm_event_text(*event));
+                    });
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_and_events() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.record_bus_message();
+        diagnostics.record_request(false);
+        diagnostics.record_result(
+            Some(FunctionCode::ReadCoils),
+            &Ok(Response::ReadCoils(vec![true])),
+            false,
+        );
+        diagnostics.record_bus_message();
+        diagnostics.record_request(false);
+        diagnostics.record_result(
+            Some(FunctionCode::ReadCoils),
+            &Err(ExceptionCode::IllegalDataAddress),
+            false,
+        );
+        diagnostics.record_communication_error();
+        assert_eq!(
+            diagnostics.counter(DiagnosticCode::BusMessageCount),
+            Some(2)
+        );
+        assert_eq!(diagnostics.bus_exception_errors, 1);
+        assert_eq!(diagnostics.event_count, 1);
+        assert_eq!(
+            diagnostics.event_log().events,
+            vec![0x82, 0x41, 0x80, 0x40, 0x80]
+        );
+
+        diagnostics.clear_counters();
+        assert_eq!(
+            diagnostics.counter(DiagnosticCode::BusMessageCount),
+            Some(0)
+        );
+        assert_eq!(diagnostics.event_log().events.len(), 5);
+        diagnostics.restart(true);
+        assert_eq!(diagnostics.event_log().events, vec![0x00]);
+    }
+}
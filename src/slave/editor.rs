@@ -0,0 +1,251 @@
+//! 从机寄存器存储的查看和编辑
+
+use super::store::{RegisterStore, Table, TableKind, UnitStore};
+use crate::app_ui::show_value_format;
+use crate::modbus::value::ValueFormat;
+use eframe::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub struct StoreEditor {
+    unit: u8,
+    table: TableKind,
+    //待添加的站号
+    new_unit: u8,
+    //正在编辑、尚未应用的地址范围
+    range_start: u16,
+    range_count: usize,
+    //编辑中的范围对应的站号和表，切换后重新读取
+    range_source: Option<(u8, TableKind)>,
+    //寄存器按十六进制显示
+    hex: bool,
+    //寄存器另外按此类型显示和编辑
+    format: ValueFormat,
+    //正在按类型编辑的地址和输入
+    editing: Option<(usize, String)>,
+    edit_error: Option<String>,
+}
+
+impl Default for StoreEditor {
+    fn default() -> Self {
+        Self {
+            unit: 1,
+            table: TableKind::HoldingRegisters,
+            new_unit: 2,
+            range_start: 0,
+            range_count: 0,
+            range_source: None,
+            hex: false,
+            format: ValueFormat::default(),
+            editing: None,
+            edit_error: None,
+        }
+    }
+}
+
+impl StoreEditor {
+    pub fn show(&mut self, ui: &mut egui::Ui, store: &Arc<Mutex<RegisterStore>>) {
+        let mut store = store.lock().unwrap();
+        self.show_unit_selector(ui, &mut store);
+        ui.separator();
+
+        let Some(unit) = store.unit_mut(self.unit) else {
+            ui.label("没有配置站号");
+            return;
+        };
+        self.show_table_selector(ui);
+        self.show_range_editor(ui, unit);
+        ui.separator();
+        self.show_values(ui, unit);
+    }
+
+    fn show_unit_selector(&mut self, ui: &mut egui::Ui, store: &mut RegisterStore) {
+        let units = store.unit_ids();
+        if !units.contains(&self.unit)
+            && let Some(&first) = units.first()
+        {
+            self.unit = first;
+        }
+        ui.horizontal(|ui| {
+            ui.label("站号:");
+            egui::ComboBox::from_id_salt("slave_unit_selector")
+                .selected_text(self.unit.to_string())
+                .show_ui(ui, |ui| {
+                    for unit in &units {
+                        ui.selectable_value(&mut self.unit, *unit, unit.to_string());
+                    }
+                });
+            if ui.button("删除站号").clicked() && store.remove_unit(self.unit).is_some() {
+                log::info!("删除从机站号 {}", self.unit);
+            }
+            ui.separator();
+            ui.add(egui::DragValue::new(&mut self.new_unit).range(1..=247));
+            if ui.button("添加站号").clicked() && store.add_unit(self.new_unit) {
+                log::info!("添加从机站号 {}", self.new_unit);
+                self.unit = self.new_unit;
+            }
+        });
+    }
+
+    fn show_table_selector(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for kind in TableKind::ALL {
+                ui.selectable_value(&mut self.table, kind, kind.label());
+            }
+            if !self.table.is_bits() {
+                ui.separator();
+                ui.checkbox(&mut self.hex, "十六进制");
+                ui.separator();
+                show_value_format(ui, "slave_values", &mut self.format);
+            }
+        });
+        if !self.table.is_bits()
+            && let Some(err) = &self.edit_error
+        {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+        }
+    }
+
+    fn show_range_editor(&mut self, ui: &mut egui::Ui, unit: &mut UnitStore) {
+        if self.range_source != Some((self.unit, self.table)) {
+            (self.range_start, self.range_count) = unit.range(self.table);
+            self.range_source = Some((self.unit, self.table));
+            self.editing = None;
+            self.edit_error = None;
+        }
+        ui.horizontal(|ui| {
+            ui.label("起始地址:");
+            ui.add(egui::DragValue::new(&mut self.range_start));
+            ui.label("数量:");
+            let max_count = 0x10000 - self.range_start as usize;
+            ui.add(egui::DragValue::new(&mut self.range_count).range(0..=max_count));
+            let changed = (self.range_start, self.range_count) != unit.range(self.table);
+            if ui
+                .add_enabled(changed, egui::Button::new("应用范围"))
+                .clicked()
+            {
+                log::info!(
+                    "站号 {} {}范围修改为 {} 起 {} 个",
+                    self.unit,
+                    self.table.label(),
+                    self.range_start,
+                    self.range_count
+                );
+                unit.resize(self.table, self.range_start, self.range_count);
+            }
+        });
+    }
+
+    fn show_values(&mut self, ui: &mut egui::Ui, unit: &mut UnitStore) {
+        let (start, count) = unit.range(self.table);
+        let row_height = ui.spacing().interact_size.y;
+        egui::ScrollArea::vertical()
+            .id_salt("slave_values")
+            .show_rows(ui, row_height, count, |ui, rows| {
+                egui::Grid::new("slave_values_grid")
+                    .num_columns(3)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for row in rows {
+                            let address = start as usize + row;
+                            ui.label(address.to_string());
+                            match self.table {
+                                TableKind::Coils => {
+                                    show_bit(ui, unit.coils.get_mut(address));
+                                }
+                                TableKind::DiscreteInputs => {
+                                    show_bit(ui, unit.discrete_inputs.get_mut(address));
+                                }
+                                TableKind::HoldingRegisters => {
+                                    show_register(
+                                        ui,
+                                        unit.holding_registers.get_mut(address),
+                                        self.hex,
+                                    );
+                                    self.show_typed(ui, &mut unit.holding_registers, address);
+                                }
+                                TableKind::InputRegisters => {
+                                    show_register(
+                                        ui,
+                                        unit.input_registers.get_mut(address),
+                                        self.hex,
+                                    );
+                                    self.show_typed(ui, &mut unit.input_registers, address);
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    /// 按所选类型显示从该地址开始的值，点击后可以输入新值，回车写入
+    fn show_typed(&mut self, ui: &mut egui::Ui, table: &mut Table<u16>, address: usize) {
+        let format = self.format;
+        let count = format.data_type.register_count();
+        let offset = address - table.start() as usize;
+        let span = match table.read(address as u16, count as u16) {
+            Ok(span) if offset.is_multiple_of(count) => span,
+            _ => {
+                ui.label("");
+                return;
+            }
+        };
+        let id = ui.make_persistent_id(("slave_typed_value", address));
+        match &mut self.editing {
+            Some((editing, text)) if *editing == address => {
+                let response = ui.add(egui::TextEdit::singleline(text).id(id));
+                if response.lost_focus() {
+                    if ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                        let result = format.encode_text(text).and_then(|values| {
+                            table
+                                .write(address as u16, &values)
+                                .map_err(|err| err.to_string())
+                        });
+                        match result {
+                            Ok(()) => {
+                                log::info!(
+                                    "地址 {} 按 {} 写入 {}",
+                                    address,
+                                    format.data_type.label(),
+                                    text
+                                );
+                                self.edit_error = None;
+                            }
+                            Err(err) => self.edit_error = Some(err),
+                        }
+                    }
+                    self.editing = None;
+                }
+            }
+            _ => {
+                let text = match format.decode(&span) {
+                    Ok(value) => value.to_string(),
+                    Err(err) => err,
+                };
+                let label = egui::Label::new(text.clone()).sense(egui::Sense::click());
+                if ui.add(label).on_hover_text("点击编辑").clicked() {
+                    self.editing = Some((address, text));
+                    ui.memory_mut(|memory| memory.request_focus(id));
+                }
+            }
+        }
+    }
+}
+
+fn show_bit(ui: &mut egui::Ui, value: Option<&mut bool>) {
+    if let Some(value) = value {
+        ui.checkbox(value, if *value { "ON" } else { "OFF" });
+    }
+}
+
+fn show_register(ui: &mut egui::Ui, value: Option<&mut u16>, hex: bool) {
+    if let Some(value) = value {
+        let mut drag = egui::DragValue::new(value);
+        if hex {
+            drag = drag.hexadecimal(4, false, true);
+        }
+        ui.add(drag);
+    }
+}
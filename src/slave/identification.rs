@@ -0,0 +1,107 @@
+//! 从机设备标识的编辑
+//!
+//! 每个站号的标识对象用于应答 43/14 读设备标识，修改立即生效。
+
+use super::store::{MAX_OBJECT_LEN, RegisterStore, default_identification};
+use crate::modbus::decode::device_object_name;
+use eframe::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub struct IdentificationEditor {
+    unit: u8,
+    //待添加的对象编号
+    new_object: u8,
+}
+
+impl Default for IdentificationEditor {
+    fn default() -> Self {
+        Self {
+            unit: 1,
+            new_object: 0x03,
+        }
+    }
+}
+
+impl IdentificationEditor {
+    pub fn show(&mut self, ui: &mut egui::Ui, store: &Arc<Mutex<RegisterStore>>) {
+        let mut store = store.lock().unwrap();
+        let units = store.unit_ids();
+        if !units.contains(&self.unit)
+            && let Some(&first) = units.first()
+        {
+            self.unit = first;
+        }
+        let Some(unit) = store.unit_mut(self.unit) else {
+            ui.label("没有配置站号");
+            return;
+        };
+        let objects = &mut unit.identification;
+        ui.horizontal(|ui| {
+            ui.label("站号:");
+            egui::ComboBox::from_id_salt("slave_identification_unit")
+                .selected_text(self.unit.to_string())
+                .show_ui(ui, |ui| {
+                    for id in &units {
+                        ui.selectable_value(&mut self.unit, *id, id.to_string());
+                    }
+                });
+            ui.separator();
+            ui.add(egui::DragValue::new(&mut self.new_object).hexadecimal(2, false, true));
+            ui.label(device_object_name(self.new_object));
+            if ui
+                .add_enabled(
+                    !objects.contains_key(&self.new_object),
+                    egui::Button::new("添加对象"),
+                )
+                .clicked()
+            {
+                objects.insert(self.new_object, String::new());
+            }
+            if ui
+                .button("恢复默认")
+                .on_hover_text("替换为默认的厂商、产品代码、版本和产品名称")
+                .clicked()
+            {
+                *objects = default_identification();
+            }
+        });
+        ui.label("0x00–0x02 为基本类别，0x03–0x06 为常规类别，0x80 起为扩展类别的厂商自定义对象");
+        ui.separator();
+
+        let mut removed = None;
+        egui::ScrollArea::vertical()
+            .id_salt("slave_identification")
+            .show(ui, |ui| {
+                egui::Grid::new("slave_identification_grid")
+                    .num_columns(4)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for title in ["编号", "名称", "内容", ""] {
+                            ui.strong(title);
+                        }
+                        ui.end_row();
+                        for (id, text) in objects.iter_mut() {
+                            ui.monospace(format!("0x{:02X}", id));
+                            ui.label(device_object_name(*id));
+                            let response =
+                                ui.add(egui::TextEdit::singleline(text).desired_width(300.0));
+                            if text.len() > MAX_OBJECT_LEN {
+                                response.on_hover_text(format!(
+                                    "超过 {} 字节，应答时截断",
+                                    MAX_OBJECT_LEN
+                                ));
+                            }
+                            if ui.button("删除").clicked() {
+                                removed = Some(*id);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        if let Some(id) = removed {
+            objects.remove(&id);
+        }
+    }
+}
@@ -1,12 +1,349 @@
+pub mod diagnostics;
+pub mod editor;
+pub mod identification;
+pub mod server;
+pub mod store;
+
+use crate::modbus::pdu::{ExceptionCode, Request, Response};
+use crate::tag::editor::{MapFile, show_tags};
+use crate::tag::map::MapPoint;
+use crate::tag::{SharedTags, Tag};
+use diagnostics::DiagnosticsView;
+use editor::StoreEditor;
 use eframe::*;
+use identification::IdentificationEditor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use store::RegisterStore;
+
+/// 访问记录最多保留的条数
+const MAX_ACCESS_LOG: usize = 100;
+
+/// 从机页面中间区域显示的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum View {
+    #[default]
+    Registers,
+    Identification,
+    Diagnostics,
+    Tags,
+}
 
 #[derive(Debug, Default)]
-pub struct Slave {}
+pub struct Slave {
+    store: Arc<Mutex<RegisterStore>>,
+    //最近应答过的请求
+    access_log: Vec<String>,
+    //TCP 从机的客户端，断开后保留到手动清除
+    clients: Vec<ClientStats>,
+    editor: StoreEditor,
+    identification: IdentificationEditor,
+    diagnostics: DiagnosticsView,
+    view: View,
+    //与主机页面共享的标签列表
+    tags: SharedTags,
+    //正在编辑的标签和输入的工程值
+    tag_edit: Option<(u64, String)>,
+    tag_error: Option<String>,
+    map_file: MapFile,
+}
+
+/// 一个 TCP 客户端的连接情况和请求计数
+#[derive(Debug, Clone)]
+pub struct ClientStats {
+    pub address: SocketAddr,
+    pub connected_at: Instant,
+    pub connected: bool,
+    /// 应答过的请求数，包括异常应答
+    pub requests: u64,
+    /// 以异常码应答的请求数
+    pub exceptions: u64,
+}
 
 impl Slave {
+    pub fn new(tags: SharedTags) -> Self {
+        Self {
+            tags,
+            ..Default::default()
+        }
+    }
+
+    /// 从机引擎使用的寄存器存储
+    pub fn store(&self) -> Arc<Mutex<RegisterStore>> {
+        self.store.clone()
+    }
+
+    /// 替换寄存器存储的全部内容，例如打开工作区时
+    pub fn set_store(&mut self, store: RegisterStore) {
+        *self.store.lock().unwrap() = store;
+        // 编辑器缓存了地址范围，需要重新读取
+        self.editor = StoreEditor::default();
+    }
+
     pub fn show(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::right("slave_activity")
+            .default_width(360.0)
+            .show(_ctx, |ui| {
+                self.show_clients(ui);
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .id_salt("slave_access_log")
+                    .show(ui, |ui| {
+                        for entry in self.access_log.iter().rev() {
+                            ui.label(entry);
+                        }
+                    });
+            });
         egui::CentralPanel::default().show(_ctx, |ui| {
-            ui.label("Modbus 从机界面");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.view, View::Registers, "寄存器");
+                ui.selectable_value(&mut self.view, View::Identification, "设备标识");
+                ui.selectable_value(&mut self.view, View::Diagnostics, "诊断计数");
+                ui.selectable_value(&mut self.view, View::Tags, "标签");
+            });
+            ui.separator();
+            match self.view {
+                View::Registers => self.editor.show(ui, &self.store),
+                View::Identification => self.identification.show(ui, &self.store),
+                View::Diagnostics => self.diagnostics.show(ui, &self.store),
+                View::Tags => self.show_tags(ui),
+            }
+        });
+    }
+
+    /// 标签表，值直接读写寄存器存储，点击值输入新的工程值，回车写入
+    fn show_tags(&mut self, ui: &mut egui::Ui) {
+        if let Some(err) = &self.tag_error {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+        }
+        let tags = self.tags.clone();
+        let store = self.store.clone();
+        let imported = self.map_file.show(ui, &tags, |tag| {
+            let store = store.lock().unwrap();
+            tag.read_store(&store).ok().map(|value| value.to_string())
+        });
+        if let Some(points) = imported {
+            self.tag_error = apply_map(&mut store.lock().unwrap(), &points).err();
+        }
+        show_tags(ui, "slave_tags", &tags, |ui, tag| {
+            self.show_tag_value(ui, tag)
         });
     }
+
+    fn show_tag_value(&mut self, ui: &mut egui::Ui, tag: &Tag) {
+        let mut store = self.store.lock().unwrap();
+        let id = ui.make_persistent_id(("slave_tag_value", tag.id));
+        match &mut self.tag_edit {
+            Some((editing, text)) if *editing == tag.id => {
+                let response = ui.add(egui::TextEdit::singleline(text).id(id));
+                if response.lost_focus() {
+                    if ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                        match tag.write_store(&mut store, text) {
+                            Ok(()) => {
+                                log::info!("标签 {} 写入 {} {}", tag.name, text, tag.unit_text);
+                                self.tag_error = None;
+                            }
+                            Err(err) => self.tag_error = Some(err),
+                        }
+                    }
+                    self.tag_edit = None;
+                }
+            }
+            _ => match tag.read_store(&store) {
+                Ok(value) => {
+                    let text = format!("{} {}", value, tag.unit_text);
+                    let color = if value.out_of_range {
+                        egui::Color32::from_rgb(220, 50, 50)
+                    } else {
+                        ui.visuals().text_color()
+                    };
+                    let label = egui::Label::new(egui::RichText::new(text).color(color))
+                        .sense(egui::Sense::click());
+                    if ui.add(label).on_hover_text("点击编辑").clicked() {
+                        self.tag_edit = Some((tag.id, value.to_string()));
+                        ui.memory_mut(|memory| memory.request_focus(id));
+                    }
+                }
+                Err(err) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                }
+            },
+        }
+    }
+
+    fn show_clients(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "TCP 客户端: {} 个在线",
+                self.clients
+                    .iter()
+                    .filter(|client| client.connected)
+                    .count()
+            ));
+            if ui.button("清除已断开").clicked() {
+                self.clients.retain(|client| client.connected);
+            }
+        });
+        if self.clients.is_empty() {
+            return;
+        }
+        egui::Grid::new("slave_clients_grid")
+            .num_columns(5)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("客户端");
+                ui.strong("状态");
+                ui.strong("连接时长");
+                ui.strong("请求数");
+                ui.strong("异常数");
+                ui.end_row();
+                for client in &self.clients {
+                    ui.label(client.address.to_string());
+                    if client.connected {
+                        ui.colored_label(egui::Color32::from_rgb(50, 220, 50), "已连接");
+                        ui.label(format!("{} s", client.connected_at.elapsed().as_secs()));
+                    } else {
+                        ui.colored_label(egui::Color32::from_rgb(150, 150, 150), "已断开");
+                        ui.label("-");
+                    }
+                    ui.label(client.requests.to_string());
+                    ui.label(client.exceptions.to_string());
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// TCP 从机接受了新的客户端
+    pub fn client_connected(&mut self, address: SocketAddr) {
+        // 同一地址重新连接时按新连接重新计数
+        self.clients.retain(|client| client.address != address);
+        self.clients.push(ClientStats {
+            address,
+            connected_at: Instant::now(),
+            connected: true,
+            requests: 0,
+            exceptions: 0,
+        });
+    }
+
+    pub fn client_disconnected(&mut self, address: SocketAddr) {
+        if let Some(client) = self.client_mut(address) {
+            client.connected = false;
+        }
+    }
+
+    pub fn clients(&self) -> &[ClientStats] {
+        &self.clients
+    }
+
+    fn client_mut(&mut self, address: SocketAddr) -> Option<&mut ClientStats> {
+        self.clients
+            .iter_mut()
+            .find(|client| client.address == address)
+    }
+
+    /// 记录从机引擎应答过的请求，TCP 请求同时计入对应客户端
+    pub fn record_access(
+        &mut self,
+        client: Option<SocketAddr>,
+        unit: u8,
+        request: &Request,
+        result: &Result<Response, ExceptionCode>,
+    ) {
+        let outcome = match result {
+            Ok(_) => "成功".to_string(),
+            Err(code) => code.to_string(),
+        };
+        let source = match client {
+            Some(address) => {
+                if let Some(stats) = self.client_mut(address) {
+                    stats.requests += 1;
+                    if result.is_err() {
+                        stats.exceptions += 1;
+                    }
+                }
+                format!("{} ", address)
+            }
+            None => String::new(),
+        };
+        self.access_log
+            .push(format!("{}站号 {} {}: {}", source, unit, request, outcome));
+        if self.access_log.len() > MAX_ACCESS_LOG {
+            self.access_log.remove(0);
+        }
+    }
+}
+
+/// 把导入的寄存器表加入存储：补上缺少的站号和地址范围，写入给出的初始值
+fn apply_map(store: &mut RegisterStore, points: &[MapPoint]) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for point in points {
+        let tag = &point.tag;
+        store.add_unit(tag.unit);
+        if let Some(unit) = store.unit_mut(tag.unit) {
+            unit.extend_range(tag.table, tag.address, tag.quantity() as usize);
+        }
+        if let Some(value) = &point.value
+            && let Err(err) = tag.write_store(store, value)
+        {
+            errors.push(format!("{}: {}", tag.name, err));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::map;
+    use store::TableKind;
+
+    #[test]
+    fn test_client_counters() {
+        let mut slave = Slave::default();
+        let address: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        slave.client_connected(address);
+        let request = Request::ReadHoldingRegisters(0, 1);
+        slave.record_access(
+            Some(address),
+            1,
+            &request,
+            &Ok(Response::ReadHoldingRegisters(vec![0])),
+        );
+        slave.record_access(
+            Some(address),
+            1,
+            &request,
+            &Err(ExceptionCode::IllegalDataAddress),
+        );
+        slave.client_disconnected(address);
+
+        let client = &slave.clients()[0];
+        assert!(!client.connected);
+        assert_eq!(client.requests, 2);
+        assert_eq!(client.exceptions, 1);
+    }
+
+    #[test]
+    fn test_apply_map() {
+        let text = "unit,table,address,type,scale,value\n\
+            3,holding_register,20000,u32,0.5,100\n\
+            1,coil,5,,,1\n";
+        let points = map::import(text, map::MapFormat::Csv).unwrap();
+        let mut store = RegisterStore::default();
+        store.unit_mut(1).unwrap().resize(TableKind::Coils, 10, 10);
+        apply_map(&mut store, &points).unwrap();
+
+        let unit = store.unit(3).unwrap();
+        assert_eq!(unit.read_holding_registers(20000, 2), Ok(vec![0, 200]));
+        let unit = store.unit(1).unwrap();
+        assert_eq!(unit.range(TableKind::Coils), (5, 15));
+        assert_eq!(unit.read_coils(5, 1), Ok(vec![true]));
+    }
 }
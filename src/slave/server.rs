@@ -0,0 +1,430 @@
+//! 从机引擎
+//!
+//! 在串口上运行 tokio-modbus RTU 服务端，或在 TCP 端口上运行 Modbus TCP 服务端，
+//! 用同一份寄存器存储应答主机请求。
+
+use super::store::RegisterStore;
+use crate::modbus::frame::{FrameKind, Framing};
+use crate::modbus::pdu::{
+    DIAGNOSTICS, DiagnosticCode, ExceptionCode, GET_COMM_EVENT_COUNTER, GET_COMM_EVENT_LOG,
+    MEI_FUNCTION, MEI_READ_DEVICE_ID, ReadDeviceIdCode, Request, Response,
+};
+use crate::task::{EventSender, TaskEvent};
+use crate::transport::FramedTransport;
+use std::future::{self, Future, Ready};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_modbus::SlaveRequest;
+use tokio_modbus::server::Service;
+use tokio_modbus::server::{rtu, tcp};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+#[derive(Debug, Clone)]
+pub struct SlaveService {
+    store: Arc<Mutex<RegisterStore>>,
+    events: EventSender,
+    /// TCP 客户端的地址，串口从机为 `None`
+    client: Option<SocketAddr>,
+    /// 站号 0 是否按广播处理，Modbus TCP 中不是广播
+    broadcast: bool,
+}
+
+impl SlaveService {
+    pub fn new(store: Arc<Mutex<RegisterStore>>, events: EventSender) -> Self {
+        Self {
+            store,
+            events,
+            client: None,
+            broadcast: true,
+        }
+    }
+
+    /// 为某个 TCP 客户端创建服务，应答记录会带上客户端地址
+    ///
+    /// `framing` 是连接上的帧格式，只有 RTU/ASCII 帧格式的站号 0 按广播处理。
+    pub fn for_client(
+        store: Arc<Mutex<RegisterStore>>,
+        events: EventSender,
+        client: SocketAddr,
+        framing: Framing,
+    ) -> Self {
+        Self {
+            store,
+            events,
+            client: Some(client),
+            broadcast: framing != Framing::Tcp,
+        }
+    }
+
+    /// 返回 `Ok(None)` 表示不应答
+    fn handle(
+        &self,
+        unit: u8,
+        request: tokio_modbus::Request<'static>,
+    ) -> Result<Option<tokio_modbus::Response>, tokio_modbus::ExceptionCode> {
+        // 先按站号路由再应答解析错误，未配置的站号和广播请求即使无法解析也不应答
+        let request = from_tokio_request(request);
+        let result = self.store.lock().unwrap().handle_parsed(
+            unit,
+            request.as_ref().map_err(|code| *code),
+            self.broadcast,
+        );
+        let Some(result) = result else {
+            log::debug!("站号 {} 的请求不应答", unit);
+            return Ok(None);
+        };
+        if let Ok(request) = request {
+            self.events.send(TaskEvent::SlaveServed {
+                client: self.client,
+                unit,
+                request,
+                result: result.clone(),
+            });
+        }
+        result
+            .map(|response| Some(to_tokio_response(response)))
+            .map_err(to_tokio_exception)
+    }
+}
+
+impl Service for SlaveService {
+    type Request = SlaveRequest<'static>;
+    type Response = Option<tokio_modbus::Response>;
+    type Exception = tokio_modbus::ExceptionCode;
+    type Future = Ready<Result<Self::Response, Self::Exception>>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        log::debug!("从机收到请求: 站号 {}, {:?}", req.slave, req.request);
+        future::ready(self.handle(req.slave, req.request))
+    }
+}
+
+/// 在给定传输层上运行 RTU 从机，直到传输层出错
+///
+/// 只应答寄存器存储中配置了的站号。
+pub async fn run<T>(
+    transport: T,
+    store: Arc<Mutex<RegisterStore>>,
+    events: EventSender,
+) -> io::Result<()>
+where
+    T: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Unpin + 'static,
+{
+    log::info!("RTU 从机启动");
+    rtu::Server::new(transport)
+        .serve_forever(SlaveService::new(store, events))
+        .await
+}
+
+/// 在监听端口上运行从机，直到监听出错
+///
+/// `framing` 为 TCP 时按 Modbus TCP 应答；为 RTU 或 ASCII 时每个连接按串口帧格式应答，
+/// 用于模拟串口服务器后面的设备。每个客户端连接在单独的任务中处理。
+/// 引擎停止（任务取消或切换模式）时，所有客户端连接随之关闭，不会在后台继续应答。
+pub async fn run_tcp(
+    listener: TcpListener,
+    framing: Framing,
+    store: Arc<Mutex<RegisterStore>>,
+    events: EventSender,
+) -> io::Result<()> {
+    log::info!("{} 从机启动，监听 {}", framing, listener.local_addr()?);
+    // 本函数返回或被丢弃时取消令牌，通知所有客户端连接关闭
+    let closing = CancellationToken::new();
+    let _close_clients = closing.clone().drop_guard();
+
+    if framing != Framing::Tcp {
+        return serve_serial_framing(listener, framing, store, events, closing).await;
+    }
+
+    let on_connected = |stream: TcpStream, peer: SocketAddr| {
+        let store = store.clone();
+        let events = events.clone();
+        let closing = closing.clone();
+        async move {
+            let new_service = |peer| {
+                Ok(Some(SlaveService::for_client(
+                    store.clone(),
+                    events.clone(),
+                    peer,
+                    Framing::Tcp,
+                )))
+            };
+            let accepted = tcp::accept_tcp_connection(stream, peer, new_service).await?;
+            Ok(accepted.map(|(service, stream)| {
+                log::info!("TCP 客户端 {} 已连接", peer);
+                events.send(TaskEvent::ClientConnected(peer));
+                let stream = ClientStream::new(stream, peer, events.clone(), closing);
+                let transport =
+                    FramedTransport::new(stream, Framing::Tcp, FrameKind::Request, events);
+                (service, transport)
+            }))
+        }
+    };
+    let on_process_error = |err: io::Error| {
+        log::warn!("处理 TCP 客户端请求失败: {}", err);
+    };
+    tcp::Server::new(listener)
+        .serve(&on_connected, on_process_error)
+        .await
+}
+
+/// 在 TCP 连接上按 RTU/ASCII 帧格式应答，每个连接运行一个 RTU 服务端
+async fn serve_serial_framing(
+    listener: TcpListener,
+    framing: Framing,
+    store: Arc<Mutex<RegisterStore>>,
+    events: EventSender,
+    closing: CancellationToken,
+) -> io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        log::info!("TCP 客户端 {} 已连接", peer);
+        events.send(TaskEvent::ClientConnected(peer));
+        let service = SlaveService::for_client(store.clone(), events.clone(), peer, framing);
+        let stream = ClientStream::new(stream, peer, events.clone(), closing.clone());
+        let transport = FramedTransport::new(stream, framing, FrameKind::Request, events.clone())
+            .count_errors_in(store.clone());
+        tokio::spawn(async move {
+            if let Err(err) = rtu::Server::new(transport).serve_forever(service).await {
+                log::warn!("处理 TCP 客户端 {} 的请求失败: {}", peer, err);
+            }
+        });
+    }
+}
+
+/// TCP 客户端连接
+///
+/// 引擎停止时读取返回结束，使 tokio-modbus 关闭连接；连接释放时通知界面客户端已断开。
+#[derive(Debug)]
+struct ClientStream {
+    stream: TcpStream,
+    peer: SocketAddr,
+    events: EventSender,
+    closing: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl ClientStream {
+    fn new(
+        stream: TcpStream,
+        peer: SocketAddr,
+        events: EventSender,
+        closing: CancellationToken,
+    ) -> Self {
+        Self {
+            stream,
+            peer,
+            events,
+            closing: Box::pin(closing.cancelled_owned()),
+        }
+    }
+}
+
+impl Drop for ClientStream {
+    fn drop(&mut self) {
+        log::info!("TCP 客户端 {} 已断开", self.peer);
+        self.events.send(TaskEvent::ClientDisconnected(self.peer));
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.closing.as_mut().poll(cx).is_ready() {
+            // 不读入任何数据即表示连接结束
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// 不支持的功能码和诊断子功能返回 `IllegalFunction`，数据格式不对时返回 `IllegalDataValue`
+fn from_tokio_request(request: tokio_modbus::Request<'static>) -> Result<Request, ExceptionCode> {
+    use tokio_modbus::Request as R;
+    let request = match request {
+        R::ReadCoils(address, quantity) => Request::ReadCoils(address, quantity),
+        R::ReadDiscreteInputs(address, quantity) => Request::ReadDiscreteInputs(address, quantity),
+        R::ReadHoldingRegisters(address, quantity) => {
+            Request::ReadHoldingRegisters(address, quantity)
+        }
+        R::ReadInputRegisters(address, quantity) => Request::ReadInputRegisters(address, quantity),
+        R::WriteSingleCoil(address, value) => Request::WriteSingleCoil(address, value),
+        R::WriteSingleRegister(address, value) => Request::WriteSingleRegister(address, value),
+        R::WriteMultipleCoils(address, values) => {
+            Request::WriteMultipleCoils(address, values.into_owned())
+        }
+        R::WriteMultipleRegisters(address, values) => {
+            Request::WriteMultipleRegisters(address, values.into_owned())
+        }
+        R::MaskWriteRegister(address, and_mask, or_mask) => {
+            Request::MaskWriteRegister(address, and_mask, or_mask)
+        }
+        R::ReadWriteMultipleRegisters(read_address, quantity, write_address, values) => {
+            Request::ReadWriteMultipleRegisters(
+                read_address,
+                quantity,
+                write_address,
+                values.into_owned(),
+            )
+        }
+        R::Custom(DIAGNOSTICS, data) => {
+            let (code, words) =
+                DiagnosticCode::parse(&data).ok_or(ExceptionCode::IllegalDataValue)?;
+            let code = DiagnosticCode::from_code(code).ok_or(ExceptionCode::IllegalFunction)?;
+            Request::Diagnostics(code, words)
+        }
+        R::Custom(GET_COMM_EVENT_COUNTER, _) => Request::GetCommEventCounter,
+        R::Custom(GET_COMM_EVENT_LOG, _) => Request::GetCommEventLog,
+        R::Custom(MEI_FUNCTION, data) => match *data {
+            [MEI_READ_DEVICE_ID, code, object] => Request::ReadDeviceIdentification(
+                ReadDeviceIdCode::from_code(code).ok_or(ExceptionCode::IllegalDataValue)?,
+                object,
+            ),
+            _ => return Err(ExceptionCode::IllegalFunction),
+        },
+        _ => return Err(ExceptionCode::IllegalFunction),
+    };
+    Ok(request)
+}
+
+fn to_tokio_response(response: Response) -> tokio_modbus::Response {
+    use tokio_modbus::Response as R;
+    match response {
+        Response::ReadCoils(values) => R::ReadCoils(values),
+        Response::ReadDiscreteInputs(values) => R::ReadDiscreteInputs(values),
+        Response::ReadHoldingRegisters(values) => R::ReadHoldingRegisters(values),
+        Response::ReadInputRegisters(values) => R::ReadInputRegisters(values),
+        Response::WriteSingleCoil(address, value) => R::WriteSingleCoil(address, value),
+        Response::WriteSingleRegister(address, value) => R::WriteSingleRegister(address, value),
+        Response::WriteMultipleCoils(address, quantity) => R::WriteMultipleCoils(address, quantity),
+        Response::WriteMultipleRegisters(address, quantity) => {
+            R::WriteMultipleRegisters(address, quantity)
+        }
+        Response::MaskWriteRegister(address, and_mask, or_mask) => {
+            R::MaskWriteRegister(address, and_mask, or_mask)
+        }
+        Response::ReadWriteMultipleRegisters(values) => R::ReadWriteMultipleRegisters(values),
+        Response::Diagnostics(code, data) => R::Custom(DIAGNOSTICS, code.encode(&data)),
+        Response::GetCommEventCounter(status, count) => {
+            let mut data = status.to_be_bytes().to_vec();
+            data.extend_from_slice(&count.to_be_bytes());
+            R::Custom(GET_COMM_EVENT_COUNTER, data)
+        }
+        Response::GetCommEventLog(log) => R::Custom(GET_COMM_EVENT_LOG, log.encode()),
+        Response::ReadDeviceIdentification(identification) => {
+            R::Custom(MEI_FUNCTION, identification.encode())
+        }
+    }
+}
+
+fn to_tokio_exception(exception: ExceptionCode) -> tokio_modbus::ExceptionCode {
+    use tokio_modbus::ExceptionCode as E;
+    match exception {
+        ExceptionCode::IllegalFunction => E::IllegalFunction,
+        ExceptionCode::IllegalDataAddress => E::IllegalDataAddress,
+        ExceptionCode::IllegalDataValue => E::IllegalDataValue,
+        ExceptionCode::ServerDeviceFailure => E::ServerDeviceFailure,
+        ExceptionCode::Acknowledge => E::Acknowledge,
+        ExceptionCode::ServerDeviceBusy => E::ServerDeviceBusy,
+        ExceptionCode::MemoryParityError => E::MemoryParityError,
+        ExceptionCode::GatewayPathUnavailable => E::GatewayPathUnavailable,
+        ExceptionCode::GatewayTargetDevice => E::GatewayTargetDevice,
+        ExceptionCode::Other(code) => E::Custom(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use tokio::sync::mpsc;
+    use tokio_modbus::ExceptionCode as E;
+
+    fn service(store: &Arc<Mutex<RegisterStore>>, framing: Option<Framing>) -> SlaveService {
+        let events = EventSender::new(mpsc::unbounded_channel().0);
+        match framing {
+            Some(framing) => SlaveService::for_client(
+                store.clone(),
+                events,
+                "127.0.0.1:502".parse().unwrap(),
+                framing,
+            ),
+            None => SlaveService::new(store.clone(), events),
+        }
+    }
+
+    fn custom(function: u8, data: &'static [u8]) -> tokio_modbus::Request<'static> {
+        tokio_modbus::Request::Custom(function, Cow::Borrowed(data))
+    }
+
+    #[test]
+    fn test_unsupported_function_routing() {
+        let store = Arc::new(Mutex::new(RegisterStore::new()));
+        // 串口帧格式下未配置的站号和广播即使无法解析也不应答
+        for framing in [None, Some(Framing::Rtu)] {
+            let service = service(&store, framing);
+            assert_eq!(service.handle(3, custom(0x41, &[])), Ok(None));
+            assert_eq!(service.handle(0, custom(0x41, &[])), Ok(None));
+            assert_eq!(
+                service.handle(1, custom(0x41, &[])),
+                Err(E::IllegalFunction)
+            );
+        }
+        // Modbus TCP 的站号 0 由站号 1 应答
+        let service = service(&store, Some(Framing::Tcp));
+        assert_eq!(
+            service.handle(0, custom(0x41, &[])),
+            Err(E::IllegalFunction)
+        );
+    }
+
+    #[test]
+    fn test_invalid_requests_counted() {
+        let store = Arc::new(Mutex::new(RegisterStore::new()));
+        let service = service(&store, None);
+        assert_eq!(
+            service.handle(1, custom(DIAGNOSTICS, &[0x00, 0x63, 0x00, 0x00])),
+            Err(E::IllegalFunction)
+        );
+        // 不支持的子功能计入总线报文和异常应答
+        assert_eq!(
+            service.handle(1, custom(DIAGNOSTICS, &[0x00, 0x0B, 0x00, 0x00])),
+            Ok(Some(tokio_modbus::Response::Custom(
+                DIAGNOSTICS,
+                vec![0x00, 0x0B, 0x00, 0x02]
+            )))
+        );
+        assert_eq!(
+            service.handle(1, custom(DIAGNOSTICS, &[0x00, 0x0D, 0x00, 0x00])),
+            Ok(Some(tokio_modbus::Response::Custom(
+                DIAGNOSTICS,
+                vec![0x00, 0x0D, 0x00, 0x01]
+            )))
+        );
+    }
+}
@@ -0,0 +1,781 @@
+//! 从机寄存器存储
+//!
+//! 每个站号保存线圈、离散输入、保持寄存器和输入寄存器四张表，供从机引擎读写。
+//! 每张表只覆盖配置的地址范围，超出范围的访问返回 `IllegalDataAddress`，与真实设备一致。
+//! 每个站号另外保存一组设备标识对象，用于应答 43/14 读设备标识，
+//! 以及诊断计数器，用于应答 08 诊断和 11、12 通信事件请求。
+
+use super::diagnostics::Diagnostics;
+use crate::modbus::pdu::{
+    DeviceIdentification, DiagnosticCode, ExceptionCode, RESTART_CLEAR_LOG, ReadDeviceIdCode,
+    Request, Response,
+};
+use std::collections::BTreeMap;
+
+/// 每张表默认的地址数量
+pub const DEFAULT_TABLE_SIZE: usize = 10000;
+/// 广播站号，所有站号执行写请求但都不应答
+///
+/// 只有串口帧格式（包括 TCP 上的 RTU/ASCII）有广播，Modbus TCP 中站号 0 表示直接访问服务器。
+pub const BROADCAST_UNIT: u8 = 0;
+
+/// 一次读取线圈/离散输入的最大数量
+const MAX_READ_BITS: u16 = 2000;
+/// 一次读取寄存器的最大数量
+const MAX_READ_REGISTERS: u16 = 125;
+/// 一次写入多个线圈的最大数量
+const MAX_WRITE_BITS: usize = 1968;
+/// 一次写入多个寄存器的最大数量
+const MAX_WRITE_REGISTERS: usize = 123;
+/// 读写多个寄存器时一次写入的最大数量
+const MAX_READ_WRITE_REGISTERS: usize = 121;
+/// Modbus 地址空间的大小
+const ADDRESS_SPACE: usize = 0x10000;
+/// 读设备标识应答 PDU 的最大长度
+const MAX_IDENTIFICATION_PDU: usize = 253;
+/// 应答 PDU 中对象列表之前的字节：功能码、MEI 类型、读取类别、等级、后续标志、下一对象、对象数
+const IDENTIFICATION_HEADER: usize = 7;
+/// 单个标识对象的最大长度，保证单独读取时也能放进一帧
+pub const MAX_OBJECT_LEN: usize = MAX_IDENTIFICATION_PDU - IDENTIFICATION_HEADER - 2;
+
+/// 四张数据表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Coils,
+    DiscreteInputs,
+    HoldingRegisters,
+    InputRegisters,
+}
+
+impl TableKind {
+    pub const ALL: [TableKind; 4] = [
+        TableKind::Coils,
+        TableKind::DiscreteInputs,
+        TableKind::HoldingRegisters,
+        TableKind::InputRegisters,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TableKind::Coils => "线圈",
+            TableKind::DiscreteInputs => "离散输入",
+            TableKind::HoldingRegisters => "保持寄存器",
+            TableKind::InputRegisters => "输入寄存器",
+        }
+    }
+
+    /// 文件中使用的名称
+    pub fn key(self) -> &'static str {
+        match self {
+            TableKind::Coils => "coil",
+            TableKind::DiscreteInputs => "discrete_input",
+            TableKind::HoldingRegisters => "holding_register",
+            TableKind::InputRegisters => "input_register",
+        }
+    }
+
+    /// 按文件中的名称或界面上的名称查找
+    pub fn from_key(key: &str) -> Option<TableKind> {
+        let key = key.trim();
+        TableKind::ALL
+            .into_iter()
+            .find(|kind| kind.key().eq_ignore_ascii_case(key) || kind.label() == key)
+    }
+
+    /// 是否是按位访问的表
+    pub fn is_bits(self) -> bool {
+        matches!(self, TableKind::Coils | TableKind::DiscreteInputs)
+    }
+}
+
+/// 一张表，覆盖从 `start` 开始的一段连续地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table<T> {
+    start: u16,
+    values: Vec<T>,
+}
+
+impl<T: Copy + Default> Table<T> {
+    /// 数量超出地址空间的部分会被截掉
+    pub fn new(start: u16, count: usize) -> Self {
+        let count = count.min(ADDRESS_SPACE - start as usize);
+        Self {
+            start,
+            values: vec![T::default(); count],
+        }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// 修改地址范围，新旧范围重叠部分的值保持不变
+    pub fn resize(&mut self, start: u16, count: usize) {
+        let mut resized = Table::new(start, count);
+        for (offset, value) in resized.values.iter_mut().enumerate() {
+            if let Some(old) = self.get(start as usize + offset) {
+                *value = old;
+            }
+        }
+        *self = resized;
+    }
+
+    pub fn get(&self, address: usize) -> Option<T> {
+        self.index(address).map(|index| self.values[index])
+    }
+
+    pub fn get_mut(&mut self, address: usize) -> Option<&mut T> {
+        self.index(address).map(|index| &mut self.values[index])
+    }
+
+    /// 按顺序遍历 `(地址, 值)`
+    pub fn iter(&self) -> impl Iterator<Item = (u16, T)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(offset, &value)| (self.start.wrapping_add(offset as u16), value))
+    }
+
+    fn index(&self, address: usize) -> Option<usize> {
+        let index = address.checked_sub(self.start as usize)?;
+        (index < self.values.len()).then_some(index)
+    }
+
+    fn slice(&self, address: u16, quantity: usize) -> Option<std::ops::Range<usize>> {
+        let start = self.index(address as usize)?;
+        let end = start + quantity;
+        (end <= self.values.len()).then_some(start..end)
+    }
+
+    pub fn read(&self, address: u16, quantity: u16) -> Result<Vec<T>, ExceptionCode> {
+        self.slice(address, quantity as usize)
+            .map(|range| self.values[range].to_vec())
+            .ok_or(ExceptionCode::IllegalDataAddress)
+    }
+
+    pub fn write(&mut self, address: u16, values: &[T]) -> Result<(), ExceptionCode> {
+        let range = self
+            .slice(address, values.len())
+            .ok_or(ExceptionCode::IllegalDataAddress)?;
+        self.values[range].copy_from_slice(values);
+        Ok(())
+    }
+}
+
+/// 一个站号的四张表
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitStore {
+    pub coils: Table<bool>,
+    pub discrete_inputs: Table<bool>,
+    pub holding_registers: Table<u16>,
+    pub input_registers: Table<u16>,
+    /// 设备标识对象，编号到内容
+    pub identification: BTreeMap<u8, String>,
+    /// 诊断计数器和事件记录，不保存到工作区
+    pub diagnostics: Diagnostics,
+}
+
+impl Default for UnitStore {
+    fn default() -> Self {
+        Self {
+            coils: Table::new(0, DEFAULT_TABLE_SIZE),
+            discrete_inputs: Table::new(0, DEFAULT_TABLE_SIZE),
+            holding_registers: Table::new(0, DEFAULT_TABLE_SIZE),
+            input_registers: Table::new(0, DEFAULT_TABLE_SIZE),
+            identification: default_identification(),
+            diagnostics: Diagnostics::default(),
+        }
+    }
+}
+
+/// 新站号默认的设备标识：基本类别的三个必需对象和产品名称
+pub fn default_identification() -> BTreeMap<u8, String> {
+    BTreeMap::from([
+        (0x00, "Modbus Tool".to_string()),
+        (0x01, "MBT-SIM".to_string()),
+        (0x02, env!("CARGO_PKG_VERSION").to_string()),
+        (0x04, "Modbus 从机模拟器".to_string()),
+    ])
+}
+
+impl UnitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 表的地址范围 `(起始地址, 数量)`
+    pub fn range(&self, kind: TableKind) -> (u16, usize) {
+        match kind {
+            TableKind::Coils => (self.coils.start(), self.coils.len()),
+            TableKind::DiscreteInputs => (self.discrete_inputs.start(), self.discrete_inputs.len()),
+            TableKind::HoldingRegisters => {
+                (self.holding_registers.start(), self.holding_registers.len())
+            }
+            TableKind::InputRegisters => (self.input_registers.start(), self.input_registers.len()),
+        }
+    }
+
+    pub fn resize(&mut self, kind: TableKind, start: u16, count: usize) {
+        match kind {
+            TableKind::Coils => self.coils.resize(start, count),
+            TableKind::DiscreteInputs => self.discrete_inputs.resize(start, count),
+            TableKind::HoldingRegisters => self.holding_registers.resize(start, count),
+            TableKind::InputRegisters => self.input_registers.resize(start, count),
+        }
+    }
+
+    /// 扩大地址范围使其包含 `[address, address + quantity)`，已覆盖时不变
+    pub fn extend_range(&mut self, kind: TableKind, address: u16, quantity: usize) {
+        let (start, count) = (self.range(kind).0 as usize, self.range(kind).1);
+        let (address, end) = (address as usize, address as usize + quantity);
+        let (new_start, new_end) = if count == 0 {
+            (address, end)
+        } else {
+            (start.min(address), (start + count).max(end))
+        };
+        if (new_start, new_end) != (start, start + count) {
+            self.resize(kind, new_start as u16, new_end - new_start);
+        }
+    }
+
+    /// 按请求读写存储，返回应答或异常码
+    pub fn handle(&mut self, request: &Request) -> Result<Response, ExceptionCode> {
+        match request {
+            Request::ReadCoils(address, quantity) => self
+                .read_coils(*address, *quantity)
+                .map(Response::ReadCoils),
+            Request::ReadDiscreteInputs(address, quantity) => self
+                .read_discrete_inputs(*address, *quantity)
+                .map(Response::ReadDiscreteInputs),
+            Request::ReadHoldingRegisters(address, quantity) => self
+                .read_holding_registers(*address, *quantity)
+                .map(Response::ReadHoldingRegisters),
+            Request::ReadInputRegisters(address, quantity) => self
+                .read_input_registers(*address, *quantity)
+                .map(Response::ReadInputRegisters),
+            Request::WriteSingleCoil(address, value) => self
+                .write_single_coil(*address, *value)
+                .map(|_| Response::WriteSingleCoil(*address, *value)),
+            Request::WriteSingleRegister(address, value) => self
+                .write_single_register(*address, *value)
+                .map(|_| Response::WriteSingleRegister(*address, *value)),
+            Request::WriteMultipleCoils(address, values) => self
+                .write_multiple_coils(*address, values)
+                .map(|_| Response::WriteMultipleCoils(*address, values.len() as u16)),
+            Request::WriteMultipleRegisters(address, values) => self
+                .write_multiple_registers(*address, values)
+                .map(|_| Response::WriteMultipleRegisters(*address, values.len() as u16)),
+            Request::MaskWriteRegister(address, and_mask, or_mask) => self
+                .mask_write_register(*address, *and_mask, *or_mask)
+                .map(|_| Response::MaskWriteRegister(*address, *and_mask, *or_mask)),
+            Request::ReadWriteMultipleRegisters(read_address, quantity, write_address, values) => {
+                self.read_write_multiple_registers(*read_address, *quantity, *write_address, values)
+                    .map(Response::ReadWriteMultipleRegisters)
+            }
+            Request::Diagnostics(code, data) => self
+                .diagnose(*code, data)
+                .map(|data| Response::Diagnostics(*code, data)),
+            Request::GetCommEventCounter => Ok(Response::GetCommEventCounter(
+                0,
+                self.diagnostics.event_count,
+            )),
+            Request::GetCommEventLog => Ok(Response::GetCommEventLog(self.diagnostics.event_log())),
+            Request::ReadDeviceIdentification(code, object) => self
+                .read_device_identification(*code, *object)
+                .map(Response::ReadDeviceIdentification),
+        }
+    }
+
+    /// 处理请求并计入本站的诊断计数，`request` 为 `Err` 时是解析请求失败的异常码
+    fn handle_counted(
+        &mut self,
+        request: Result<&Request, ExceptionCode>,
+        broadcast: bool,
+    ) -> Result<Response, ExceptionCode> {
+        self.diagnostics.record_request(broadcast);
+        let result = request.and_then(|request| self.handle(request));
+        let function = request.ok().map(Request::function);
+        self.diagnostics.record_result(function, &result, broadcast);
+        result
+    }
+
+    /// 执行诊断子功能，返回应答中的数据
+    ///
+    /// 除回显外，请求数据必须是 0x0000，重启通信还可以是 0xFF00。
+    pub fn diagnose(
+        &mut self,
+        code: DiagnosticCode,
+        data: &[u16],
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        match (code, data) {
+            (DiagnosticCode::ReturnQueryData, _) => Ok(data.to_vec()),
+            (DiagnosticCode::RestartCommunications, [0x0000 | RESTART_CLEAR_LOG]) => {
+                log::info!("从机重启通信");
+                self.diagnostics.restart(data[0] == RESTART_CLEAR_LOG);
+                Ok(data.to_vec())
+            }
+            (DiagnosticCode::ClearCounters, [0x0000]) => {
+                self.diagnostics.clear_counters();
+                Ok(data.to_vec())
+            }
+            (code, [0x0000]) => self
+                .diagnostics
+                .counter(code)
+                .map(|count| vec![count])
+                .ok_or(ExceptionCode::IllegalDataValue),
+            _ => Err(ExceptionCode::IllegalDataValue),
+        }
+    }
+
+    /// 按读取类别返回设备标识对象
+    ///
+    /// 流式读取（基本、常规、扩展）从 `object` 开始，`object` 不在该类别中时从头读取；
+    /// 一帧放不下时设置下一个对象编号，由主机继续读取。单个读取时对象不存在返回
+    /// `IllegalDataAddress`。
+    pub fn read_device_identification(
+        &self,
+        code: ReadDeviceIdCode,
+        object: u8,
+    ) -> Result<DeviceIdentification, ExceptionCode> {
+        // 超长的内容在字符边界处截断
+        let value = |text: &String| {
+            let end = (0..=text.len().min(MAX_OBJECT_LEN))
+                .rev()
+                .find(|&end| text.is_char_boundary(end))
+                .unwrap_or_default();
+            text.as_bytes()[..end].to_vec()
+        };
+        let mut identification = DeviceIdentification {
+            code: code.code(),
+            conformity: self.identification_conformity(),
+            ..Default::default()
+        };
+        let last = match code {
+            ReadDeviceIdCode::Basic => 0x02,
+            ReadDeviceIdCode::Regular => 0x06,
+            ReadDeviceIdCode::Extended => 0xFF,
+            ReadDeviceIdCode::Individual => {
+                let text = self
+                    .identification
+                    .get(&object)
+                    .ok_or(ExceptionCode::IllegalDataAddress)?;
+                identification.objects.push((object, value(text)));
+                return Ok(identification);
+            }
+        };
+        let first = if self.identification.contains_key(&object) && object <= last {
+            object
+        } else {
+            0
+        };
+        let mut length = IDENTIFICATION_HEADER;
+        for (&id, text) in self.identification.range(first..=last) {
+            let value = value(text);
+            length += 2 + value.len();
+            if length > MAX_IDENTIFICATION_PDU {
+                identification.next_object = Some(id);
+                break;
+            }
+            identification.objects.push((id, value));
+        }
+        Ok(identification)
+    }
+
+    /// 设备标识等级：按已配置的最高对象编号，都支持单个读取
+    fn identification_conformity(&self) -> u8 {
+        match self.identification.keys().next_back() {
+            Some(0x80..) => 0x83,
+            Some(0x03..) => 0x82,
+            _ => 0x81,
+        }
+    }
+
+    pub fn read_coils(&self, address: u16, quantity: u16) -> Result<Vec<bool>, ExceptionCode> {
+        check_quantity(quantity as usize, MAX_READ_BITS as usize)?;
+        self.coils.read(address, quantity)
+    }
+
+    pub fn read_discrete_inputs(
+        &self,
+        address: u16,
+        quantity: u16,
+    ) -> Result<Vec<bool>, ExceptionCode> {
+        check_quantity(quantity as usize, MAX_READ_BITS as usize)?;
+        self.discrete_inputs.read(address, quantity)
+    }
+
+    pub fn read_holding_registers(
+        &self,
+        address: u16,
+        quantity: u16,
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        check_quantity(quantity as usize, MAX_READ_REGISTERS as usize)?;
+        self.holding_registers.read(address, quantity)
+    }
+
+    pub fn read_input_registers(
+        &self,
+        address: u16,
+        quantity: u16,
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        check_quantity(quantity as usize, MAX_READ_REGISTERS as usize)?;
+        self.input_registers.read(address, quantity)
+    }
+
+    pub fn write_single_coil(&mut self, address: u16, value: bool) -> Result<(), ExceptionCode> {
+        self.coils.write(address, &[value])
+    }
+
+    pub fn write_multiple_coils(
+        &mut self,
+        address: u16,
+        values: &[bool],
+    ) -> Result<(), ExceptionCode> {
+        check_quantity(values.len(), MAX_WRITE_BITS)?;
+        self.coils.write(address, values)
+    }
+
+    pub fn write_single_register(&mut self, address: u16, value: u16) -> Result<(), ExceptionCode> {
+        self.holding_registers.write(address, &[value])
+    }
+
+    pub fn write_multiple_registers(
+        &mut self,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), ExceptionCode> {
+        check_quantity(values.len(), MAX_WRITE_REGISTERS)?;
+        self.holding_registers.write(address, values)
+    }
+
+    /// 功能码 22：结果 = (当前值 AND and_mask) OR (or_mask AND NOT and_mask)
+    pub fn mask_write_register(
+        &mut self,
+        address: u16,
+        and_mask: u16,
+        or_mask: u16,
+    ) -> Result<(), ExceptionCode> {
+        let value = self
+            .holding_registers
+            .get_mut(address as usize)
+            .ok_or(ExceptionCode::IllegalDataAddress)?;
+        *value = (*value & and_mask) | (or_mask & !and_mask);
+        Ok(())
+    }
+
+    /// 功能码 23：先写入再读取，两段地址都必须在范围内才会执行
+    pub fn read_write_multiple_registers(
+        &mut self,
+        read_address: u16,
+        quantity: u16,
+        write_address: u16,
+        values: &[u16],
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        check_quantity(quantity as usize, MAX_READ_REGISTERS as usize)?;
+        check_quantity(values.len(), MAX_READ_WRITE_REGISTERS)?;
+        // 先检查读取范围，避免写入后才发现读取越界
+        self.holding_registers.read(read_address, quantity)?;
+        self.holding_registers.write(write_address, values)?;
+        self.holding_registers.read(read_address, quantity)
+    }
+}
+
+/// 所有站号的存储
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterStore {
+    units: BTreeMap<u8, UnitStore>,
+}
+
+impl Default for RegisterStore {
+    /// 默认只有站号 1
+    fn default() -> Self {
+        Self {
+            units: BTreeMap::from([(1, UnitStore::default())]),
+        }
+    }
+}
+
+impl RegisterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 已配置的站号，从小到大
+    pub fn unit_ids(&self) -> Vec<u8> {
+        self.units.keys().copied().collect()
+    }
+
+    pub fn unit(&self, unit: u8) -> Option<&UnitStore> {
+        self.units.get(&unit)
+    }
+
+    pub fn unit_mut(&mut self, unit: u8) -> Option<&mut UnitStore> {
+        self.units.get_mut(&unit)
+    }
+
+    /// 添加使用默认地址范围的站号，站号已存在或为广播站号时返回 `false`
+    pub fn add_unit(&mut self, unit: u8) -> bool {
+        if unit == BROADCAST_UNIT || self.units.contains_key(&unit) {
+            return false;
+        }
+        self.units.insert(unit, UnitStore::default());
+        true
+    }
+
+    pub fn remove_unit(&mut self, unit: u8) -> Option<UnitStore> {
+        self.units.remove(&unit)
+    }
+
+    /// 按站号处理请求，返回 `None` 表示不应答
+    ///
+    /// 未配置的站号不应答，由主机按超时处理；广播请求由所有站号执行，同样不应答。
+    /// 所有站号都把请求计入总线报文计数。
+    pub fn handle(
+        &mut self,
+        unit: u8,
+        request: &Request,
+    ) -> Option<Result<Response, ExceptionCode>> {
+        self.handle_parsed(unit, Ok(request), true)
+    }
+
+    /// 按 Modbus TCP 的规则处理请求，返回 `None` 表示不应答
+    ///
+    /// Modbus TCP 没有广播，站号 0 的请求交给最小的已配置站号执行并应答，其他站号同 `handle`。
+    pub fn handle_tcp(
+        &mut self,
+        unit: u8,
+        request: &Request,
+    ) -> Option<Result<Response, ExceptionCode>> {
+        self.handle_parsed(unit, Ok(request), false)
+    }
+
+    /// 处理从报文解析出的请求，`request` 为 `Err` 时是解析失败要应答的异常码
+    ///
+    /// 解析失败的请求同样先计入总线报文再按站号路由，未配置的站号和广播请求都不应答，
+    /// 发给本站的计入异常应答计数。`broadcast` 为假时按 Modbus TCP 处理站号 0。
+    pub fn handle_parsed(
+        &mut self,
+        unit: u8,
+        request: Result<&Request, ExceptionCode>,
+        broadcast: bool,
+    ) -> Option<Result<Response, ExceptionCode>> {
+        for store in self.units.values_mut() {
+            store.diagnostics.record_bus_message();
+        }
+        let unit = match unit {
+            BROADCAST_UNIT if broadcast => {
+                for store in self.units.values_mut() {
+                    let _ = store.handle_counted(request, true);
+                }
+                return None;
+            }
+            BROADCAST_UNIT => *self.units.keys().next()?,
+            unit => unit,
+        };
+        self.units
+            .get_mut(&unit)
+            .map(|store| store.handle_counted(request, false))
+    }
+
+    /// 总线上一帧校验失败，计入所有站号的总线通信错误计数
+    pub fn record_frame_error(&mut self) {
+        for store in self.units.values_mut() {
+            store.diagnostics.record_communication_error();
+        }
+    }
+}
+
+fn check_quantity(quantity: usize, max: usize) -> Result<(), ExceptionCode> {
+    if quantity == 0 || quantity > max {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_then_read_registers() {
+        let mut store = UnitStore::new();
+        store.write_multiple_registers(10, &[1, 2, 3]).unwrap();
+        assert_eq!(store.read_holding_registers(10, 3).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let store = UnitStore::new();
+        assert_eq!(
+            store.read_coils(DEFAULT_TABLE_SIZE as u16 - 1, 2),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            store.read_input_registers(0, 126),
+            Err(ExceptionCode::IllegalDataValue)
+        );
+    }
+
+    #[test]
+    fn test_configured_range() {
+        let mut store = UnitStore::new();
+        store.write_single_register(40, 7).unwrap();
+        store.resize(TableKind::HoldingRegisters, 40, 10);
+        assert_eq!(store.read_holding_registers(40, 1), Ok(vec![7]));
+        assert_eq!(
+            store.read_holding_registers(39, 1),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            store.read_holding_registers(45, 6),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn test_mask_write_and_read_write() {
+        let mut store = UnitStore::new();
+        store.write_single_register(4, 0x12).unwrap();
+        store.mask_write_register(4, 0xF2, 0x25).unwrap();
+        assert_eq!(store.read_holding_registers(4, 1), Ok(vec![0x17]));
+
+        let read = store.read_write_multiple_registers(4, 2, 5, &[9]).unwrap();
+        assert_eq!(read, vec![0x17, 9]);
+    }
+
+    #[test]
+    fn test_units_and_broadcast() {
+        let mut store = RegisterStore::new();
+        assert!(store.add_unit(2));
+        assert_eq!(
+            store.handle(3, &Request::ReadCoils(0, 1)),
+            None,
+            "未配置的站号不应答"
+        );
+        assert_eq!(
+            store.handle(BROADCAST_UNIT, &Request::WriteSingleCoil(0, true)),
+            None
+        );
+        for unit in [1, 2] {
+            assert_eq!(
+                store.handle(unit, &Request::ReadCoils(0, 1)),
+                Some(Ok(Response::ReadCoils(vec![true])))
+            );
+        }
+
+        // Modbus TCP 的站号 0 只由站号 1 执行并应答
+        assert_eq!(
+            store.handle_tcp(BROADCAST_UNIT, &Request::WriteSingleCoil(1, true)),
+            Some(Ok(Response::WriteSingleCoil(1, true)))
+        );
+        assert_eq!(
+            store.handle(2, &Request::ReadCoils(1, 1)),
+            Some(Ok(Response::ReadCoils(vec![false])))
+        );
+    }
+
+    #[test]
+    fn test_read_device_identification() {
+        let mut store = UnitStore::new();
+        store.identification.insert(0x05, "M-1".to_string());
+        store
+            .identification
+            .insert(0x80, "x".repeat(MAX_OBJECT_LEN + 10));
+        store.identification.insert(0x81, "y".repeat(100));
+
+        let basic = store
+            .read_device_identification(ReadDeviceIdCode::Basic, 0)
+            .unwrap();
+        assert_eq!(basic.conformity, 0x83);
+        assert_eq!(basic.objects.len(), 3);
+        assert_eq!(basic.text(0x02).as_deref(), Some(env!("CARGO_PKG_VERSION")));
+
+        // 不在类别中的对象从头读取
+        let regular = store
+            .read_device_identification(ReadDeviceIdCode::Regular, 0x80)
+            .unwrap();
+        assert_eq!(regular.objects.first().map(|(id, _)| *id), Some(0x00));
+        assert_eq!(regular.text(0x05).as_deref(), Some("M-1"));
+        assert_eq!(regular.next_object, None);
+
+        let first = store
+            .read_device_identification(ReadDeviceIdCode::Extended, 0)
+            .unwrap();
+        assert_eq!(first.next_object, Some(0x80));
+        let second = store
+            .read_device_identification(ReadDeviceIdCode::Extended, 0x80)
+            .unwrap();
+        assert_eq!(second.objects[0].1.len(), MAX_OBJECT_LEN);
+        assert_eq!(second.next_object, Some(0x81));
+        assert!(second.encode().len() < MAX_IDENTIFICATION_PDU);
+
+        assert_eq!(
+            store.read_device_identification(ReadDeviceIdCode::Individual, 0x03),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut store = RegisterStore::new();
+        store.add_unit(2);
+        let echo = Request::Diagnostics(DiagnosticCode::ReturnQueryData, vec![0xA537]);
+        assert_eq!(
+            store.handle(1, &echo),
+            Some(Ok(Response::Diagnostics(
+                DiagnosticCode::ReturnQueryData,
+                vec![0xA537]
+            )))
+        );
+        store.handle(2, &Request::ReadCoils(0, 1));
+        store.handle(BROADCAST_UNIT, &Request::WriteSingleCoil(0, true));
+        store.record_frame_error();
+
+        let count = |store: &mut RegisterStore, code| match store
+            .handle(1, &Request::Diagnostics(code, vec![0]))
+        {
+            Some(Ok(Response::Diagnostics(_, data))) => data[0],
+            other => panic!("{:?}", other),
+        };
+        // 本次请求也计入总线报文
+        assert_eq!(count(&mut store, DiagnosticCode::BusMessageCount), 4);
+        assert_eq!(count(&mut store, DiagnosticCode::ServerMessageCount), 4);
+        assert_eq!(count(&mut store, DiagnosticCode::ServerNoResponseCount), 1);
+        assert_eq!(
+            count(&mut store, DiagnosticCode::BusCommunicationErrorCount),
+            1
+        );
+        assert_eq!(
+            store.handle(1, &Request::GetCommEventCounter),
+            Some(Ok(Response::GetCommEventCounter(0, 6)))
+        );
+
+        assert_eq!(
+            store.handle(
+                1,
+                &Request::Diagnostics(DiagnosticCode::BusMessageCount, vec![1])
+            ),
+            Some(Err(ExceptionCode::IllegalDataValue))
+        );
+        assert_eq!(count(&mut store, DiagnosticCode::BusExceptionErrorCount), 1);
+        store.handle(
+            1,
+            &Request::Diagnostics(DiagnosticCode::ClearCounters, vec![0]),
+        );
+        assert_eq!(count(&mut store, DiagnosticCode::BusMessageCount), 1);
+        let Some(Ok(Response::GetCommEventLog(log))) = store.handle(1, &Request::GetCommEventLog)
+        else {
+            panic!("应答类型不符");
+        };
+        assert_eq!(log.message_count, 2);
+        assert_eq!(log.events[0], 0x80);
+    }
+}
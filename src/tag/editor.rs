@@ -0,0 +1,203 @@
+//! 标签表的编辑，主机和从机页面共用
+
+use super::map::{self, MapFormat, MapPoint};
+use super::{SharedTags, Tag};
+use crate::app_ui::show_value_format;
+use crate::slave::store::TableKind;
+use eframe::*;
+use std::path::Path;
+
+/// 寄存器表文件的路径输入和导入导出按钮
+#[derive(Debug, Default)]
+pub struct MapFile {
+    path: String,
+    //最近一次导入或导出的结果，失败时是所有出错的行
+    outcome: Option<Result<String, Vec<String>>>,
+}
+
+impl MapFile {
+    /// 导入成功时替换标签列表并返回导入的点，`value` 给出导出时每个标签的当前值
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        tags: &SharedTags,
+        value: impl Fn(&Tag) -> Option<String>,
+    ) -> Option<Vec<MapPoint>> {
+        let mut imported = None;
+        ui.horizontal(|ui| {
+            ui.label("寄存器表:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.path)
+                    .hint_text("map.csv 或 map.json")
+                    .desired_width(240.0),
+            );
+            if ui
+                .button("导入")
+                .on_hover_text("导入会替换当前的标签")
+                .clicked()
+            {
+                match self.import(tags) {
+                    Ok(points) => imported = Some(points),
+                    Err(errors) => self.outcome = Some(Err(errors)),
+                }
+            }
+            if ui.button("导出").clicked() {
+                self.outcome = Some(self.export(tags, value).map_err(|err| vec![err]));
+            }
+        });
+        match &self.outcome {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(errors)) => {
+                egui::ScrollArea::vertical()
+                    .id_salt("map_file_errors")
+                    .max_height(120.0)
+                    .show(ui, |ui| {
+                        for err in errors {
+                            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                        }
+                    });
+            }
+            None => {}
+        }
+        imported
+    }
+
+    fn format(&self) -> Result<MapFormat, String> {
+        MapFormat::from_path(Path::new(self.path.trim()))
+            .ok_or_else(|| "只支持 .csv 和 .json 文件".to_string())
+    }
+
+    fn import(&mut self, tags: &SharedTags) -> Result<Vec<MapPoint>, Vec<String>> {
+        let format = self.format().map_err(|err| vec![err])?;
+        let path = self.path.trim();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| vec![format!("读取 {} 失败: {}", path, err)])?;
+        let points = map::import(&text, format)
+            .map_err(|errors| errors.iter().map(ToString::to_string).collect::<Vec<_>>())?;
+        log::info!("从 {} 导入 {} 个标签", path, points.len());
+        *tags.lock().unwrap() = points.iter().map(|point| point.tag.clone()).collect();
+        self.outcome = Some(Ok(format!("已导入 {} 个标签", points.len())));
+        Ok(points)
+    }
+
+    fn export(
+        &self,
+        tags: &SharedTags,
+        value: impl Fn(&Tag) -> Option<String>,
+    ) -> Result<String, String> {
+        let format = self.format()?;
+        let points: Vec<MapPoint> = tags
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tag| MapPoint {
+                tag: tag.clone(),
+                value: value(tag),
+            })
+            .collect();
+        let path = self.path.trim();
+        std::fs::write(path, map::export(&points, format))
+            .map_err(|err| format!("写入 {} 失败: {}", path, err))?;
+        log::info!("导出 {} 个标签到 {}", points.len(), path);
+        Ok(format!("已导出 {} 个标签", points.len()))
+    }
+}
+
+/// 显示标签表，定义直接在表中修改，`show_value` 显示每个标签的当前值
+pub fn show_tags(
+    ui: &mut egui::Ui,
+    id_salt: &str,
+    tags: &SharedTags,
+    mut show_value: impl FnMut(&mut egui::Ui, &Tag),
+) {
+    let mut tags = tags.lock().unwrap();
+    if ui.button("添加标签").clicked() {
+        let id = tags.iter().map(|tag| tag.id).max().unwrap_or_default() + 1;
+        // 新标签接在上一个标签之后
+        let tag = match tags.last() {
+            Some(last) => Tag {
+                id,
+                name: format!("标签 {}", id),
+                address: last.address.saturating_add(last.quantity()),
+                ..last.clone()
+            },
+            None => Tag {
+                id,
+                name: format!("标签 {}", id),
+                ..Default::default()
+            },
+        };
+        tags.push(tag);
+    }
+    let mut removed = None;
+    egui::ScrollArea::both().id_salt(id_salt).show(ui, |ui| {
+        egui::Grid::new((id_salt, "grid"))
+            .num_columns(13)
+            .spacing([12.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for title in [
+                    "名称", "站号", "表", "地址", "类型", "比例", "偏移", "单位", "最小", "最大",
+                    "说明", "值", "",
+                ] {
+                    ui.strong(title);
+                }
+                ui.end_row();
+                for tag in tags.iter_mut() {
+                    show_tag_row(ui, id_salt, tag);
+                    show_value(ui, tag);
+                    if ui.button("删除").clicked() {
+                        removed = Some(tag.id);
+                    }
+                    ui.end_row();
+                }
+            });
+        if tags.is_empty() {
+            ui.label("点击“添加标签”为寄存器命名并设置换算");
+        }
+    });
+    if let Some(id) = removed {
+        tags.retain(|tag| tag.id != id);
+    }
+}
+
+fn show_tag_row(ui: &mut egui::Ui, id_salt: &str, tag: &mut Tag) {
+    ui.add(egui::TextEdit::singleline(&mut tag.name).desired_width(120.0));
+    ui.add(egui::DragValue::new(&mut tag.unit));
+    egui::ComboBox::from_id_salt((id_salt, "table", tag.id))
+        .selected_text(tag.table.label())
+        .show_ui(ui, |ui| {
+            for kind in TableKind::ALL {
+                ui.selectable_value(&mut tag.table, kind, kind.label());
+            }
+        });
+    ui.add(egui::DragValue::new(&mut tag.address));
+    ui.horizontal(|ui| {
+        if tag.table.is_bits() {
+            ui.label("位");
+        } else {
+            show_value_format(ui, &format!("{}_{}", id_salt, tag.id), &mut tag.format);
+        }
+    });
+    ui.add(egui::DragValue::new(&mut tag.scale).speed(0.01));
+    ui.add(egui::DragValue::new(&mut tag.offset).speed(0.1));
+    ui.add(egui::TextEdit::singleline(&mut tag.unit_text).desired_width(40.0));
+    show_limit(ui, &mut tag.min);
+    show_limit(ui, &mut tag.max);
+    ui.add(egui::TextEdit::singleline(&mut tag.description).desired_width(120.0));
+}
+
+/// 可选的最小值或最大值，不勾选表示不限制
+fn show_limit(ui: &mut egui::Ui, limit: &mut Option<f64>) {
+    ui.horizontal(|ui| {
+        let mut enabled = limit.is_some();
+        if ui.checkbox(&mut enabled, "").changed() {
+            *limit = enabled.then_some(0.0);
+        }
+        if let Some(value) = limit {
+            ui.add(egui::DragValue::new(value).speed(0.1));
+        }
+    });
+}
@@ -0,0 +1,459 @@
+//! 寄存器表文件
+//!
+//! 厂家提供的寄存器表整理成 CSV 或 JSON 后可以导入为标签，当前的标签也可以导出。
+//! CSV 第一行是列名，列的顺序任意，除 `table` 和 `address` 外都可以省略；
+//! JSON 为 `{"version": 1, "points": [...]}`，每个点的字段与 CSV 的列名相同。
+
+use super::Tag;
+use crate::modbus::value::{ByteOrder, DataType, ValueFormat};
+use crate::slave::store::TableKind;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// 当前的 JSON 格式版本
+const VERSION: u64 = 1;
+
+/// 文件中的列，也是 JSON 中点的字段
+const COLUMNS: [&str; 13] = [
+    "name",
+    "unit",
+    "table",
+    "address",
+    "type",
+    "order",
+    "scale",
+    "offset",
+    "unit_text",
+    "min",
+    "max",
+    "value",
+    "description",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MapFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl MapFormat {
+    /// 按扩展名判断格式
+    pub fn from_path(path: &Path) -> Option<MapFormat> {
+        let extension = path.extension()?.to_str()?;
+        if extension.eq_ignore_ascii_case("csv") {
+            Some(MapFormat::Csv)
+        } else if extension.eq_ignore_ascii_case("json") {
+            Some(MapFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// 寄存器表中的一个点
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPoint {
+    pub tag: Tag,
+    /// 从机存储的初始工程值，主机忽略
+    pub value: Option<String>,
+}
+
+/// 导入失败的一行，`position` 是 CSV 的行号或 JSON 中点的序号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapError {
+    pub position: String,
+    pub message: String,
+}
+
+impl MapError {
+    fn line(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            position: format!("第 {} 行", line),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+/// 解析寄存器表，有任何一行出错时不导入，返回所有出错的行
+pub fn import(text: &str, format: MapFormat) -> Result<Vec<MapPoint>, Vec<MapError>> {
+    let mut points = match format {
+        MapFormat::Csv => import_csv(text)?,
+        MapFormat::Json => import_json(text)?,
+    };
+    for (index, point) in points.iter_mut().enumerate() {
+        point.tag.id = index as u64 + 1;
+    }
+    Ok(points)
+}
+
+/// 生成寄存器表文件的内容
+pub fn export(points: &[MapPoint], format: MapFormat) -> String {
+    match format {
+        MapFormat::Csv => export_csv(points),
+        MapFormat::Json => export_json(points),
+    }
+}
+
+fn import_csv(text: &str) -> Result<Vec<MapPoint>, Vec<MapError>> {
+    // Excel 导出的 UTF-8 文件带 BOM
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let Some((header_line, header)) = lines.next() else {
+        return Err(vec![MapError::line(1, "文件为空")]);
+    };
+    let header = split_csv_line(header)
+        .map_err(|err| vec![MapError::line(header_line, err)])?
+        .into_iter()
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    if let Some(unknown) = header
+        .iter()
+        .find(|column| !COLUMNS.contains(&column.as_str()))
+    {
+        return Err(vec![MapError::line(
+            header_line,
+            format!("未知的列 \"{}\"", unknown),
+        )]);
+    }
+    for required in ["table", "address"] {
+        if !header.iter().any(|column| column == required) {
+            return Err(vec![MapError::line(
+                header_line,
+                format!("缺少 {} 列", required),
+            )]);
+        }
+    }
+
+    let mut points = Vec::new();
+    let mut errors = Vec::new();
+    for (line, text) in lines {
+        let point = split_csv_line(text).and_then(|values| {
+            if values.len() > header.len() {
+                return Err(format!(
+                    "有 {} 列，比列名多 {} 列",
+                    values.len(),
+                    values.len() - header.len()
+                ));
+            }
+            let fields = header.iter().cloned().zip(values).collect();
+            point_from_fields(&fields)
+        });
+        match point {
+            Ok(point) => points.push(point),
+            Err(err) => errors.push(MapError::line(line, err)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(points)
+    } else {
+        Err(errors)
+    }
+}
+
+/// 拆分一行 CSV，支持双引号包围的字段和 `""` 转义，不支持跨行的字段
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (_, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("引号没有闭合".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+fn import_json(text: &str) -> Result<Vec<MapPoint>, Vec<MapError>> {
+    let root: serde_json::Value = serde_json::from_str(text).map_err(|err| {
+        vec![MapError {
+            position: format!("第 {} 行第 {} 列", err.line(), err.column()),
+            message: err.to_string(),
+        }]
+    })?;
+    let file_error = |message: String| {
+        vec![MapError {
+            position: "文件".to_string(),
+            message,
+        }]
+    };
+    if let Some(version) = root.get("version") {
+        match version.as_u64() {
+            Some(version) if version <= VERSION => {}
+            _ => return Err(file_error(format!("不支持的版本 {}", version))),
+        }
+    }
+    let Some(items) = root.get("points").unwrap_or(&root).as_array() else {
+        return Err(file_error("缺少 points 数组".to_string()));
+    };
+
+    points_from_json(items)
+}
+
+/// 解析 JSON 中的点，工作区文件也使用这一格式
+pub(crate) fn points_from_json(
+    items: &[serde_json::Value],
+) -> Result<Vec<MapPoint>, Vec<MapError>> {
+    let mut points = Vec::new();
+    let mut errors = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let point = match item.as_object() {
+            Some(object) => object
+                .iter()
+                .map(|(key, value)| {
+                    if !COLUMNS.contains(&key.as_str()) {
+                        return Err(format!("未知的字段 \"{}\"", key));
+                    }
+                    let value = match value {
+                        serde_json::Value::Null => String::new(),
+                        serde_json::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    Ok((key.clone(), value))
+                })
+                .collect::<Result<HashMap<_, _>, _>>()
+                .and_then(|fields| point_from_fields(&fields)),
+            None => Err("不是对象".to_string()),
+        };
+        match point {
+            Ok(point) => points.push(point),
+            Err(message) => errors.push(MapError {
+                position: format!("第 {} 个点", index + 1),
+                message,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(points)
+    } else {
+        Err(errors)
+    }
+}
+
+/// 按列名取值转换成点，空字段使用默认值
+fn point_from_fields(fields: &HashMap<String, String>) -> Result<MapPoint, String> {
+    let get = |key: &str| {
+        fields
+            .get(key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+    let number = |key: &str| -> Result<Option<f64>, String> {
+        get(key)
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("{} \"{}\" 不是数值", key, value))
+            })
+            .transpose()
+    };
+
+    let table = get("table").ok_or("缺少 table")?;
+    let table = TableKind::from_key(table).ok_or_else(|| format!("未知的表 \"{}\"", table))?;
+    let address = get("address").ok_or("缺少 address")?;
+    let address = parse_address(address).ok_or_else(|| format!("无效的地址 \"{}\"", address))?;
+    let unit = match get("unit") {
+        Some(unit) => unit
+            .parse::<u8>()
+            .ok()
+            .filter(|unit| (1..=247).contains(unit))
+            .ok_or_else(|| format!("无效的站号 \"{}\"", unit))?,
+        None => 1,
+    };
+    let data_type = match get("type") {
+        Some(key) => DataType::from_key(key).ok_or_else(|| format!("未知的类型 \"{}\"", key))?,
+        None => DataType::default(),
+    };
+    let order = match get("order") {
+        Some(label) => {
+            ByteOrder::from_label(label).ok_or_else(|| format!("未知的字节序 \"{}\"", label))?
+        }
+        None => ByteOrder::default(),
+    };
+    let scale = number("scale")?.unwrap_or(1.0);
+    if scale == 0.0 {
+        return Err("scale 不能为 0".to_string());
+    }
+    let tag = Tag {
+        id: 0,
+        name: get("name")
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{} {}", table.label(), address)),
+        unit,
+        table,
+        address,
+        format: ValueFormat { data_type, order },
+        scale,
+        offset: number("offset")?.unwrap_or(0.0),
+        unit_text: get("unit_text").unwrap_or_default().to_string(),
+        min: number("min")?,
+        max: number("max")?,
+        description: get("description").unwrap_or_default().to_string(),
+    };
+    if !table.is_bits() && address as usize + tag.quantity() as usize > 0x10000 {
+        return Err(format!(
+            "{} 从地址 {} 开始超出地址空间",
+            data_type.label(),
+            address
+        ));
+    }
+    Ok(MapPoint {
+        tag,
+        value: get("value").map(str::to_string),
+    })
+}
+
+/// 十进制或 `0x` 开头的十六进制
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn point_fields(point: &MapPoint) -> [String; 13] {
+    let tag = &point.tag;
+    let optional = |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+    [
+        tag.name.clone(),
+        tag.unit.to_string(),
+        tag.table.key().to_string(),
+        tag.address.to_string(),
+        tag.format.data_type.key(),
+        tag.format.order.label().to_string(),
+        tag.scale.to_string(),
+        tag.offset.to_string(),
+        tag.unit_text.clone(),
+        optional(tag.min),
+        optional(tag.max),
+        point.value.clone().unwrap_or_default(),
+        tag.description.clone(),
+    ]
+}
+
+fn export_csv(points: &[MapPoint]) -> String {
+    let mut text = COLUMNS.join(",");
+    text.push('\n');
+    for point in points {
+        let row = point_fields(point)
+            .iter()
+            .map(|field| {
+                if field.contains([',', '"', '\n']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        text.push_str(&row.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+fn export_json(points: &[MapPoint]) -> String {
+    let root = json!({ "version": VERSION, "points": points_to_json(points) });
+    serde_json::to_string_pretty(&root).unwrap_or_default()
+}
+
+pub(crate) fn points_to_json(points: &[MapPoint]) -> Vec<serde_json::Value> {
+    points
+        .iter()
+        .map(|point| {
+            let tag = &point.tag;
+            let mut object = json!({
+                "name": tag.name,
+                "unit": tag.unit,
+                "table": tag.table.key(),
+                "address": tag.address,
+                "type": tag.format.data_type.key(),
+                "order": tag.format.order.label(),
+                "scale": tag.scale,
+                "offset": tag.offset,
+                "unit_text": tag.unit_text,
+                "min": tag.min,
+                "max": tag.max,
+                "description": tag.description,
+            });
+            if let Some(value) = &point.value {
+                object["value"] = json!(value);
+            }
+            object
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\u{feff}name,table,address,type,order,scale,unit_text,description\n\
+        # 电压\n\
+        Voltage L1,holding_register,0x100,u16,ABCD,0.1,V,\"相电压, L1\"\n\
+        Energy,input_register,200,f32,cdab,,kWh,\n";
+
+    #[test]
+    fn test_csv_round_trip() {
+        let points = import(CSV, MapFormat::Csv).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].tag.address, 0x100);
+        assert_eq!(points[0].tag.description, "相电压, L1");
+        assert_eq!(points[1].tag.format.order, ByteOrder::Cdab);
+        assert_eq!(points[1].tag.id, 2);
+
+        for format in [MapFormat::Csv, MapFormat::Json] {
+            let text = export(&points, format);
+            assert_eq!(import(&text, format).unwrap(), points, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_errors_report_lines() {
+        let text = "table,address,type\n\
+            holding_register,1,u16\n\
+            holding_register,x,u16\n\
+            \n\
+            register,2,u16\n\
+            holding_register,3,f128\n";
+        let errors = import(text, MapFormat::Csv).unwrap_err();
+        let positions: Vec<_> = errors.iter().map(|err| err.position.as_str()).collect();
+        assert_eq!(positions, ["第 3 行", "第 5 行", "第 6 行"]);
+
+        let errors = import("name,address\n", MapFormat::Csv).unwrap_err();
+        assert_eq!(errors[0].message, "缺少 table 列");
+
+        let errors = import(
+            r#"{"points": [{"table": "coil", "address": 1}, {"table": "coil"}]}"#,
+            MapFormat::Json,
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].position, "第 2 个点");
+    }
+}
@@ -0,0 +1,285 @@
+//! 标签
+//!
+//! 标签给一段寄存器起名字，并按数据类型、比例和偏移换算成工程值，
+//! 主机页面按标签显示轮询到的值，从机页面按标签查看和修改寄存器存储。
+
+pub mod editor;
+pub mod map;
+
+use crate::modbus::pdu::{FunctionCode, Request, Response};
+use crate::modbus::value::{Value, ValueFormat, encode, parse};
+use crate::slave::store::{RegisterStore, TableKind};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// 主机和从机页面共享的标签列表
+pub type SharedTags = Arc<Mutex<Vec<Tag>>>;
+
+/// 一个标签，工程值 = 原始值 × 比例 + 偏移
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: u64,
+    pub name: String,
+    pub unit: u8,
+    pub table: TableKind,
+    pub address: u16,
+    /// 线圈和离散输入忽略此项
+    pub format: ValueFormat,
+    pub scale: f64,
+    pub offset: f64,
+    /// 工程单位，例如 V、A、kWh
+    pub unit_text: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub description: String,
+}
+
+impl Default for Tag {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            name: String::new(),
+            unit: 1,
+            table: TableKind::HoldingRegisters,
+            address: 0,
+            format: ValueFormat::default(),
+            scale: 1.0,
+            offset: 0.0,
+            unit_text: String::new(),
+            min: None,
+            max: None,
+            description: String::new(),
+        }
+    }
+}
+
+/// 标签的当前值
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagValue {
+    pub value: Value,
+    /// 超出最小值或最大值
+    pub out_of_range: bool,
+}
+
+impl Tag {
+    /// 读取标签的功能码
+    pub fn read_function(&self) -> FunctionCode {
+        match self.table {
+            TableKind::Coils => FunctionCode::ReadCoils,
+            TableKind::DiscreteInputs => FunctionCode::ReadDiscreteInputs,
+            TableKind::HoldingRegisters => FunctionCode::ReadHoldingRegisters,
+            TableKind::InputRegisters => FunctionCode::ReadInputRegisters,
+        }
+    }
+
+    /// 标签占用的地址个数
+    pub fn quantity(&self) -> u16 {
+        if self.table.is_bits() {
+            1
+        } else {
+            self.format.data_type.register_count() as u16
+        }
+    }
+
+    /// 读取标签的请求
+    pub fn request(&self) -> Request {
+        let quantity = self.quantity();
+        match self.table {
+            TableKind::Coils => Request::ReadCoils(self.address, quantity),
+            TableKind::DiscreteInputs => Request::ReadDiscreteInputs(self.address, quantity),
+            TableKind::HoldingRegisters => Request::ReadHoldingRegisters(self.address, quantity),
+            TableKind::InputRegisters => Request::ReadInputRegisters(self.address, quantity),
+        }
+    }
+
+    /// `[start, start + quantity)` 是否完整覆盖标签的地址
+    pub fn is_covered_by(&self, start: u16, quantity: u16) -> bool {
+        let end = start as usize + quantity as usize;
+        self.address >= start && self.address as usize + self.quantity() as usize <= end
+    }
+
+    /// 是否按比例和偏移换算
+    fn is_scaled(&self) -> bool {
+        !self.table.is_bits()
+            && self.format.data_type.is_numeric()
+            && (self.scale != 1.0 || self.offset != 0.0)
+    }
+
+    /// 把原始值换算成工程值，并检查范围
+    pub fn to_engineering(&self, raw: Value) -> TagValue {
+        let value = match raw.as_f64() {
+            Some(number) if self.is_scaled() => Value::Float(number * self.scale + self.offset),
+            _ => raw,
+        };
+        let out_of_range = value.as_f64().is_some_and(|number| {
+            self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max)
+        });
+        TagValue {
+            value,
+            out_of_range,
+        }
+    }
+
+    /// 从起始地址为 `start` 的读应答中取出标签的值，应答不包含标签时返回 `None`
+    pub fn extract(&self, start: u16, response: &Response) -> Option<Result<TagValue, String>> {
+        let offset = self.address.checked_sub(start)? as usize;
+        let quantity = self.quantity() as usize;
+        if self.table.is_bits() {
+            let bit = *response.bits()?.get(offset)?;
+            return Some(Ok(self.to_engineering(Value::Unsigned(bit as u64))));
+        }
+        let span = response.registers()?.get(offset..offset + quantity)?;
+        Some(self.format.decode(span).map(|raw| self.to_engineering(raw)))
+    }
+
+    /// 读取从机寄存器存储中的值
+    pub fn read_store(&self, store: &RegisterStore) -> Result<TagValue, String> {
+        let unit = store
+            .unit(self.unit)
+            .ok_or_else(|| format!("站号 {} 不存在", self.unit))?;
+        let quantity = self.quantity();
+        let raw = match self.table {
+            TableKind::Coils | TableKind::DiscreteInputs => {
+                let table = match self.table {
+                    TableKind::Coils => &unit.coils,
+                    _ => &unit.discrete_inputs,
+                };
+                let bits = table
+                    .read(self.address, quantity)
+                    .map_err(|err| err.to_string())?;
+                Value::Unsigned(bits[0] as u64)
+            }
+            TableKind::HoldingRegisters | TableKind::InputRegisters => {
+                let table = match self.table {
+                    TableKind::HoldingRegisters => &unit.holding_registers,
+                    _ => &unit.input_registers,
+                };
+                let registers = table
+                    .read(self.address, quantity)
+                    .map_err(|err| err.to_string())?;
+                self.format.decode(&registers)?
+            }
+        };
+        Ok(self.to_engineering(raw))
+    }
+
+    /// 把输入的工程值换算回原始值写入从机寄存器存储
+    pub fn write_store(&self, store: &mut RegisterStore, text: &str) -> Result<(), String> {
+        let unit = store
+            .unit_mut(self.unit)
+            .ok_or_else(|| format!("站号 {} 不存在", self.unit))?;
+        if self.table.is_bits() {
+            let bit = match text.trim() {
+                "1" | "on" | "ON" | "true" => true,
+                "0" | "off" | "OFF" | "false" => false,
+                _ => return Err(format!("无法解析位值 \"{}\"", text.trim())),
+            };
+            let table = match self.table {
+                TableKind::Coils => &mut unit.coils,
+                _ => &mut unit.discrete_inputs,
+            };
+            return table
+                .write(self.address, &[bit])
+                .map_err(|err| err.to_string());
+        }
+        let registers = self.encode(text)?;
+        let table = match self.table {
+            TableKind::HoldingRegisters => &mut unit.holding_registers,
+            _ => &mut unit.input_registers,
+        };
+        table
+            .write(self.address, &registers)
+            .map_err(|err| err.to_string())
+    }
+
+    /// 把输入的工程值编码成寄存器，超出最小值或最大值时拒绝
+    pub fn encode(&self, text: &str) -> Result<Vec<u16>, String> {
+        let data_type = self.format.data_type;
+        if !data_type.is_numeric() {
+            return encode(&parse(text, data_type)?, data_type, self.format.order);
+        }
+        let number: f64 = text
+            .trim()
+            .parse()
+            .map_err(|_| format!("无法解析数值 \"{}\"", text.trim()))?;
+        if self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max) {
+            return Err(format!("{} 超出 {} 的范围", number, self.name));
+        }
+        let raw = if self.is_scaled() {
+            if self.scale == 0.0 {
+                return Err("比例不能为 0".to_string());
+            }
+            Value::from_f64((number - self.offset) / self.scale, data_type)?
+        } else {
+            parse(text, data_type)?
+        };
+        encode(&raw, data_type, self.format.order)
+    }
+
+    /// 显示用的地址，例如 `站号 1 保持寄存器 100`
+    pub fn location(&self) -> String {
+        format!("站号 {} {} {}", self.unit, self.table.label(), self.address)
+    }
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            // 换算后的浮点数最多保留 6 位小数，去掉末尾的 0
+            Value::Float(number) => {
+                let text = format!("{:.6}", number);
+                let text = text.trim_end_matches('0').trim_end_matches('.');
+                write!(f, "{}", text)
+            }
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::value::{ByteOrder, DataType};
+
+    fn voltage() -> Tag {
+        Tag {
+            name: "Voltage L1".to_string(),
+            address: 100,
+            scale: 0.1,
+            unit_text: "V".to_string(),
+            min: Some(0.0),
+            max: Some(300.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scaled_value() {
+        let tag = voltage();
+        let response = Response::ReadHoldingRegisters(vec![0, 2314, 0]);
+        let value = tag.extract(99, &response).unwrap().unwrap();
+        assert_eq!(value.to_string(), "231.4");
+        assert!(!value.out_of_range);
+        assert_eq!(tag.extract(101, &response), None);
+        assert_eq!(tag.encode("231.4"), Ok(vec![2314]));
+        assert!(tag.encode("400").is_err());
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let mut store = RegisterStore::default();
+        let tag = Tag {
+            format: ValueFormat {
+                data_type: DataType::F32,
+                order: ByteOrder::Cdab,
+            },
+            offset: -40.0,
+            ..voltage()
+        };
+        tag.write_store(&mut store, "25.5").unwrap();
+        let value = tag.read_store(&store).unwrap();
+        assert_eq!(value.to_string(), "25.5");
+        assert!(tag.is_covered_by(100, 2));
+        assert!(!tag.is_covered_by(100, 1));
+    }
+}
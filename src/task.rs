@@ -1,25 +1,139 @@
+use crate::master::client::{self, MasterError, MasterRequest, MasterResponse, Timing};
+use crate::master::discovery::DiscoveryResult;
+use crate::master::poll::SharedPolls;
+use crate::master::scan::{ScanCommand, ScanEnd, ScanResult};
+use crate::modbus::frame::{FrameError, FrameKind, Framing};
+use crate::modbus::pdu::{ExceptionCode, Request, Response};
+use crate::mode::{ModeError, OperatingMode, TaskStatus};
+use crate::net::{TcpSettings, describe_connect_error};
+use crate::serial::{
+    LineParams, PortSettings, SharedPort, describe_open_error, release_control_lines,
+};
+use crate::slave::server;
+use crate::slave::store::RegisterStore;
+use crate::traffic::{TrafficFrame, sniffer};
+use crate::transport::{FramedTransport, Transport};
+use eframe::egui;
 use log;
+use std::net::SocketAddr;
 use std::sync::{
-    Arc,
+    Arc, Mutex,
     atomic::{AtomicBool, Ordering},
 };
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
+use tokio_modbus::prelude::{Slave, rtu, tcp};
+use tokio_serial::SerialStream;
+use tokio_util::sync::CancellationToken;
+
+/// 任务检查模式切换和设置更新的间隔
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(100);
+/// 删除任务时等待正在进行的事务完成的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// 创建任务所需的共享状态
+#[derive(Debug, Clone)]
+pub struct TaskContext {
+    pub transport: Transport,
+    pub store: Arc<Mutex<RegisterStore>>,
+    pub polls: SharedPolls,
+}
+
+/// 界面发给后台任务的命令
+#[derive(Debug, Clone)]
+pub enum TaskCommand {
+    Master(MasterRequest),
+    Scan(ScanCommand),
+}
+
+/// 后台任务发给界面的事件
+#[derive(Debug, Clone)]
+pub enum TaskEvent {
+    /// 主机请求执行完毕
+    Master(MasterResponse),
+    /// 一项轮询执行完毕
+    Polled { poll: u64, response: MasterResponse },
+    /// 总线扫描探测完一个站号
+    Scanned(ScanResult),
+    /// 参数识别试完一组线路参数
+    Discovered(DiscoveryResult),
+    /// 总线扫描或参数识别结束
+    ScanFinished(ScanEnd),
+    /// 从机应答了一条请求
+    SlaveServed {
+        /// TCP 客户端的地址，串口从机为 `None`
+        client: Option<SocketAddr>,
+        unit: u8,
+        request: Request,
+        result: Result<Response, ExceptionCode>,
+    },
+    /// TCP 从机接受了一个客户端连接
+    ClientConnected(SocketAddr),
+    /// TCP 从机的客户端连接已关闭
+    ClientDisconnected(SocketAddr),
+    /// 收到的帧校验失败或无法解析
+    FrameError {
+        framing: Framing,
+        frame: Vec<u8>,
+        error: FrameError,
+    },
+    /// 传输层收发的一帧
+    Traffic(TrafficFrame),
+}
+
+/// 事件发送端，发送后请求界面重绘以便及时显示
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    tx: UnboundedSender<TaskEvent>,
+    repaint: Option<egui::Context>,
+}
+
+impl EventSender {
+    /// 不触发重绘的发送端，界面上下文由 `TaskManager::set_repaint_context` 设置
+    pub fn new(tx: UnboundedSender<TaskEvent>) -> Self {
+        Self { tx, repaint: None }
+    }
+
+    pub fn send(&self, event: TaskEvent) {
+        // 界面关闭后接收端会被释放，此时事件没有意义，直接丢弃
+        let _ = self.tx.send(event);
+        if let Some(ctx) = &self.repaint {
+            ctx.request_repaint();
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct TaskManager {
-    cancel_flag: Arc<AtomicBool>,
-    handle_type: Arc<AtomicBool>,
+    cancel: CancellationToken,
+    //当前工作模式，任务运行中切换时引擎随之切换
+    mode: Arc<Mutex<OperatingMode>>,
+    //由任务发布的状态，界面据此显示
+    status: Arc<Mutex<TaskStatus>>,
+    //主机事务进行中，此时拒绝切换模式
+    in_flight: Arc<AtomicBool>,
     task_handle: Option<JoinHandle<()>>,
     runtime: Option<tokio::runtime::Runtime>,
+    //发往当前任务的命令通道，每个任务单独创建
+    command_tx: Option<UnboundedSender<TaskCommand>>,
+    event_tx: EventSender,
+    event_rx: UnboundedReceiver<TaskEvent>,
 }
 
 impl Default for TaskManager {
     fn default() -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
         Self {
-            cancel_flag: Arc::new(AtomicBool::new(false)),
-            handle_type: Arc::new(AtomicBool::new(false)),
+            cancel: CancellationToken::new(),
+            mode: Arc::new(Mutex::new(OperatingMode::default())),
+            status: Arc::new(Mutex::new(TaskStatus::default())),
+            in_flight: Arc::new(AtomicBool::new(false)),
             task_handle: None,
             runtime: None,
+            command_tx: None,
+            event_tx: EventSender::new(event_tx),
+            event_rx,
         }
     }
 }
@@ -29,11 +143,20 @@ impl TaskManager {
         Self::default()
     }
 
-    pub fn create_task(&mut self) {
-        log::info!("创建新的串口任务");
+    /// 设置收到事件时需要重绘的界面上下文
+    pub fn set_repaint_context(&mut self, ctx: egui::Context) {
+        self.event_tx.repaint = Some(ctx);
+    }
+
+    /// 按传输层设置创建任务，串口打开失败时返回错误且不创建任务
+    ///
+    /// 任务按当前工作模式运行对应的引擎。串口任务会观察 `need_update`，
+    /// 把修改后的设置应用到已打开的串口上；TCP 任务在引擎启动时才建立连接或开始监听。
+    pub fn create_task(&mut self, context: TaskContext) -> Result<(), String> {
+        log::info!("创建新的通信任务");
 
-        // 重置取消标志
-        self.cancel_flag.store(false, Ordering::Relaxed);
+        // 每个任务使用新的取消令牌，已取消的令牌无法复位
+        self.cancel = CancellationToken::new();
 
         // 如果运行时不存在，创建一个
         if self.runtime.is_none() {
@@ -42,63 +165,469 @@ impl TaskManager {
                 Some(tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime"));
         }
 
-        let cancel_flag = self.cancel_flag.clone();
-        let handle_type = self.handle_type.clone();
+        let cancel = self.cancel.clone();
+        let mode = self.mode.clone();
+        let status = self.status.clone();
+        let in_flight = self.in_flight.clone();
+        let events = self.event_tx.clone();
 
         // 使用运行时创建任务
         if let Some(ref runtime) = self.runtime {
-            let task_handle = runtime.spawn(async move {
-                log::info!("串口任务启动");
-                while !cancel_flag.load(Ordering::Relaxed) {
-                    // 任务逻辑
-                    if handle_type.load(Ordering::Relaxed) {
-                        println!("master");
-                    } else {
-                        println!("slave");
-                    }
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            self.set_status(TaskStatus::Connecting);
+            let (link, current) = match context.transport {
+                Transport::Serial {
+                    settings,
+                    need_update,
+                } => {
+                    // 打开时已使用最新设置，之前积累的修改无需再应用
+                    need_update.store(false, Ordering::Relaxed);
+                    let current = settings.lock().unwrap().clone();
+                    let monitor = *mode.lock().unwrap() == OperatingMode::Monitor;
+                    // 串口需要注册到运行时的反应器上，因此在运行时上下文中打开
+                    let stream = {
+                        let _guard = runtime.enter();
+                        let opened = if monitor {
+                            current.open_monitor()
+                        } else {
+                            current.open()
+                        };
+                        opened.map_err(|err| {
+                            let message = describe_open_error(&current.path, &err);
+                            self.set_status(TaskStatus::Error(message.clone()));
+                            message
+                        })?
+                    };
+                    log::info!("串口 {} 打开成功", current.path);
+                    let link = Link::Serial {
+                        // 任务持有串口，任务结束时串口随之关闭
+                        port: SharedPort::new(stream),
+                        settings,
+                        need_update,
+                    };
+                    (link, Some(current))
                 }
-                log::info!("串口任务退出");
+                Transport::Tcp(settings) => (Link::Tcp(settings), None),
+            };
+
+            let (command_tx, commands) = mpsc::unbounded_channel();
+            self.command_tx = Some(command_tx);
+
+            let task = TaskState {
+                store: context.store,
+                polls: context.polls,
+                commands,
+                events,
+                cancel,
+                mode,
+                in_flight,
+                status: status.clone(),
+            };
+            let task_handle = runtime.spawn(async move {
+                log::info!("通信任务启动");
+                let result = run_task(link, current, task).await;
+                *status.lock().unwrap() = match result {
+                    Ok(()) => TaskStatus::Stopped,
+                    Err(error) => TaskStatus::Error(error),
+                };
+                log::info!("通信任务退出");
             });
             self.task_handle = Some(task_handle);
         }
 
-        log::info!("串口任务创建成功");
+        log::info!("通信任务创建成功");
+        Ok(())
     }
 
+    /// 取消任务并等待其退出
+    ///
+    /// 任务会先完成正在进行的事务再释放串口，超过 `SHUTDOWN_TIMEOUT` 仍未退出时强制终止。
     pub fn delete_task(&mut self) {
-        log::info!("删除串口任务");
-        if let Some(handle) = self.task_handle.take() {
-            log::info!("设置取消标志，等待任务退出");
-            self.cancel_flag.store(true, Ordering::Relaxed);
-            handle.abort();
-            log::info!("串口任务删除成功");
+        log::info!("删除通信任务");
+        self.command_tx = None;
+        if let Some(mut handle) = self.task_handle.take() {
+            log::info!("取消任务，等待任务退出");
+            self.cancel.cancel();
+            if let Some(runtime) = &self.runtime {
+                let finished = runtime
+                    .block_on(async { tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut handle).await })
+                    .is_ok();
+                if !finished {
+                    log::warn!("任务未能在 {:?} 内退出，强制终止", SHUTDOWN_TIMEOUT);
+                    handle.abort();
+                    self.in_flight.store(false, Ordering::Relaxed);
+                    self.set_status(TaskStatus::Stopped);
+                }
+            }
+            log::info!("通信任务删除成功");
         } else {
-            log::info!("没有正在运行的串口任务");
+            log::info!("没有正在运行的通信任务");
         }
     }
 
-    pub fn recreate_task(&mut self) {
-        log::info!("重新创建串口任务");
+    /// 退出程序前调用：删除任务并关闭运行时
+    pub fn shutdown(&mut self) {
         self.delete_task();
-        log::info!("创建新的取消标志");
-        self.cancel_flag = Arc::new(AtomicBool::new(false));
-        self.create_task();
-        log::info!("串口任务重新创建成功");
+        if let Some(runtime) = self.runtime.take() {
+            log::info!("关闭 Tokio 运行时");
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        }
     }
 
-    pub fn set_handle_type(&mut self, value: bool) {
-        self.handle_type.store(value, Ordering::Relaxed);
-        log::info!("设置 handle_type: {}", value);
+    pub fn recreate_task(&mut self, context: TaskContext) -> Result<(), String> {
+        log::info!("重新创建通信任务");
+        self.delete_task();
+        self.create_task(context)?;
+        log::info!("通信任务重新创建成功");
+        Ok(())
     }
 
-    pub fn get_handle_type(&self) -> bool {
-        let value = self.handle_type.load(Ordering::Relaxed);
-        log::info!("获取 handle_type: {}", value);
-        value
+    /// 切换工作模式，不符合切换规则或有事务正在进行时拒绝
+    pub fn set_mode(&mut self, next: OperatingMode) -> Result<(), ModeError> {
+        let mut mode = self.mode.lock().unwrap();
+        if *mode == next {
+            return Ok(());
+        }
+        if !next.is_supported() {
+            return Err(ModeError::Unsupported(next));
+        }
+        if !mode.can_transition_to(next) {
+            return Err(ModeError::InvalidTransition {
+                from: *mode,
+                to: next,
+            });
+        }
+        if self.in_flight.load(Ordering::Relaxed) {
+            return Err(ModeError::Busy);
+        }
+        log::info!("工作模式切换: {} -> {}", *mode, next);
+        *mode = next;
+        Ok(())
+    }
+
+    pub fn mode(&self) -> OperatingMode {
+        *self.mode.lock().unwrap()
+    }
+
+    pub fn status(&self) -> TaskStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn set_status(&self, status: TaskStatus) {
+        *self.status.lock().unwrap() = status;
     }
 
     pub fn has_task(&self) -> bool {
         self.task_handle.is_some()
     }
+
+    /// 把命令发给当前任务，没有任务时返回 false
+    pub fn send(&self, command: TaskCommand) -> bool {
+        match &self.command_tx {
+            Some(tx) => tx.send(command).is_ok(),
+            None => false,
+        }
+    }
+
+    /// 取出任务发来的全部事件，不会阻塞
+    pub fn poll_events(&mut self) -> Vec<TaskEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.event_rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+}
+
+/// 任务使用的传输层
+enum Link {
+    /// 已打开的串口和界面共享的设置
+    Serial {
+        port: SharedPort,
+        settings: Arc<Mutex<PortSettings>>,
+        need_update: Arc<AtomicBool>,
+    },
+    /// 主机模式连接目标设备、从机模式监听端口，都在引擎启动时进行
+    Tcp(TcpSettings),
+}
+
+/// 任务持有的通道和共享状态
+struct TaskState {
+    store: Arc<Mutex<RegisterStore>>,
+    polls: SharedPolls,
+    commands: UnboundedReceiver<TaskCommand>,
+    events: EventSender,
+    cancel: CancellationToken,
+    mode: Arc<Mutex<OperatingMode>>,
+    in_flight: Arc<AtomicBool>,
+    status: Arc<Mutex<TaskStatus>>,
+}
+
+/// 任务主循环：运行当前工作模式的引擎，模式切换时停止旧引擎并启动新引擎
+///
+/// `current` 是串口当前生效的设置，TCP 任务为 `None`。
+async fn run_task(
+    link: Link,
+    mut current: Option<PortSettings>,
+    mut task: TaskState,
+) -> Result<(), String> {
+    let mut monitoring = false;
+    while !task.cancel.is_cancelled() {
+        let running = *task.mode.lock().unwrap();
+        log::info!("启动{}模式", running);
+        // 监听模式不驱动控制线，离开监听模式时恢复设置中的流控和 DTR
+        if let (Link::Serial { port, .. }, Some(current)) = (&link, &current) {
+            let result = if running == OperatingMode::Monitor {
+                port.with(release_control_lines)
+            } else if monitoring {
+                port.with(|stream| current.apply(stream))
+            } else {
+                Ok(())
+            };
+            result.map_err(|err| describe_open_error(&current.path, &err))?;
+        }
+        monitoring = running == OperatingMode::Monitor;
+        let TaskState {
+            store,
+            polls,
+            commands,
+            events,
+            cancel,
+            mode,
+            in_flight,
+            status,
+        } = &mut task;
+        let engine = run_engine(
+            &link, running, store, polls, commands, events, cancel, in_flight, status,
+        );
+        let supervise = async {
+            loop {
+                tokio::time::sleep(SUPERVISE_INTERVAL).await;
+                if *mode.lock().unwrap() != running {
+                    return Ok(());
+                }
+                if let (
+                    Link::Serial {
+                        port,
+                        settings,
+                        need_update,
+                    },
+                    Some(current),
+                ) = (&link, &mut current)
+                    && need_update.swap(false, Ordering::Relaxed)
+                {
+                    let updated = settings.lock().unwrap().clone();
+                    let restart =
+                        updated.path != current.path || updated.framing != current.framing;
+                    port.with(|stream| update_port(stream, current, &updated, monitoring))
+                        .map_err(|err| describe_open_error(&updated.path, &err))?;
+                    *current = updated;
+                    // 换了新串口或帧格式后，引擎需要重新开始
+                    if restart {
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            result = engine => {
+                return match result {
+                    // 主机引擎在取消后完成当前事务才返回
+                    Ok(()) if cancel.is_cancelled() => Ok(()),
+                    Ok(()) => Err("通信已结束".to_string()),
+                    Err(err) => {
+                        log::error!("{}", err);
+                        Err(err)
+                    }
+                };
+            }
+            result = supervise => {
+                if let Err(err) = result {
+                    log::error!("更新串口设置失败: {}", err);
+                    return Err(err);
+                }
+            }
+            // 只有主机引擎有进行中的事务，其他模式取消时直接停止
+            _ = cancel.cancelled(), if running != OperatingMode::Master => {}
+        }
+    }
+    Ok(())
+}
+
+/// 在传输层上运行指定模式的引擎
+#[allow(clippy::too_many_arguments)]
+async fn run_engine(
+    link: &Link,
+    running: OperatingMode,
+    store: &Arc<Mutex<RegisterStore>>,
+    polls: &SharedPolls,
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+    cancel: &CancellationToken,
+    in_flight: &AtomicBool,
+    status: &Mutex<TaskStatus>,
+) -> Result<(), String> {
+    match (running, link) {
+        (OperatingMode::Master, Link::Serial { port, settings, .. }) => {
+            *status.lock().unwrap() = TaskStatus::Running;
+            let framing = settings.lock().unwrap().framing;
+            let transport =
+                FramedTransport::new(port.clone(), framing, FrameKind::Response, events.clone());
+            let ctx = rtu::attach_slave(transport, Slave(1));
+            let timing = || {
+                let settings = settings.lock().unwrap();
+                Timing {
+                    timeout: settings.timeout,
+                    gap: settings.inter_frame_gap,
+                }
+            };
+            // 参数识别期间临时修改串口参数，结束后恢复为共享设置中的参数
+            let reconfigure = |params: Option<LineParams>| {
+                let mut updated = settings.lock().unwrap().clone();
+                if let Some(params) = params {
+                    params.apply_to(&mut updated);
+                }
+                port.with(|stream| updated.apply(stream))
+                    .map_err(|err| describe_open_error(&updated.path, &err))
+            };
+            client::run(
+                ctx,
+                &timing,
+                Some(&reconfigure),
+                polls,
+                commands,
+                events,
+                cancel,
+                in_flight,
+            )
+            .await
+            .map_err(|err| format!("Modbus 通信错误: {}", err))
+        }
+        (OperatingMode::Master, Link::Tcp(settings)) => {
+            *status.lock().unwrap() = TaskStatus::Connecting;
+            log::info!(
+                "连接 Modbus TCP 设备 {}，帧格式 {}",
+                settings.address(),
+                settings.framing
+            );
+            let stream = settings
+                .connect()
+                .await
+                .map_err(|err| describe_connect_error(&err))?;
+            *status.lock().unwrap() = TaskStatus::Running;
+            let transport = FramedTransport::new(
+                stream,
+                settings.framing,
+                FrameKind::Response,
+                events.clone(),
+            );
+            let ctx = match settings.framing {
+                Framing::Tcp => tcp::attach_slave(transport, Slave(settings.unit_id)),
+                // 串口服务器透传的 RTU/ASCII 报文
+                _ => rtu::attach_slave(transport, Slave(settings.unit_id)),
+            };
+            let timing = || Timing {
+                timeout: settings.timeout,
+                gap: Duration::ZERO,
+            };
+            client::run(
+                ctx, &timing, None, polls, commands, events, cancel, in_flight,
+            )
+            .await
+            .map_err(|err| format!("Modbus 通信错误: {}", err))
+        }
+        (OperatingMode::Slave, Link::Serial { port, settings, .. }) => {
+            *status.lock().unwrap() = TaskStatus::Running;
+            let framing = settings.lock().unwrap().framing;
+            let transport =
+                FramedTransport::new(port.clone(), framing, FrameKind::Request, events.clone())
+                    .count_errors_in(store.clone());
+            tokio::select! {
+                result = server::run(transport, store.clone(), events.clone()) => result,
+                result = reject_commands(commands, events) => result,
+            }
+            .map_err(|err| format!("Modbus 通信错误: {}", err))
+        }
+        (OperatingMode::Slave, Link::Tcp(settings)) => {
+            *status.lock().unwrap() = TaskStatus::Connecting;
+            let listener = settings
+                .listen()
+                .await
+                .map_err(|err| describe_connect_error(&err))?;
+            *status.lock().unwrap() = TaskStatus::Running;
+            let serve = server::run_tcp(listener, settings.framing, store.clone(), events.clone());
+            tokio::select! {
+                result = serve => result,
+                result = reject_commands(commands, events) => result,
+            }
+            .map_err(|err| format!("Modbus 通信错误: {}", err))
+        }
+        (OperatingMode::Monitor, Link::Serial { port, settings, .. }) => {
+            *status.lock().unwrap() = TaskStatus::Running;
+            tokio::select! {
+                result = sniffer::run(port.clone(), settings.clone(), events.clone()) => result,
+                result = reject_commands(commands, events) => result,
+            }
+            .map_err(|err| format!("监听串口失败: {}", err))
+        }
+        (OperatingMode::Monitor, Link::Tcp(_)) => Err("监听模式只能用于串口".to_string()),
+        // 空闲时只保持连接；尚未支持的模式在 set_mode 中已被拒绝
+        (OperatingMode::Idle | OperatingMode::Gateway, _) => {
+            *status.lock().unwrap() = TaskStatus::Running;
+            reject_commands(commands, events)
+                .await
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// 非主机模式下收到的主机请求直接回复错误，避免切换模式后执行过期的请求
+async fn reject_commands(
+    commands: &mut UnboundedReceiver<TaskCommand>,
+    events: &EventSender,
+) -> std::io::Result<()> {
+    while let Some(command) = commands.recv().await {
+        match command {
+            TaskCommand::Master(request) => events.send(TaskEvent::Master(MasterResponse::failed(
+                request,
+                MasterError::NotMaster,
+            ))),
+            TaskCommand::Scan(ScanCommand::Start(_) | ScanCommand::Discover(_)) => events.send(
+                TaskEvent::ScanFinished(ScanEnd::Failed(MasterError::NotMaster)),
+            ),
+            TaskCommand::Scan(ScanCommand::Stop) => {}
+        }
+    }
+    Ok(())
+}
+
+/// 把新设置应用到正在使用的串口：路径变化时关闭后重新打开，否则原地修改参数
+///
+/// `monitor` 为真时串口用于监听，更新后仍不驱动控制线。
+fn update_port(
+    stream: &mut SerialStream,
+    current: &PortSettings,
+    updated: &PortSettings,
+    monitor: bool,
+) -> tokio_serial::Result<()> {
+    if updated.path != current.path {
+        log::info!(
+            "串口路径变化: {} -> {}，重新打开",
+            current.path,
+            updated.path
+        );
+        // 先替换成新串口，旧串口在赋值时被释放
+        *stream = if monitor {
+            updated.open_monitor()?
+        } else {
+            updated.open()?
+        };
+    } else {
+        log::info!("原地更新串口 {} 的设置", updated.path);
+        updated.apply(stream)?;
+        if monitor {
+            release_control_lines(stream)?;
+        }
+    }
+    Ok(())
 }
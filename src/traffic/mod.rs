@@ -0,0 +1,648 @@
+//! 报文监视
+//!
+//! 传输层收发的每一帧都带上时间戳作为事件发给界面，
+//! 报文页面按时间顺序显示，可以按方向、内容和校验结果过滤。
+//! 选中的帧解码成树形结构显示，应答与对应的请求配对并计算往返时间。
+
+pub mod capture;
+pub mod sniffer;
+
+use crate::modbus::decode::{Adu, Node, decode};
+use crate::modbus::frame::{FrameError, FrameKind, Framing};
+use capture::{CaptureFormat, CsvRow};
+use eframe::*;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// 页面最多保留的帧数，超出后丢弃最早的帧
+pub const CAPACITY: usize = 10_000;
+/// 应答向前查找对应请求的帧数
+const PAIR_WINDOW: usize = 64;
+
+/// 帧的方向，相对于本机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+impl Direction {
+    pub fn label(self) -> &'static str {
+        match self {
+            Direction::Tx => "发送",
+            Direction::Rx => "接收",
+        }
+    }
+}
+
+/// 传输层上的一帧，字节是线路上的原始内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficFrame {
+    pub time: SystemTime,
+    pub direction: Direction,
+    /// 请求还是应答
+    pub kind: FrameKind,
+    pub framing: Framing,
+    pub bytes: Vec<u8>,
+    /// 校验失败或无法解析的原因
+    pub error: Option<FrameError>,
+}
+
+impl TrafficFrame {
+    pub fn new(
+        direction: Direction,
+        kind: FrameKind,
+        framing: Framing,
+        bytes: Vec<u8>,
+        error: Option<FrameError>,
+    ) -> Self {
+        Self {
+            time: SystemTime::now(),
+            direction,
+            kind,
+            framing,
+            bytes,
+            error,
+        }
+    }
+
+    /// 以空格分隔的十六进制字节
+    pub fn hex(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// 校验结果，Modbus TCP 没有校验码
+    pub fn status(&self) -> String {
+        match (&self.error, self.framing) {
+            (Some(error), _) => error.to_string(),
+            (None, Framing::Rtu) => "CRC 正确".to_string(),
+            (None, Framing::Ascii) => "LRC 正确".to_string(),
+            (None, Framing::Tcp) => "-".to_string(),
+        }
+    }
+}
+
+/// 方向过滤
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectionFilter {
+    #[default]
+    All,
+    Only(Direction),
+}
+
+impl DirectionFilter {
+    pub const ALL: [DirectionFilter; 3] = [
+        DirectionFilter::All,
+        DirectionFilter::Only(Direction::Tx),
+        DirectionFilter::Only(Direction::Rx),
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DirectionFilter::All => "全部",
+            DirectionFilter::Only(direction) => direction.label(),
+        }
+    }
+}
+
+/// 报文页面的过滤条件
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub direction: DirectionFilter,
+    /// 只显示校验失败的帧
+    pub errors_only: bool,
+    /// 帧中包含的十六进制字节，忽略空格和大小写
+    pub hex: String,
+}
+
+impl Filter {
+    pub fn matches(&self, frame: &TrafficFrame) -> bool {
+        if let DirectionFilter::Only(direction) = self.direction
+            && frame.direction != direction
+        {
+            return false;
+        }
+        if self.errors_only && frame.error.is_none() {
+            return false;
+        }
+        let pattern: String = self
+            .hex
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_uppercase();
+        if pattern.is_empty() {
+            return true;
+        }
+        // 只在字节边界上匹配，避免 "12" 匹配到 "01 23"
+        let text: String = frame.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        text.match_indices(&pattern)
+            .any(|(index, _)| index.is_multiple_of(2))
+    }
+}
+
+/// 一条记录，序号在清除后继续递增，用于选中和配对
+#[derive(Debug, Clone)]
+struct Record {
+    seq: u64,
+    frame: TrafficFrame,
+    //去掉帧格式后的报文，无法解析时为 None
+    adu: Option<Adu>,
+    summary: String,
+    //请求对应的应答，或应答对应的请求
+    peer: Option<u64>,
+    //应答相对请求的往返时间
+    latency: Option<Duration>,
+}
+
+/// 报文页面
+#[derive(Debug, Default)]
+pub struct Traffic {
+    records: VecDeque<Record>,
+    next_seq: u64,
+    //暂停时收到的帧不记录，只计数
+    paused: bool,
+    skipped: usize,
+    filter: Filter,
+    selected: Option<u64>,
+    //抓包文件的路径和最近一次导入或导出的结果
+    path: String,
+    outcome: Option<Result<String, String>>,
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一帧并与之前的请求配对，暂停时丢弃
+    pub fn record(&mut self, frame: TrafficFrame) {
+        if self.paused {
+            self.skipped += 1;
+            return;
+        }
+        self.push(frame);
+    }
+
+    /// 用文件中的帧替换当前记录，与实时收到的帧一样配对和解码
+    pub fn load(&mut self, frames: Vec<TrafficFrame>) {
+        self.clear();
+        for frame in frames {
+            self.push(frame);
+        }
+    }
+
+    fn push(&mut self, frame: TrafficFrame) {
+        if self.records.len() >= CAPACITY {
+            self.records.pop_front();
+        }
+        self.next_seq += 1;
+        let seq = self.next_seq;
+        let adu = Adu::parse(&frame.bytes, frame.framing).ok();
+        // 应答对应最近一个尚未应答、站号和功能码相同的请求
+        let request = match (&adu, frame.kind) {
+            (Some(adu), FrameKind::Response) => self
+                .records
+                .iter_mut()
+                .rev()
+                .take(PAIR_WINDOW)
+                .find(|record| {
+                    record.frame.kind == FrameKind::Request
+                        && record.peer.is_none()
+                        && record
+                            .adu
+                            .as_ref()
+                            .is_some_and(|request| adu.is_response_to(request))
+                }),
+            _ => None,
+        };
+        let (peer, latency, request_adu) = match request {
+            Some(request) => {
+                request.peer = Some(seq);
+                (
+                    Some(request.seq),
+                    Some(since(request.frame.time, frame.time)),
+                    request.adu.clone(),
+                )
+            }
+            None => (None, None, None),
+        };
+        let summary = decode(
+            &frame.bytes,
+            frame.framing,
+            frame.kind,
+            request_adu.as_ref(),
+        )
+        .summary;
+        self.records.push_back(Record {
+            seq,
+            frame,
+            adu,
+            summary,
+            peer,
+            latency,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.skipped = 0;
+        self.selected = None;
+    }
+
+    /// 按时间顺序排列的全部帧
+    pub fn frames(&self) -> impl Iterator<Item = &TrafficFrame> {
+        self.records.iter().map(|record| &record.frame)
+    }
+
+    fn find(&self, seq: u64) -> Option<&Record> {
+        self.records.iter().find(|record| record.seq == seq)
+    }
+
+    fn selected_frame(&self) -> Option<&TrafficFrame> {
+        self.find(self.selected?).map(|record| &record.frame)
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("traffic_toolbar").show(ctx, |ui| {
+            self.show_toolbar(ui);
+        });
+        egui::SidePanel::right("traffic_decoded")
+            .default_width(380.0)
+            .show(ctx, |ui| {
+                self.show_decoded(ui);
+            });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.show_records(ui);
+        });
+    }
+
+    fn show_toolbar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.paused { "继续" } else { "暂停" };
+            if ui.button(label).clicked() {
+                self.paused = !self.paused;
+                log::info!("报文监视{}", if self.paused { "暂停" } else { "继续" });
+            }
+            if ui.button("清除").clicked() {
+                self.clear();
+            }
+            let selected = self.selected_frame().map(TrafficFrame::hex);
+            if ui
+                .add_enabled(selected.is_some(), egui::Button::new("复制选中"))
+                .clicked()
+                && let Some(hex) = selected
+            {
+                ui.ctx().copy_text(hex);
+            }
+            if ui
+                .button("复制全部")
+                .on_hover_text("按十六进制复制过滤后的所有帧，每帧一行")
+                .clicked()
+            {
+                let lines: Vec<String> = self
+                    .frames()
+                    .filter(|frame| self.filter.matches(frame))
+                    .map(TrafficFrame::hex)
+                    .collect();
+                ui.ctx().copy_text(lines.join("\n"));
+            }
+            ui.separator();
+            ui.label("方向:");
+            egui::ComboBox::from_id_salt("traffic_direction")
+                .selected_text(self.filter.direction.label())
+                .show_ui(ui, |ui| {
+                    for direction in DirectionFilter::ALL {
+                        ui.selectable_value(
+                            &mut self.filter.direction,
+                            direction,
+                            direction.label(),
+                        );
+                    }
+                });
+            ui.checkbox(&mut self.filter.errors_only, "只看错误");
+            ui.label("包含:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.filter.hex)
+                    .hint_text("例如 01 03")
+                    .desired_width(160.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("抓包文件:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.path)
+                    .hint_text(".mbcap、.csv、.pcap 或 .pcapng")
+                    .desired_width(240.0),
+            );
+            if ui
+                .button("导入")
+                .on_hover_text("导入会替换当前的记录，CSV 只能导出")
+                .clicked()
+            {
+                self.outcome = Some(self.import());
+            }
+            if ui.button("导出").clicked() {
+                self.outcome = Some(self.export());
+            }
+            match &self.outcome {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(err)) => {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), err);
+                }
+                None => {}
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label(format!("共 {} 帧", self.records.len()));
+            if self.paused {
+                ui.colored_label(
+                    egui::Color32::from_rgb(220, 160, 0),
+                    format!("已暂停，未记录 {} 帧", self.skipped),
+                );
+            }
+        });
+    }
+
+    fn format(&self) -> Result<CaptureFormat, String> {
+        CaptureFormat::from_path(Path::new(self.path.trim()))
+            .ok_or_else(|| "只支持 .mbcap、.csv、.pcap 和 .pcapng 文件".to_string())
+    }
+
+    fn import(&mut self) -> Result<String, String> {
+        let format = self.format()?;
+        let path = self.path.trim().to_string();
+        let data = std::fs::read(&path).map_err(|err| format!("读取 {} 失败: {}", path, err))?;
+        let frames = match format {
+            CaptureFormat::Native => capture::from_native(&String::from_utf8_lossy(&data)),
+            CaptureFormat::Csv => Err("CSV 文件只能导出".to_string()),
+            CaptureFormat::Pcap | CaptureFormat::Pcapng => capture::from_capture(&data),
+        }
+        .map_err(|err| format!("导入 {} 失败: {}", path, err))?;
+        log::info!("从 {} 导入 {} 帧", path, frames.len());
+        let count = frames.len();
+        self.load(frames);
+        Ok(format!("已导入 {} 帧", count))
+    }
+
+    fn export(&self) -> Result<String, String> {
+        let format = self.format()?;
+        let path = self.path.trim();
+        let frames: Vec<&TrafficFrame> = self.frames().collect();
+        let data = match format {
+            CaptureFormat::Native => capture::to_native(&frames).into_bytes(),
+            CaptureFormat::Csv => {
+                let rows: Vec<CsvRow> = self
+                    .records
+                    .iter()
+                    .map(|record| CsvRow {
+                        frame: &record.frame,
+                        summary: &record.summary,
+                        latency: record.latency,
+                    })
+                    .collect();
+                capture::to_csv(&rows).into_bytes()
+            }
+            CaptureFormat::Pcap => capture::to_pcap(&frames)?,
+            CaptureFormat::Pcapng => capture::to_pcapng(&frames),
+        };
+        std::fs::write(path, data).map_err(|err| format!("写入 {} 失败: {}", path, err))?;
+        log::info!("导出 {} 帧到 {}", frames.len(), path);
+        Ok(format!("已导出 {} 帧", frames.len()))
+    }
+
+    fn show_records(&mut self, ui: &mut egui::Ui) {
+        let records: Vec<&Record> = self
+            .records
+            .iter()
+            .filter(|record| self.filter.matches(&record.frame))
+            .collect();
+        let Some(first) = self.records.front().map(|record| record.frame.time) else {
+            ui.label("连接后收发的每一帧都会显示在这里");
+            return;
+        };
+        let mut clicked = None;
+        let row_height = ui.spacing().interact_size.y;
+        egui::ScrollArea::both()
+            .id_salt("traffic_records")
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, records.len(), |ui, rows| {
+                egui::Grid::new("traffic_records_grid")
+                    .num_columns(10)
+                    .spacing([16.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for row in rows {
+                            let record = records[row];
+                            let frame = &record.frame;
+                            let previous = row.checked_sub(1).map(|row| records[row].frame.time);
+                            let selected = self.selected == Some(record.seq);
+                            if ui
+                                .selectable_label(selected, record.seq.to_string())
+                                .clicked()
+                            {
+                                clicked = Some(record.seq);
+                            }
+                            ui.monospace(format_seconds(since(first, frame.time)));
+                            ui.monospace(match previous {
+                                Some(previous) => {
+                                    format!("+{}", format_millis(since(previous, frame.time)))
+                                }
+                                None => String::new(),
+                            });
+                            ui.label(frame.direction.label());
+                            ui.label(frame.bytes.len().to_string());
+                            if frame.error.is_some() {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 50, 50),
+                                    frame.status(),
+                                );
+                            } else {
+                                ui.label(frame.status());
+                            }
+                            ui.monospace(match record.latency {
+                                Some(latency) => format_millis(latency),
+                                None => String::new(),
+                            });
+                            ui.label(&record.summary);
+                            ui.monospace(frame.hex());
+                            ui.end_row();
+                        }
+                    });
+            });
+        if let Some(seq) = clicked {
+            self.selected = Some(seq);
+        }
+    }
+
+    /// 选中帧的解码树，点击配对的帧跳转过去
+    fn show_decoded(&mut self, ui: &mut egui::Ui) {
+        let Some(record) = self.selected.and_then(|seq| self.find(seq)) else {
+            ui.label("点击帧的编号查看解码结果");
+            return;
+        };
+        let frame = &record.frame;
+        ui.strong(format!(
+            "#{} {} {}",
+            record.seq,
+            frame.direction.label(),
+            frame.kind.label()
+        ));
+        let request = match frame.kind {
+            FrameKind::Response => record.peer.and_then(|seq| self.find(seq)),
+            FrameKind::Request => None,
+        };
+        let mut jump = None;
+        match (record.peer, frame.kind) {
+            (Some(peer), FrameKind::Request) => {
+                let latency = self.find(peer).and_then(|response| response.latency);
+                let text = match latency {
+                    Some(latency) => format!("应答 #{}，往返 {}", peer, format_millis(latency)),
+                    None => format!("应答 #{}", peer),
+                };
+                if ui.link(text).clicked() {
+                    jump = Some(peer);
+                }
+            }
+            (Some(peer), FrameKind::Response) => {
+                let text = match record.latency {
+                    Some(latency) => format!("请求 #{}，往返 {}", peer, format_millis(latency)),
+                    None => format!("请求 #{}", peer),
+                };
+                if ui.link(text).clicked() {
+                    jump = Some(peer);
+                }
+            }
+            (None, FrameKind::Request) => {
+                ui.label("没有应答");
+            }
+            (None, FrameKind::Response) => {
+                ui.label("没有找到对应的请求");
+            }
+        }
+        ui.separator();
+        let decoded = decode(
+            &frame.bytes,
+            frame.framing,
+            frame.kind,
+            request.and_then(|request| request.adu.as_ref()),
+        );
+        egui::ScrollArea::vertical()
+            .id_salt("traffic_decoded_tree")
+            .show(ui, |ui| {
+                for (index, node) in decoded.tree.iter().enumerate() {
+                    show_node(ui, node, egui::Id::new(("traffic_node", record.seq, index)));
+                }
+            });
+        if jump.is_some() {
+            self.selected = jump;
+        }
+    }
+}
+
+fn show_node(ui: &mut egui::Ui, node: &Node, id: egui::Id) {
+    if node.children.is_empty() {
+        ui.label(&node.text);
+        return;
+    }
+    egui::CollapsingHeader::new(&node.text)
+        .id_salt(id)
+        .default_open(node.children.len() <= 32)
+        .show(ui, |ui| {
+            for (index, child) in node.children.iter().enumerate() {
+                show_node(ui, child, id.with(index));
+            }
+        });
+}
+
+fn since(earlier: SystemTime, later: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_default()
+}
+
+/// 相对第一帧的时间，精确到微秒
+fn format_seconds(elapsed: Duration) -> String {
+    format!("{}.{:06} s", elapsed.as_secs(), elapsed.subsec_micros())
+}
+
+fn format_millis(elapsed: Duration) -> String {
+    format!("{:.3} ms", elapsed.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::frame::encode_rtu;
+
+    #[test]
+    fn test_filter() {
+        let request = TrafficFrame::new(
+            Direction::Tx,
+            FrameKind::Request,
+            Framing::Rtu,
+            vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A],
+            None,
+        );
+        let mut filter = Filter {
+            hex: "03 00".to_string(),
+            ..Default::default()
+        };
+        assert!(filter.matches(&request));
+        filter.hex = "30".to_string();
+        assert!(!filter.matches(&request));
+        filter.hex = "840a".to_string();
+        assert!(filter.matches(&request));
+        filter.direction = DirectionFilter::Only(Direction::Rx);
+        assert!(!filter.matches(&request));
+        filter.direction = DirectionFilter::All;
+        filter.errors_only = true;
+        assert!(!filter.matches(&request));
+
+        let mut traffic = Traffic::new();
+        for _ in 0..CAPACITY + 1 {
+            traffic.record(request.clone());
+        }
+        assert_eq!(traffic.frames().count(), CAPACITY);
+        traffic.paused = true;
+        traffic.record(request);
+        assert_eq!(traffic.skipped, 1);
+    }
+
+    #[test]
+    fn test_pairing() {
+        let frame = |direction, kind, adu: &[u8]| {
+            TrafficFrame::new(direction, kind, Framing::Rtu, encode_rtu(adu), None)
+        };
+        let mut traffic = Traffic::new();
+        traffic.record(frame(
+            Direction::Tx,
+            FrameKind::Request,
+            &[0x01, 0x03, 0x00, 0x00, 0x00, 0x01],
+        ));
+        traffic.record(frame(
+            Direction::Tx,
+            FrameKind::Request,
+            &[0x02, 0x03, 0x00, 0x00, 0x00, 0x01],
+        ));
+        traffic.record(frame(
+            Direction::Rx,
+            FrameKind::Response,
+            &[0x01, 0x83, 0x02],
+        ));
+        let records: Vec<&Record> = traffic.records.iter().collect();
+        assert_eq!(records[0].peer, Some(3));
+        assert_eq!(records[1].peer, None);
+        assert_eq!(records[2].peer, Some(1));
+        assert!(records[2].latency.is_some());
+        assert_eq!(
+            records[2].summary,
+            "异常 站号 1 读保持寄存器: 非法数据地址 (0x02)"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! 总线监听
+//!
+//! 监听模式只读取串口、从不发送，用来旁听总线上已有的主机和从机通信。
+//! RTU 按 t3.5 静默间隔把字节流分成帧，ASCII 按行分帧，
+//! 再按帧长度和校验码判断每一帧是请求还是应答，作为报文事件发给界面。
+
+use super::{Direction, TrafficFrame};
+use crate::modbus::frame::{
+    FrameError, FrameKind, Framing, decode_ascii, decode_rtu, encode_rtu, rtu_frame_length,
+};
+use crate::serial::PortSettings;
+use crate::task::{EventSender, TaskEvent};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 没有静默间隔时最多累积的字节数，超过后强制分帧，RTU 帧最长 256 字节
+const MAX_BURST: usize = 1024;
+
+/// 从连续的字节中分出的一帧
+#[derive(Debug, Clone, PartialEq)]
+struct Sniffed {
+    //在这段字节中的起始位置，用于推算时间戳
+    offset: usize,
+    bytes: Vec<u8>,
+    kind: FrameKind,
+    error: Option<FrameError>,
+}
+
+/// 监听串口，直到串口出错或关闭
+///
+/// 只需要 `AsyncRead`，因此不可能向总线发送任何数据。
+/// 每一帧开始读取时重新读取设置，修改波特率后静默间隔随之改变。
+pub async fn run<R>(
+    mut port: R,
+    settings: Arc<Mutex<PortSettings>>,
+    events: EventSender,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    log::info!("总线监听启动");
+    let mut expected = FrameKind::Request;
+    let mut burst = Vec::new();
+    let mut started = SystemTime::now();
+    let mut chunk = [0u8; 256];
+    loop {
+        let (framing, gap, char_time) = {
+            let settings = settings.lock().unwrap();
+            (
+                settings.framing,
+                settings.silent_interval(),
+                settings.char_time(),
+            )
+        };
+        // RTU 收到数据后等待静默间隔，超时说明一帧（或连在一起的几帧）已结束
+        let read = if burst.is_empty() || framing == Framing::Ascii {
+            Some(port.read(&mut chunk).await?)
+        } else {
+            match tokio::time::timeout(gap, port.read(&mut chunk)).await {
+                Ok(read) => Some(read?),
+                Err(_) => None,
+            }
+        };
+        if let Some(count) = read {
+            if count == 0 {
+                log::info!("串口已关闭，总线监听结束");
+                return Ok(());
+            }
+            if burst.is_empty() {
+                started = SystemTime::now();
+            }
+            burst.extend_from_slice(&chunk[..count]);
+        }
+
+        let frames = match framing {
+            Framing::Ascii => split_ascii(&mut burst, &mut expected),
+            _ if read.is_none() || burst.len() >= MAX_BURST => {
+                let frames = split_rtu(&burst, &mut expected);
+                burst.clear();
+                frames
+            }
+            _ => continue,
+        };
+        for sniffed in frames {
+            if let Some(error) = &sniffed.error {
+                log::warn!("监听到 {} 坏帧: {}", framing, error);
+                events.send(TaskEvent::FrameError {
+                    framing,
+                    frame: sniffed.bytes.clone(),
+                    error: error.clone(),
+                });
+            }
+            let mut frame = TrafficFrame::new(
+                Direction::Rx,
+                sniffed.kind,
+                framing,
+                sniffed.bytes,
+                sniffed.error,
+            );
+            // 一次读到的几帧按字符时间推算各自的开始时间
+            if framing != Framing::Ascii {
+                frame.time = started + char_time * sniffed.offset as u32;
+            }
+            events.send(TaskEvent::Traffic(frame));
+        }
+    }
+}
+
+/// 判断一个完整的 RTU 帧是请求还是应答，优先按 `expected` 判断
+///
+/// 返回 `None` 表示按两种类型计算的长度都不符合。
+pub(crate) fn classify_rtu(frame: &[u8], expected: FrameKind) -> Option<FrameKind> {
+    [expected, expected.opposite()]
+        .into_iter()
+        .find(|&kind| rtu_frame_length(frame, kind) == Ok(Some(frame.len())))
+}
+
+/// 下一帧预期的类型：请求之后是应答，广播请求没有应答
+pub(crate) fn next_expected(frame: &[u8], kind: FrameKind) -> FrameKind {
+    match (kind, frame.first()) {
+        (FrameKind::Request, Some(0)) => FrameKind::Request,
+        (kind, _) => kind.opposite(),
+    }
+}
+
+/// 把两个静默间隔之间的字节分成 RTU 帧
+///
+/// 串口驱动可能把紧挨着的请求和应答一起交上来，因此依次按请求和应答计算帧长度，
+/// CRC 正确就切出一帧；都不符合时剩余字节作为一帧，CRC 正确时视为长度未知的帧。
+fn split_rtu(bytes: &[u8], expected: &mut FrameKind) -> Vec<Sniffed> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let found = [*expected, expected.opposite()]
+            .into_iter()
+            .find_map(|kind| match rtu_frame_length(rest, kind) {
+                Ok(Some(length)) if length <= rest.len() && decode_rtu(&rest[..length]).is_ok() => {
+                    Some((kind, length))
+                }
+                _ => None,
+            });
+        let (kind, length, error) = match found {
+            Some((kind, length)) => (kind, length, None),
+            None => (
+                classify_rtu(rest, *expected).unwrap_or(*expected),
+                rest.len(),
+                decode_rtu(rest).err(),
+            ),
+        };
+        let frame = rest[..length].to_vec();
+        *expected = next_expected(&frame, kind);
+        frames.push(Sniffed {
+            offset,
+            bytes: frame,
+            kind,
+            error,
+        });
+        offset += length;
+    }
+    frames
+}
+
+/// 取出完整的 ASCII 行，剩下的字节留到下次
+fn split_ascii(buffer: &mut Vec<u8>, expected: &mut FrameKind) -> Vec<Sniffed> {
+    let mut frames = Vec::new();
+    while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let Some(start) = line.iter().position(|&byte| byte == b':') else {
+            continue;
+        };
+        let line = line[start..].to_vec();
+        let (kind, error) = match decode_ascii(&line) {
+            Ok(adu) => {
+                let rtu = encode_rtu(&adu);
+                let kind = classify_rtu(&rtu, *expected).unwrap_or(*expected);
+                *expected = next_expected(&rtu, kind);
+                (kind, None)
+            }
+            Err(error) => (*expected, Some(error)),
+        };
+        frames.push(Sniffed {
+            offset: 0,
+            bytes: line,
+            kind,
+            error,
+        });
+    }
+    if buffer.len() > MAX_BURST {
+        buffer.clear();
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_rtu() {
+        let request = encode_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        let response = encode_rtu(&[0x01, 0x03, 0x04, 0x00, 0x01, 0x00, 0x02]);
+        let mut expected = FrameKind::Request;
+
+        // 请求和应答一起读到，按长度和 CRC 分开
+        let burst = [request.clone(), response.clone()].concat();
+        let frames = split_rtu(&burst, &mut expected);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].kind, FrameKind::Request);
+        assert_eq!(frames[1].kind, FrameKind::Response);
+        assert_eq!(frames[1].offset, request.len());
+        assert_eq!(frames[1].bytes, response);
+        assert_eq!(expected, FrameKind::Request);
+
+        // CRC 错误的应答整段作为坏帧
+        let mut corrupted = response.clone();
+        corrupted[3] ^= 0x01;
+        let frames = split_rtu(&[request, corrupted].concat(), &mut expected);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].kind, FrameKind::Response);
+        assert!(matches!(frames[1].error, Some(FrameError::Crc { .. })));
+    }
+
+    #[test]
+    fn test_silent_interval() {
+        let mut settings = PortSettings {
+            baud_rate: 9600,
+            ..Default::default()
+        };
+        // 8N1 每个字符 10 位
+        assert_eq!(settings.silent_interval().as_micros(), 3645);
+        settings.baud_rate = 115200;
+        assert_eq!(settings.silent_interval().as_micros(), 1750);
+    }
+}
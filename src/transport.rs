@@ -0,0 +1,415 @@
+//! 传输层选择
+//!
+//! 同一套主机/从机引擎可以运行在串口或 Modbus TCP 之上，
+//! 串口和 TCP 连接上还可以选择 RTU 或 ASCII 帧格式。
+
+use crate::modbus::frame::{
+    FrameError, FrameKind, Framing, decode_ascii, decode_rtu, encode_ascii, encode_rtu,
+    mbap_frame_length, rtu_frame_length,
+};
+use crate::net::TcpSettings;
+use crate::serial::PortSettings;
+use crate::slave::store::RegisterStore;
+use crate::task::{EventSender, TaskEvent};
+use crate::traffic::{Direction, TrafficFrame};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, atomic::AtomicBool};
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// 主页上选择的传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransportKind {
+    #[default]
+    Serial,
+    Tcp,
+}
+
+impl TransportKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            TransportKind::Serial => "串口 RTU",
+            TransportKind::Tcp => "Modbus TCP",
+        }
+    }
+}
+
+/// 创建任务时使用的传输层设置
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// 串口设置与界面共享，任务通过 `need_update` 感知修改
+    Serial {
+        settings: Arc<Mutex<PortSettings>>,
+        need_update: Arc<AtomicBool>,
+    },
+    Tcp(TcpSettings),
+}
+
+/// 把 tokio-modbus 的 RTU 报文转换成指定帧格式的传输层
+///
+/// 串口帧格式下引擎按 RTU 收发。RTU 帧格式下数据原样透传，同时按帧校验收到的 CRC；
+/// ASCII 帧格式下发送时把 RTU 帧转换成 ASCII 行，接收时校验 LRC 后再转换回 RTU 帧。
+/// 校验失败的帧作为事件报告给界面，ASCII 的坏帧会被丢弃，由引擎按超时处理。
+/// TCP 帧格式下引擎按 Modbus TCP 收发，数据原样透传，只按 MBAP 报文头分帧。
+///
+/// 线路上收发的每一帧都作为报文事件发给界面。
+#[derive(Debug)]
+pub struct FramedTransport<T> {
+    inner: T,
+    framing: Framing,
+    //收到的是请求（从机）还是应答（主机）
+    incoming: FrameKind,
+    events: EventSender,
+    //正在接收、尚未组成完整帧的数据
+    rx: Vec<u8>,
+    //已转换好、等待引擎读取的 RTU 数据
+    decoded: Vec<u8>,
+    //引擎写入、等待转换的 RTU 帧
+    tx: Vec<u8>,
+    //已编码、尚未写完的数据
+    out: Vec<u8>,
+    //从机的寄存器存储，校验失败的帧计入其总线通信错误计数
+    error_store: Option<Arc<Mutex<RegisterStore>>>,
+}
+
+impl<T> FramedTransport<T> {
+    pub fn new(inner: T, framing: Framing, incoming: FrameKind, events: EventSender) -> Self {
+        Self {
+            inner,
+            framing,
+            incoming,
+            events,
+            rx: Vec::new(),
+            decoded: Vec::new(),
+            tx: Vec::new(),
+            out: Vec::new(),
+            error_store: None,
+        }
+    }
+
+    /// 校验失败的帧计入从机的总线通信错误计数
+    pub fn count_errors_in(mut self, store: Arc<Mutex<RegisterStore>>) -> Self {
+        self.error_store = Some(store);
+        self
+    }
+
+    fn report(&self, frame: Vec<u8>, error: FrameError) {
+        log::warn!("{} 帧校验失败: {}", self.framing, error);
+        if let Some(store) = &self.error_store {
+            store.lock().unwrap().record_frame_error();
+        }
+        self.events.send(TaskEvent::FrameError {
+            framing: self.framing,
+            frame,
+            error,
+        });
+    }
+
+    /// 把线路上的一帧发给报文页面
+    fn capture(&self, direction: Direction, bytes: Vec<u8>, error: Option<FrameError>) {
+        // 发出的帧与收到的帧相反：主机发出请求、收到应答，从机相反
+        let kind = match direction {
+            Direction::Rx => self.incoming,
+            Direction::Tx => self.incoming.opposite(),
+        };
+        self.events.send(TaskEvent::Traffic(TrafficFrame::new(
+            direction,
+            kind,
+            self.framing,
+            bytes,
+            error,
+        )));
+    }
+
+    /// 逐帧校验收到的 RTU 数据，无法确定帧边界时丢弃已收数据重新同步
+    fn check_rtu(&mut self) {
+        loop {
+            match rtu_frame_length(&self.rx, self.incoming) {
+                Ok(Some(length)) if self.rx.len() >= length => {
+                    let frame: Vec<u8> = self.rx.drain(..length).collect();
+                    match decode_rtu(&frame) {
+                        Ok(_) => self.capture(Direction::Rx, frame, None),
+                        Err(error) => {
+                            self.capture(Direction::Rx, frame.clone(), Some(error.clone()));
+                            self.report(frame, error);
+                            self.rx.clear();
+                        }
+                    }
+                }
+                Ok(_) => break,
+                Err(error) => {
+                    let frame = std::mem::take(&mut self.rx);
+                    self.capture(Direction::Rx, frame.clone(), Some(error.clone()));
+                    self.report(frame, error);
+                }
+            }
+        }
+    }
+
+    /// 按 MBAP 报文头从收到的数据中分出完整的 Modbus TCP 报文
+    fn split_mbap(&mut self) {
+        while let Some(length) = mbap_frame_length(&self.rx)
+            && self.rx.len() >= length
+        {
+            let frame: Vec<u8> = self.rx.drain(..length).collect();
+            self.capture(Direction::Rx, frame, None);
+        }
+    }
+
+    /// 发出新帧前清空接收缓冲，下一帧从对方的应答开始重新同步，未收完的帧也记录下来
+    fn resync(&mut self) {
+        if !self.rx.is_empty() {
+            let frame = std::mem::take(&mut self.rx);
+            let error = FrameError::Malformed("帧不完整".to_string());
+            self.capture(Direction::Rx, frame, Some(error));
+        }
+    }
+
+    /// 从收到的数据中取出完整的 ASCII 行，校验后转换成 RTU 帧
+    fn decode_ascii_lines(&mut self) {
+        while let Some(end) = self.rx.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.rx.drain(..=end).collect();
+            // `:` 之前的字节是噪声，没有 `:` 的行整行丢弃
+            let Some(start) = line.iter().position(|&byte| byte == b':') else {
+                continue;
+            };
+            let line = line[start..].to_vec();
+            match decode_ascii(&line) {
+                Ok(adu) => {
+                    self.decoded.extend_from_slice(&encode_rtu(&adu));
+                    self.capture(Direction::Rx, line, None);
+                }
+                Err(error) => {
+                    self.capture(Direction::Rx, line.clone(), Some(error.clone()));
+                    self.report(line, error);
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FramedTransport<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if !this.decoded.is_empty() {
+                let count = this.decoded.len().min(buf.remaining());
+                buf.put_slice(&this.decoded[..count]);
+                this.decoded.drain(..count);
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 256];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            let received = chunk_buf.filled();
+            if received.is_empty() {
+                // 底层连接已结束
+                return Poll::Ready(Ok(()));
+            }
+            this.rx.extend_from_slice(received);
+            match this.framing {
+                Framing::Ascii => this.decode_ascii_lines(),
+                Framing::Rtu => {
+                    this.decoded.extend_from_slice(received);
+                    this.check_rtu();
+                }
+                Framing::Tcp => {
+                    this.decoded.extend_from_slice(received);
+                    this.split_mbap();
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FramedTransport<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        match this.framing {
+            Framing::Ascii => {
+                this.resync();
+                // 引擎每发完一帧都会刷新，刷新时再整帧转换
+                this.tx.extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+            framing => {
+                // Modbus TCP 的请求带事务号，客户端可能连续发送多个请求，不需要重新同步
+                if framing == Framing::Rtu {
+                    this.resync();
+                }
+                let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+                // 写出的数据在刷新时作为一帧记录
+                this.tx.extend_from_slice(&buf[..written]);
+                Poll::Ready(Ok(written))
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        match this.framing {
+            Framing::Ascii if this.tx.len() > 2 => {
+                let frame = std::mem::take(&mut this.tx);
+                // 去掉 tokio-modbus 附加的 CRC，改用 LRC
+                this.out = encode_ascii(&frame[..frame.len() - 2]);
+                this.capture(Direction::Tx, this.out.clone(), None);
+            }
+            Framing::Rtu if !this.tx.is_empty() => {
+                let frame = std::mem::take(&mut this.tx);
+                let error = decode_rtu(&frame).err();
+                this.capture(Direction::Tx, frame, error);
+            }
+            Framing::Tcp if !this.tx.is_empty() => {
+                let frame = std::mem::take(&mut this.tx);
+                this.capture(Direction::Tx, frame, None);
+            }
+            _ => {}
+        }
+        while !this.out.is_empty() {
+            let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.out))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.out.drain(..written);
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_ascii_framing() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (near, mut far) = tokio::io::duplex(256);
+        let mut transport = FramedTransport::new(
+            near,
+            Framing::Ascii,
+            FrameKind::Response,
+            EventSender::new(tx),
+        );
+
+        // 引擎写出的 RTU 帧在线路上变成 ASCII 行
+        let request = encode_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        transport.write_all(&request).await.unwrap();
+        transport.flush().await.unwrap();
+        let mut line = [0u8; 17];
+        far.read_exact(&mut line).await.unwrap();
+        assert_eq!(&line, b":010300000001FB\r\n");
+
+        // LRC 错误的帧被丢弃并报告，正确的帧转换回 RTU
+        far.write_all(b":0103020001F8\r\n:0103020001F9\r\n")
+            .await
+            .unwrap();
+        let mut response = [0u8; 7];
+        transport.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            response.to_vec(),
+            encode_rtu(&[0x01, 0x03, 0x02, 0x00, 0x01])
+        );
+        let events: Vec<TaskEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TaskEvent::FrameError {
+                error: FrameError::Lrc { .. },
+                ..
+            }
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_traffic_capture() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (near, mut far) = tokio::io::duplex(256);
+        let mut transport = FramedTransport::new(
+            near,
+            Framing::Rtu,
+            FrameKind::Response,
+            EventSender::new(tx),
+        );
+
+        let request = encode_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        transport.write_all(&request).await.unwrap();
+        transport.flush().await.unwrap();
+        let mut response = encode_rtu(&[0x01, 0x03, 0x02, 0x00, 0x01]);
+        response[6] ^= 0xFF;
+        far.write_all(&response).await.unwrap();
+        let mut received = [0u8; 7];
+        transport.read_exact(&mut received).await.unwrap();
+
+        let frames: Vec<TrafficFrame> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|event| match event {
+                TaskEvent::Traffic(frame) => Some(frame),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].direction, Direction::Tx);
+        assert_eq!(frames[0].kind, FrameKind::Request);
+        assert_eq!(frames[0].bytes, request);
+        assert_eq!(frames[0].error, None);
+        assert_eq!(frames[1].direction, Direction::Rx);
+        assert_eq!(frames[1].bytes, response);
+        assert!(matches!(frames[1].error, Some(FrameError::Crc { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_multi_word_query_data() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (near, mut far) = tokio::io::duplex(256);
+        let store = Arc::new(Mutex::new(RegisterStore::new()));
+        let mut transport =
+            FramedTransport::new(near, Framing::Rtu, FrameKind::Request, EventSender::new(tx))
+                .count_errors_in(store.clone());
+
+        // 返回询问数据带三个数据字，分两次到达
+        let request = encode_rtu(&[0x01, 0x08, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC]);
+        far.write_all(&request[..8]).await.unwrap();
+        let mut received = vec![0u8; 8];
+        transport.read_exact(&mut received).await.unwrap();
+        far.write_all(&request[8..]).await.unwrap();
+        let mut rest = vec![0u8; request.len() - 8];
+        transport.read_exact(&mut rest).await.unwrap();
+
+        let events: Vec<TaskEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(
+            !events
+                .iter()
+                .any(|event| matches!(event, TaskEvent::FrameError { .. }))
+        );
+        let frames: Vec<&TrafficFrame> = events
+            .iter()
+            .filter_map(|event| match event {
+                TaskEvent::Traffic(frame) => Some(frame),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].bytes, request);
+        assert_eq!(frames[0].error, None);
+        let store = store.lock().unwrap();
+        assert_eq!(
+            store.unit(1).unwrap().diagnostics.bus_communication_errors,
+            0
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! 工作区文件
+//!
+//! 工作区保存整个会话：传输方式和连接设置、工作模式、轮询列表、标签、
+//! 从机寄存器存储的内容和窗口大小，同事打开后可以复现同样的测试环境。
+//! 文件是带版本号的 JSON，旧版本的文件在读取时逐级迁移到当前版本。
+
+use crate::master::poll::PollDefinition;
+use crate::modbus::pdu::FunctionCode;
+use crate::mode::OperatingMode;
+use crate::net::TcpSettings;
+use crate::page::Page;
+use crate::profile::{serial_from_json, serial_to_json, tcp_from_json, tcp_to_json};
+use crate::serial::PortSettings;
+use crate::slave::store::{RegisterStore, TableKind, UnitStore};
+use crate::tag::Tag;
+use crate::tag::map::{MapPoint, points_from_json, points_to_json};
+use crate::transport::TransportKind;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// 当前的文件版本
+pub const VERSION: u64 = 1;
+
+/// 把第 `n + 1` 版的文件升级到第 `n + 2` 版，格式变化时在末尾添加一步
+const MIGRATIONS: [fn(Value) -> Result<Value, String>; 0] = [];
+
+/// 一个工作区的全部内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub transport_kind: TransportKind,
+    pub serial: PortSettings,
+    pub tcp: TcpSettings,
+    /// 最近使用的连接配置名
+    pub profile: Option<String>,
+    pub mode: OperatingMode,
+    pub page: Page,
+    pub polls: Vec<PollDefinition>,
+    pub tags: Vec<Tag>,
+    pub store: RegisterStore,
+    /// 窗口内部的宽和高
+    pub window_size: Option<[f32; 2]>,
+}
+
+impl Workspace {
+    pub fn to_json(&self) -> String {
+        let polls: Vec<Value> = self.polls.iter().map(poll_to_json).collect();
+        let points: Vec<MapPoint> = self
+            .tags
+            .iter()
+            .map(|tag| MapPoint {
+                tag: tag.clone(),
+                value: None,
+            })
+            .collect();
+        let root = json!({
+            "version": VERSION,
+            "transport": transport_key(self.transport_kind),
+            "serial": serial_to_json(&self.serial),
+            "tcp": tcp_to_json(&self.tcp),
+            "profile": self.profile,
+            "mode": mode_key(self.mode),
+            "page": page_key(self.page),
+            "polls": polls,
+            "tags": points_to_json(&points),
+            "store": store_to_json(&self.store),
+            "window_size": self.window_size,
+        });
+        serde_json::to_string_pretty(&root).unwrap_or_default()
+    }
+
+    pub fn from_json(text: &str) -> Result<Workspace, String> {
+        let root: Value = serde_json::from_str(text)
+            .map_err(|err| format!("第 {} 行第 {} 列: {}", err.line(), err.column(), err))?;
+        let root = migrate(root)?;
+        let section = |key: &str| root.get(key).filter(|value| !value.is_null());
+        let text = |key: &str| section(key).and_then(Value::as_str);
+
+        let transport_kind = match text("transport") {
+            Some(key) => {
+                transport_from_key(key).ok_or_else(|| format!("未知的传输方式 {}", key))?
+            }
+            None => TransportKind::default(),
+        };
+        let mode = match text("mode") {
+            Some(key) => mode_from_key(key).ok_or_else(|| format!("未知的工作模式 {}", key))?,
+            None => OperatingMode::default(),
+        };
+        let page = match text("page") {
+            Some(key) => page_from_key(key).ok_or_else(|| format!("未知的页面 {}", key))?,
+            None => Page::default(),
+        };
+        let polls = section("polls")
+            .and_then(Value::as_array)
+            .map(|polls| {
+                polls
+                    .iter()
+                    .enumerate()
+                    .map(|(index, poll)| {
+                        poll_from_json(poll, index as u64 + 1)
+                            .map_err(|err| format!("第 {} 项轮询: {}", index + 1, err))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let tags = match section("tags").and_then(Value::as_array) {
+            Some(items) => points_from_json(items)
+                .map_err(|errors| format!("标签{}", errors[0]))?
+                .into_iter()
+                .enumerate()
+                .map(|(index, point)| Tag {
+                    id: index as u64 + 1,
+                    ..point.tag
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let window_size = section("window_size")
+            .and_then(Value::as_array)
+            .and_then(|size| {
+                Some([
+                    size.first()?.as_f64()? as f32,
+                    size.get(1)?.as_f64()? as f32,
+                ])
+            });
+
+        Ok(Workspace {
+            transport_kind,
+            serial: section("serial")
+                .map(serial_from_json)
+                .transpose()
+                .map_err(|err| format!("串口设置: {}", err))?
+                .unwrap_or_default(),
+            tcp: section("tcp")
+                .map(tcp_from_json)
+                .transpose()
+                .map_err(|err| format!("TCP 设置: {}", err))?
+                .unwrap_or_default(),
+            profile: text("profile").map(str::to_string),
+            mode,
+            page,
+            polls,
+            tags,
+            store: match section("store") {
+                Some(store) => {
+                    store_from_json(store).map_err(|err| format!("寄存器存储: {}", err))?
+                }
+                None => RegisterStore::default(),
+            },
+            window_size,
+        })
+    }
+}
+
+/// 把旧版本的文件逐级升级到当前版本，拒绝比程序更新的文件
+fn migrate(mut root: Value) -> Result<Value, String> {
+    let version = root
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or("缺少版本号")?;
+    if version == 0 || version > VERSION {
+        return Err(format!(
+            "不支持的版本 {}，当前程序支持到版本 {}",
+            version, VERSION
+        ));
+    }
+    for step in &MIGRATIONS[version as usize - 1..] {
+        root = step(root)?;
+    }
+    root["version"] = json!(VERSION);
+    Ok(root)
+}
+
+fn transport_key(kind: TransportKind) -> &'static str {
+    match kind {
+        TransportKind::Serial => "serial",
+        TransportKind::Tcp => "tcp",
+    }
+}
+
+fn transport_from_key(key: &str) -> Option<TransportKind> {
+    [TransportKind::Serial, TransportKind::Tcp]
+        .into_iter()
+        .find(|kind| transport_key(*kind) == key)
+}
+
+fn mode_key(mode: OperatingMode) -> &'static str {
+    match mode {
+        OperatingMode::Idle => "idle",
+        OperatingMode::Master => "master",
+        OperatingMode::Slave => "slave",
+        OperatingMode::Monitor => "monitor",
+        OperatingMode::Gateway => "gateway",
+    }
+}
+
+fn mode_from_key(key: &str) -> Option<OperatingMode> {
+    OperatingMode::ALL
+        .into_iter()
+        .find(|mode| mode_key(*mode) == key)
+}
+
+fn page_key(page: Page) -> &'static str {
+    match page {
+        Page::Home => "home",
+        Page::Slave => "slave",
+        Page::Master => "master",
+        Page::Traffic => "traffic",
+    }
+}
+
+fn page_from_key(key: &str) -> Option<Page> {
+    [Page::Home, Page::Slave, Page::Master, Page::Traffic]
+        .into_iter()
+        .find(|page| page_key(*page) == key)
+}
+
+fn poll_to_json(poll: &PollDefinition) -> Value {
+    json!({
+        "unit": poll.unit,
+        "function": poll.function.code(),
+        "address": poll.address,
+        "quantity": poll.quantity,
+        "period_ms": poll.period.as_millis() as u64,
+        "enabled": poll.enabled,
+    })
+}
+
+fn poll_from_json(value: &Value, id: u64) -> Result<PollDefinition, String> {
+    let number = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("缺少或无效的 {}", key))
+    };
+    let code = number("function")?;
+    let function = FunctionCode::ALL
+        .into_iter()
+        .find(|function| function.code() as u64 == code)
+        .ok_or_else(|| format!("未知的功能码 {}", code))?;
+    let poll = PollDefinition {
+        id,
+        unit: u8::try_from(number("unit")?).map_err(|_| "无效的站号")?,
+        function,
+        address: u16::try_from(number("address")?).map_err(|_| "无效的地址")?,
+        quantity: u16::try_from(number("quantity")?).map_err(|_| "无效的数量")?,
+        period: Duration::from_millis(number("period_ms")?),
+        enabled: value
+            .get("enabled")
+            .and_then(Value::as_bool)
+            .unwrap_or(true),
+    };
+    if poll.request().is_none() {
+        return Err("轮询只支持 01–04 读功能码".to_string());
+    }
+    Ok(poll)
+}
+
+/// 每张表保存地址范围和不为默认值的 `[地址, 值]`，避免文件过大
+fn store_to_json(store: &RegisterStore) -> Value {
+    let units: Vec<Value> = store
+        .unit_ids()
+        .into_iter()
+        .filter_map(|id| {
+            let unit = store.unit(id)?;
+            let mut object = json!({ "unit": id });
+            for kind in TableKind::ALL {
+                let (start, count) = unit.range(kind);
+                let values: Vec<[u16; 2]> = match kind {
+                    TableKind::Coils => bit_values(unit.coils.iter()),
+                    TableKind::DiscreteInputs => bit_values(unit.discrete_inputs.iter()),
+                    TableKind::HoldingRegisters => register_values(unit.holding_registers.iter()),
+                    TableKind::InputRegisters => register_values(unit.input_registers.iter()),
+                };
+                object[kind.key()] = json!({ "start": start, "count": count, "values": values });
+            }
+            object["identification"] = json!(unit.identification);
+            Some(object)
+        })
+        .collect();
+    json!({ "units": units })
+}
+
+fn bit_values(values: impl Iterator<Item = (u16, bool)>) -> Vec<[u16; 2]> {
+    values
+        .filter(|(_, value)| *value)
+        .map(|(address, _)| [address, 1])
+        .collect()
+}
+
+fn register_values(values: impl Iterator<Item = (u16, u16)>) -> Vec<[u16; 2]> {
+    values
+        .filter(|(_, value)| *value != 0)
+        .map(|(address, value)| [address, value])
+        .collect()
+}
+
+fn store_from_json(value: &Value) -> Result<RegisterStore, String> {
+    let mut store = RegisterStore::new();
+    for id in store.unit_ids() {
+        store.remove_unit(id);
+    }
+    let units = value
+        .get("units")
+        .and_then(Value::as_array)
+        .ok_or("缺少 units 数组")?;
+    for unit in units {
+        let id = unit
+            .get("unit")
+            .and_then(Value::as_u64)
+            .and_then(|id| u8::try_from(id).ok())
+            .ok_or("缺少或无效的站号")?;
+        if !store.add_unit(id) {
+            return Err(format!("站号 {} 重复或无效", id));
+        }
+        let Some(tables) = store.unit_mut(id) else {
+            continue;
+        };
+        for kind in TableKind::ALL {
+            if let Some(table) = unit.get(kind.key()) {
+                table_from_json(tables, kind, table)
+                    .map_err(|err| format!("站号 {} {}: {}", id, kind.label(), err))?;
+            }
+        }
+        if let Some(identification) = unit.get("identification") {
+            tables.identification = identification_from_json(identification)
+                .map_err(|err| format!("站号 {} 设备标识: {}", id, err))?;
+        }
+    }
+    Ok(store)
+}
+
+/// 设备标识保存为以对象编号为键的对象，JSON 的键只能是字符串
+fn identification_from_json(value: &Value) -> Result<BTreeMap<u8, String>, String> {
+    value
+        .as_object()
+        .ok_or("不是对象")?
+        .iter()
+        .map(|(id, text)| {
+            let id = id
+                .parse::<u8>()
+                .map_err(|_| format!("无效的对象编号 {}", id))?;
+            let text = text
+                .as_str()
+                .ok_or_else(|| format!("对象 {} 不是字符串", id))?;
+            Ok((id, text.to_string()))
+        })
+        .collect()
+}
+
+fn table_from_json(unit: &mut UnitStore, kind: TableKind, value: &Value) -> Result<(), String> {
+    let number = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("缺少或无效的 {}", key))
+    };
+    let start = u16::try_from(number("start")?).map_err(|_| "无效的起始地址")?;
+    unit.resize(kind, start, number("count")? as usize);
+    let values = value
+        .get("values")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for pair in values {
+        let (address, data) = pair
+            .as_array()
+            .and_then(|pair| Some((pair.first()?.as_u64()?, pair.get(1)?.as_u64()?)))
+            .and_then(|(address, data)| {
+                Some((u16::try_from(address).ok()?, u16::try_from(data).ok()?))
+            })
+            .ok_or_else(|| format!("无效的值 {}", pair))?;
+        let written = match kind {
+            TableKind::Coils => unit.coils.write(address, &[data != 0]),
+            TableKind::DiscreteInputs => unit.discrete_inputs.write(address, &[data != 0]),
+            TableKind::HoldingRegisters => unit.holding_registers.write(address, &[data]),
+            TableKind::InputRegisters => unit.input_registers.write(address, &[data]),
+        };
+        written.map_err(|_| format!("地址 {} 超出范围", address))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::frame::Framing;
+
+    #[test]
+    fn test_workspace_round_trip() {
+        let mut store = RegisterStore::default();
+        store.add_unit(5);
+        let unit = store.unit_mut(5).unwrap();
+        unit.resize(TableKind::HoldingRegisters, 100, 20);
+        unit.write_single_register(105, 0xBEEF).unwrap();
+        unit.write_single_coil(3, true).unwrap();
+        unit.identification.insert(0x81, "序列号 42".to_string());
+
+        let workspace = Workspace {
+            transport_kind: TransportKind::Tcp,
+            serial: PortSettings::default(),
+            tcp: TcpSettings {
+                framing: Framing::Rtu,
+                ..Default::default()
+            },
+            profile: Some("网关".to_string()),
+            mode: OperatingMode::Master,
+            page: Page::Master,
+            polls: vec![PollDefinition {
+                id: 1,
+                unit: 5,
+                function: FunctionCode::ReadHoldingRegisters,
+                address: 100,
+                quantity: 10,
+                period: Duration::from_millis(500),
+                enabled: false,
+            }],
+            tags: vec![Tag {
+                id: 1,
+                name: "温度".to_string(),
+                unit: 5,
+                address: 105,
+                scale: 0.1,
+                ..Default::default()
+            }],
+            store,
+            window_size: Some([1024.0, 700.0]),
+        };
+        let text = workspace.to_json();
+        assert_eq!(Workspace::from_json(&text), Ok(workspace));
+    }
+
+    #[test]
+    fn test_rejects_newer_version() {
+        assert!(Workspace::from_json(r#"{"version": 99}"#).is_err());
+        assert!(Workspace::from_json(r#"{"polls": []}"#).is_err());
+        // 只有版本号的文件使用默认设置
+        let workspace = Workspace::from_json(r#"{"version": 1}"#).unwrap();
+        assert_eq!(workspace.store, RegisterStore::default());
+    }
+}